@@ -0,0 +1,9 @@
+Person: Name Age Career
+Career: Name Wage
+
+Careers -> Driver 2400, Architect 5000
+Persons -> Jacek 24 Driver, Tomek 30 Architect, Ola 22 Intern
+
+get Person.Name, Career.Wage from persons join careers on Person.Career = Career.Name sorted by wage
+# Tomek  5000
+# Jacek  2400
@@ -1,67 +1,960 @@
 use std::fs;
+use std::io;
 
 use crate::{
-    declarations::{Literals, NodeType, VariableDecl},
-    parser::{get_var_size, AST},
+    ast::{walk_stmt, ExprId, StmtId, Visitor, AST},
+    declarations::{
+        BinaryOp, ExprKind, Function, InsertStmt, Literals, Predicate, Query, RecordDecl, StmtKind,
+        Type, VariableDecl,
+    },
+    diagnostic::Diagnostic,
+    parser::get_var_size,
+    plan::{self, Condition, Output, Plan},
+    records::Column,
+    tokenizer::{FloatType, IntType, Span},
+    typeck::Types,
 };
 
+/// Ints are computed in `rax`, floats in `xmm0` using SSE2.
 pub struct Assembler {
     output: String,
     filename: String,
+    /// Bytes reserved below `rbp` for variables
+    frame_size: u16,
+    types: Types,
+    /// Float literals and tables of strings, placed in `.rodata` by `finish`
+    constants: Vec<String>,
+    /// Collections inserted into so far, laid out a column at a time by `finish`
+    collections: Vec<Collection>,
+    /// Labels and sizes of the buffers rows of queries are gathered in, placed in `.bss`
+    buffers: Vec<(String, usize)>,
+    /// Jump targets made so far
+    labels: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Value of a row in a column. Strings are stored as the index of their text among the
+/// distinct texts of the column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cell {
+    Int(i64),
+    F64(f64),
+    F32(f32),
+    Str(u32),
+}
+
+/// Rows of a collection known when compiling, each column laid out by `finish` as an array
+/// labelled `Collection.Column`, so a loop reads one value after another of the fields it
+/// uses. Arrays are addressed by absolute address plus index, `ld` links at a fixed one.
+struct Collection {
+    name: String,
+    columns: Vec<Column>,
+    rows: Vec<Vec<Cell>>,
+    /// Distinct texts of each column
+    strings: Vec<Vec<String>>,
+}
+
+impl Collection {
+    fn cell(&mut self, column: usize, literal: &Literals) -> Cell {
+        match literal {
+            Literals::NUMBER(value, int_type) => Cell::Int(int_type.parse(value)),
+            Literals::FLOAT(value, FloatType::F64) => {
+                Cell::F64(value.parse().expect("Float literal is not a valid f64"))
+            }
+            Literals::FLOAT(value, FloatType::F32) => {
+                Cell::F32(value.parse().expect("Float literal is not a valid f32"))
+            }
+            Literals::STR(text) => {
+                let strings = &mut self.strings[column];
+                let index = strings.iter().position(|string| string == text);
+                Cell::Str(index.unwrap_or_else(|| {
+                    strings.push(text.clone());
+                    strings.len() - 1
+                }) as u32)
+            }
+        }
+    }
+
+    /// Index of a column of the plan among those of the collection.
+    fn position(&self, column: &Column) -> usize {
+        self.columns
+            .iter()
+            .position(|own| own.path == column.path)
+            .expect("Plans read columns of their records")
+    }
+
+    fn label(&self, column: usize) -> String {
+        format!("{}.{}", self.name, self.columns[column].name)
+    }
+
+    /// Arrays of the columns, 4 bytes for each `f32` and string, 8 for the others.
+    fn layout(&self) -> String {
+        let mut out = String::new();
+        for (index, column) in self.columns.iter().enumerate() {
+            let values: Vec<String> = self
+                .rows
+                .iter()
+                .map(|row| match row[index] {
+                    Cell::Int(value) => value.to_string(),
+                    Cell::F64(value) => format!("{:#x}", value.to_bits()),
+                    Cell::F32(value) => format!("{:#x}", value.to_bits()),
+                    Cell::Str(value) => value.to_string(),
+                })
+                .collect();
+            let directive = match column.ty {
+                Some(Type::Float(FloatType::F32) | Type::Str) => ".long",
+                _ => ".quad",
+            };
+
+            out += &format!("    .p2align 3\n{}:\n", self.label(index));
+            for line in values.chunks(16) {
+                out += &format!("    {directive} {}\n", line.join(", "));
+            }
+        }
+        out
+    }
+}
+
+/// Whether a text passes a test, decided when compiling for every text of a column.
+fn passes(predicate: Predicate, text: &str, value: &str) -> bool {
+    match predicate {
+        Predicate::Eq => text == value,
+        Predicate::Ne => text != value,
+        Predicate::StartsWith => text.starts_with(value),
+        Predicate::EndsWith => text.ends_with(value),
+        Predicate::Contains => text.contains(value),
+        predicate => unreachable!("The type checker rejects {} on str", predicate.symbol()),
+    }
+}
+
+/// Instruction suffix and operand size of scalar SSE instructions.
+fn sse(float_type: FloatType) -> (&'static str, &'static str) {
+    match float_type {
+        FloatType::F32 => ("ss", "DWORD"),
+        FloatType::F64 => ("sd", "QWORD"),
+    }
 }
 
 impl Assembler {
     pub fn new(filename: &str) -> Self {
-        let mut assem = Self {
+        Self {
             output: String::with_capacity(4096),
             filename: filename.to_string(),
-        };
+            frame_size: 0,
+            types: Types::default(),
+            constants: vec![],
+            collections: vec![],
+            buffers: vec![],
+            labels: 0,
+            diagnostics: vec![],
+        }
+    }
 
-        assem.output += "PUSH rbp\n";
-        assem.output += "MOV  rbp rsp\n";
+    pub fn assemble_ast(&mut self, ast: &AST, types: Types) -> Result<(), Vec<Diagnostic>> {
+        self.types = types;
+        self.visit_program(ast);
+        match std::mem::take(&mut self.diagnostics) {
+            diagnostics if diagnostics.is_empty() => Ok(()),
+            diagnostics => Err(diagnostics),
+        }
+    }
 
-        assem
+    /// Cuts `rax` to the width of `int_type`, extended back to 64 bits as `IntType::wrap` does.
+    fn narrow(&mut self, int_type: IntType) {
+        self.output += match int_type {
+            IntType::I64 | IntType::U64 => "",
+            IntType::I32 => "    movsxd rax, eax\n",
+            IntType::U32 => "    mov  eax, eax\n",
+            IntType::I16 => "    movsx rax, ax\n",
+            IntType::U16 => "    movzx eax, ax\n",
+            IntType::I8 => "    movsx rax, al\n",
+            IntType::U8 => "    movzx eax, al\n",
+        };
     }
 
-    pub fn assemble_based_on_nodes(&mut self, nodes: &Vec<NodeType>) {
-        for node_type in nodes {
-            match node_type {
-                NodeType::Variable(variable_decl) => self.var(variable_decl),
-                NodeType::Stmt(stmt) => {}
+    /// Moves `rax` or `xmm0` to memory at `place`, or the other way around when `load` is set.
+    fn mov(&mut self, ty: Type, place: &str, load: bool, comment: &str) {
+        let (instruction, register, size) = match ty {
+            // Strings are held as the index of their text
+            Type::Int(_) | Type::Str => ("mov ", "rax", "QWORD"),
+            Type::Float(float_type) => {
+                let (suffix, size) = sse(float_type);
+                (if suffix == "ss" { "movss" } else { "movsd" }, "xmm0", size)
             }
+        };
+
+        self.output += &if load {
+            format!("    {instruction} {register}, {size} PTR {place}{comment}\n")
+        } else {
+            format!("    {instruction} {size} PTR {place}, {register}{comment}\n")
+        };
+    }
+
+    pub fn var(&mut self, ast: &AST, variable_decl: &VariableDecl) {
+        let value = &ast[variable_decl.value];
+        self.visit_expr(ast, variable_decl.value);
+
+        let ty = self.types[variable_decl.value];
+        if ty == Type::Str {
+            return;
         }
+
+        let var_size: u16 = get_var_size(value);
+
+        let offset = variable_decl.stack_offset + var_size;
+        self.frame_size = self.frame_size.max(offset);
+
+        let comment = format!(" # {}", variable_decl.identifier);
+        self.mov(ty, &format!("[rbp-{offset}]"), false, &comment);
     }
 
-    pub fn from_ast(&mut self, ast: &AST) {
-        self.assemble_based_on_nodes(&ast.nodes)
+    /// Defines `Record.Field` as the offset of each field and `Record_size`, so code can
+    /// address fields like `[rbx+Person.Age]`. Records with fields of unknown type are left out.
+    pub fn record(&mut self, record: &RecordDecl) {
+        let Ok(layout) = self.types.records().layout(&record.name) else {
+            return;
+        };
+
+        for (field, offset) in record.fields.iter().zip(&layout.offsets) {
+            self.output += &format!("    .set {}.{}, {offset}\n", record.name, field.name);
+        }
+        self.output += &format!("    .set {}_size, {}\n", record.name, layout.size);
     }
 
-    pub fn func(&mut self) {}
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
 
-    pub fn var(&mut self, variable_decl: &VariableDecl) {
-        let var_type = match variable_decl.literal.value {
-            Literals::NUMBER(_) => "QWORD",
-            Literals::STR(_) => {
-                panic!("String is not yet supported as a variable declaration type")
+    /// Places a float literal in `.rodata` and returns its label.
+    fn constant(&mut self, value: &str, float_type: FloatType) -> String {
+        let label = format!(".LC{}", self.constants.len());
+        let directive = match float_type {
+            FloatType::F32 => ".float",
+            FloatType::F64 => ".double",
+        };
+        self.constants
+            .push(format!("{label}:\n    {directive} {value}\n"));
+        label
+    }
+
+    /// Index of the collection, empty until rows are inserted into it.
+    fn collection(&mut self, name: &str, record: &str) -> usize {
+        if let Some(index) = self.collections.iter().position(|c| c.name == name) {
+            return index;
+        }
+
+        let columns = self.types.records().columns(record);
+        self.collections.push(Collection {
+            name: name.to_string(),
+            strings: vec![vec![]; columns.len()],
+            columns,
+            rows: vec![],
+        });
+        self.collections.len() - 1
+    }
+
+    /// Keeps the rows to lay out with the collection, each row once as in the interpreter.
+    fn insert(&mut self, ast: &AST, insert: &InsertStmt) {
+        let index = self.collection(&insert.collection, &insert.record);
+        let collection = &mut self.collections[index];
+        for row in &insert.rows {
+            let cells: Result<Vec<Cell>, Diagnostic> = row
+                .iter()
+                .enumerate()
+                .map(|(column, value)| match &ast[*value].kind {
+                    ExprKind::Literal(literal) => Ok(collection.cell(column, literal)),
+                    _ => Err(Diagnostic::new(
+                        ast[*value].span,
+                        "Rows of compiled collections are literals",
+                    )),
+                })
+                .collect();
+            let cells = match cells {
+                Ok(cells) => cells,
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    continue;
+                }
+            };
+            collection.rows.push(cells);
+        }
+    }
+
+    /// Loads the value of the row at `r12` into `rax`, or `xmm0` for floats, and returns
+    /// its type. Strings load their index into `eax`.
+    fn load(&mut self, collection: usize, column: usize) -> Type {
+        let label = self.collections[collection].label(column);
+        let ty = self.collections[collection].columns[column]
+            .ty
+            .unwrap_or(Type::Int(IntType::I64));
+        match ty {
+            Type::Str => self.output += &format!("    mov  eax, DWORD PTR {label}[r12*4]\n"),
+            Type::Float(FloatType::F32) => self.mov(ty, &format!("{label}[r12*4]"), true, ""),
+            _ => self.mov(ty, &format!("{label}[r12*8]"), true, ""),
+        }
+        ty
+    }
+
+    /// Jumps to `fail` unless the row at `r12` passes the condition. Strings are looked up in
+    /// a table of whether each text of the column passes.
+    fn condition(&mut self, collection: usize, condition: &Condition, fail: &str) {
+        let (column, predicate, value) = match condition {
+            Condition::And(lhs, rhs) => {
+                self.condition(collection, lhs, fail);
+                self.condition(collection, rhs, fail);
+                return;
+            }
+            Condition::Or(lhs, rhs) => {
+                let (next, pass) = (self.label(), self.label());
+                self.condition(collection, lhs, &next);
+                self.output += &format!("    jmp  {pass}\n{next}:\n");
+                self.condition(collection, rhs, fail);
+                self.output += &format!("{pass}:\n");
+                return;
             }
+            Condition::Test {
+                column,
+                predicate,
+                value,
+                ..
+            } => (column, *predicate, value),
         };
 
-        let var_size: u16 = get_var_size(variable_decl);
+        let column = self.collections[collection].position(column);
+        let jump = match (self.load(collection, column), value) {
+            (Type::Str, Literals::STR(value)) => {
+                let table = format!(".LC{}", self.constants.len());
+                let passed: Vec<&str> = self.collections[collection].strings[column]
+                    .iter()
+                    .map(|text| {
+                        if passes(predicate, text, value) {
+                            "1"
+                        } else {
+                            "0"
+                        }
+                    })
+                    .collect();
+                let mut constant = format!("{table}:\n");
+                if !passed.is_empty() {
+                    constant += &format!("    .byte {}\n", passed.join(", "));
+                }
+                self.constants.push(constant);
+                self.output += &format!("    cmp  BYTE PTR {table}[rax], 0\n");
+                "je"
+            }
+            (Type::Int(_), Literals::NUMBER(value, _)) => {
+                self.output += &format!("    mov  rcx, {value}\n    cmp  rax, rcx\n");
+                match predicate {
+                    Predicate::Eq => "jne",
+                    Predicate::Ne => "je",
+                    Predicate::Lt => "jge",
+                    Predicate::Le => "jg",
+                    Predicate::Gt => "jle",
+                    Predicate::Ge => "jl",
+                    _ => unreachable!("The type checker rejects tests of text on numbers"),
+                }
+            }
+            (Type::Float(float_type), Literals::FLOAT(value, _)) => {
+                let constant = self.constant(value, float_type);
+                let (suffix, size) = sse(float_type);
+                self.output += &format!("    ucomi{suffix} xmm0, {size} PTR {constant}[rip]\n");
+                match predicate {
+                    Predicate::Eq => "jne",
+                    Predicate::Ne => "je",
+                    Predicate::Lt => "jae",
+                    Predicate::Le => "ja",
+                    Predicate::Gt => "jbe",
+                    Predicate::Ge => "jb",
+                    _ => unreachable!("The type checker rejects tests of text on numbers"),
+                }
+            }
+            _ => unreachable!("The type checker matches values to their columns"),
+        };
+        self.output += &format!("    {jump:<4} {fail}\n");
+    }
 
-        let offset = variable_decl.stack_offset + var_size;
+    /// Writes the `rdx` bytes at `rsi` to standard output.
+    fn write(&mut self) {
+        self.output += "    mov  rax, 1\n";
+        self.output += "    mov  rdi, 1\n";
+        self.output += "    syscall\n";
+    }
+
+    /// Compiles a query of a single collection into a loop over the columns it reads, with
+    /// `r12` the row and `r13` the rows kept so far. The rows it gives are written to
+    /// standard output, each value as its 8 bytes in turn, an `f32` in the lower 4.
+    /// Aggregates are computed in their place in the row.
+    fn query(&mut self, ast: &AST, query: &Query, span: Span) -> Result<(), Diagnostic> {
+        let collection = self.collection(&query.collection, &query.record);
+        let rows = self
+            .collections
+            .iter()
+            .map(|collection| (collection.name.clone(), Some(collection.rows.len())))
+            .collect();
+        let plan = plan::plan_query(ast, self.types.records(), &rows, query);
 
-        let var_value = match &variable_decl.literal.value {
-            Literals::NUMBER(value) => value,
-            Literals::STR(value) => value,
+        let unsupported = "Only filters and aggregates of a single collection can be compiled yet";
+        let (input, outputs, aggregates) = match plan {
+            Plan::Aggregate {
+                input,
+                group: None,
+                outputs,
+            } => (input, outputs, true),
+            Plan::Project { input, columns } => {
+                let outputs = columns
+                    .into_iter()
+                    .map(|column| Output {
+                        function: None,
+                        column: Some(column),
+                    })
+                    .collect();
+                (input, outputs, false)
+            }
+            _ => return Err(Diagnostic::new(span, unsupported)),
+        };
+        let condition = match *input {
+            Plan::Scan { .. } => None,
+            Plan::Filter { input, condition } if matches!(*input, Plan::Scan { .. }) => {
+                Some(condition)
+            }
+            _ => return Err(Diagnostic::new(span, unsupported)),
         };
 
-        let v = &variable_decl.identifier;
-        //TODO: move memory offset with each subsequent variable declaration
-        self.output += &format!("{var_type} PTR [rbp-{offset}], {var_value} ; {v}\n");
+        // Function, column and type of each value of a row
+        let outputs: Vec<(Option<Function>, usize, Type)> = outputs
+            .iter()
+            .map(|output| {
+                let Some(column) = &output.column else {
+                    return Ok((output.function, 0, Type::Int(IntType::I64)));
+                };
+                let index = self.collections[collection].position(column);
+                let ty = self.collections[collection].columns[index]
+                    .ty
+                    .unwrap_or(Type::Int(IntType::I64));
+                if ty == Type::Str && output.function != Some(Function::Count) {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("{} is str, compiled queries give numbers only", column.name),
+                    ));
+                }
+                Ok((output.function, index, ty))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let count = self.collections[collection].rows.len();
+        let width = 8 * outputs.len();
+        let buffer = format!(".LR{}", self.buffers.len());
+        let size = if aggregates { width } else { width * count };
+        self.buffers.push((buffer.clone(), size));
+        let (row, next, done) = (self.label(), self.label(), self.label());
+
+        self.output += &format!("    lea  rbx, {buffer}[rip]\n");
+        if aggregates {
+            for (slot, (function, _, ty)) in outputs.iter().enumerate() {
+                let start: u64 = match (function, ty) {
+                    (Some(Function::Sum | Function::Avg), _) => 0,
+                    (Some(Function::Min), Type::Int(_)) => i64::MAX as u64,
+                    (Some(Function::Max), Type::Int(_)) => i64::MIN as u64,
+                    (Some(Function::Min), Type::Float(FloatType::F64)) => f64::INFINITY.to_bits(),
+                    (Some(Function::Max), Type::Float(FloatType::F64)) => {
+                        f64::NEG_INFINITY.to_bits()
+                    }
+                    (Some(Function::Min), Type::Float(FloatType::F32)) => {
+                        f32::INFINITY.to_bits() as u64
+                    }
+                    (Some(Function::Max), Type::Float(FloatType::F32)) => {
+                        f32::NEG_INFINITY.to_bits() as u64
+                    }
+                    _ => continue,
+                };
+                if start == 0 {
+                    self.output += &format!("    mov  QWORD PTR [rbx+{}], 0\n", 8 * slot);
+                    continue;
+                }
+                self.output += &format!("    mov  rax, {start:#x}\n");
+                self.output += &format!("    mov  QWORD PTR [rbx+{}], rax\n", 8 * slot);
+            }
+            self.output += "    xor  r13, r13\n";
+        }
+        let name = &self.collections[collection].name;
+        self.output += &format!("    xor  r12, r12\n{row}: # rows of {name}\n");
+        self.output += &format!("    cmp  r12, {count}\n    jae  {done}\n");
+        if let Some(condition) = &condition {
+            self.condition(collection, condition, &next);
+        }
+
+        for (slot, (function, column, ty)) in outputs.iter().copied().enumerate() {
+            let place = format!("[rbx+{}]", 8 * slot);
+            let (suffix, size) = match ty {
+                Type::Float(float_type) => sse(float_type),
+                _ => ("", "QWORD"),
+            };
+            match function {
+                Some(Function::Count) => continue,
+                _ => self.load(collection, column),
+            };
+            match (function, ty) {
+                (None, _) => {}
+                (Some(Function::Sum | Function::Avg), Type::Int(_)) => {
+                    self.output += &format!("    add  QWORD PTR {place}, rax\n");
+                    continue;
+                }
+                (Some(Function::Sum | Function::Avg), _) => {
+                    self.output += &format!("    add{suffix} xmm0, {size} PTR {place}\n");
+                }
+                (Some(function), Type::Int(_)) => {
+                    let keep = if function == Function::Min { "g" } else { "l" };
+                    self.output += &format!("    cmp  rax, QWORD PTR {place}\n");
+                    self.output += &format!("    cmov{keep} rax, QWORD PTR {place}\n");
+                }
+                (Some(function), _) => {
+                    let name = function.name();
+                    self.output += &format!("    {name}{suffix} xmm0, {size} PTR {place}\n");
+                }
+            }
+            self.mov(ty, &place, false, "");
+        }
+        if aggregates {
+            self.output += "    inc  r13\n";
+        } else {
+            self.output += &format!("    add  rbx, {width}\n");
+        }
+        self.output += &format!("{next}:\n    inc  r12\n    jmp  {row}\n{done}:\n");
+
+        if !aggregates {
+            self.output += &format!("    lea  rsi, {buffer}[rip]\n");
+            self.output += "    mov  rdx, rbx\n    sub  rdx, rsi\n";
+            self.write();
+            return Ok(());
+        }
+
+        // Aggregates of no rows but their count give no row, as in the interpreter
+        let end = self.label();
+        if outputs
+            .iter()
+            .any(|(function, ..)| *function != Some(Function::Count))
+        {
+            self.output += &format!("    test r13, r13\n    jz   {end}\n");
+        }
+        for (slot, (function, _, ty)) in outputs.iter().copied().enumerate() {
+            let place = format!("[rbx+{}]", 8 * slot);
+            match (function, ty) {
+                (Some(Function::Count), _) => {
+                    self.output += &format!("    mov  QWORD PTR {place}, r13\n");
+                }
+                // Averages are f64 whatever the type of the sum
+                (Some(Function::Avg), _) => {
+                    match ty {
+                        Type::Int(_) => {
+                            self.output += &format!("    cvtsi2sd xmm0, QWORD PTR {place}\n");
+                        }
+                        Type::Float(FloatType::F32) => {
+                            self.output += &format!("    cvtss2sd xmm0, DWORD PTR {place}\n");
+                        }
+                        _ => self.mov(ty, &place, true, ""),
+                    }
+                    self.output += "    cvtsi2sd xmm1, r13\n    divsd xmm0, xmm1\n";
+                    self.mov(Type::Float(FloatType::F64), &place, false, "");
+                }
+                _ => {}
+            }
+        }
+        self.output += "    mov  rsi, rbx\n";
+        self.output += &format!("    mov  rdx, {width}\n");
+        self.write();
+        self.output += &format!("{end}:\n");
+        Ok(())
+    }
+
+    /// Wraps the body in a prologue reserving the frame and an `exit(0)` syscall,
+    /// then returns the whole listing.
+    pub fn finish(&mut self) -> String {
+        let mut listing = String::with_capacity(self.output.len() + 256);
+        listing += ".intel_syntax noprefix\n";
+        listing += ".globl _start\n";
+        listing += ".text\n";
+        listing += "_start:\n";
+        listing += "    push rbp\n";
+        listing += "    mov  rbp, rsp\n";
+        if self.frame_size > 0 {
+            listing += &format!("    sub  rsp, {}\n", self.frame_size);
+        }
+
+        listing += &std::mem::take(&mut self.output);
+
+        listing += "    mov  rsp, rbp\n";
+        listing += "    pop  rbp\n";
+        listing += "    mov  rax, 60\n";
+        listing += "    xor  rdi, rdi\n";
+        listing += "    syscall\n";
+
+        if !self.constants.is_empty() || !self.collections.is_empty() {
+            listing += "\n.section .rodata\n";
+            for constant in self.constants.drain(..) {
+                listing += &constant;
+            }
+            for collection in self.collections.drain(..) {
+                listing += &collection.layout();
+            }
+        }
+
+        if !self.buffers.is_empty() {
+            listing += "\n.section .bss\n";
+            for (label, size) in self.buffers.drain(..) {
+                listing += &format!("    .p2align 3\n{label}:\n    .zero {size}\n");
+            }
+        }
+
+        self.frame_size = 0;
+        listing
     }
 
-    pub fn build(&mut self) {
-        fs::write("./out.asm", &self.output).expect("Failed to save asm file!");
-        self.output.clear();
+    pub fn build(&mut self) -> io::Result<()> {
+        let output = self.finish();
+        fs::write(&self.filename, output)
+    }
+}
+
+impl Visitor for Assembler {
+    fn visit_stmt(&mut self, ast: &AST, id: StmtId) {
+        match &ast[id].kind {
+            StmtKind::Let(decl) => self.var(ast, decl),
+            StmtKind::Record(record) => self.record(record),
+            StmtKind::Insert(insert) => self.insert(ast, insert),
+            StmtKind::Table(table) => {
+                self.record(&table.record);
+                self.insert(ast, &table.insert);
+            }
+            StmtKind::Query(query) => {
+                if let Err(diagnostic) = self.query(ast, query, ast[id].span) {
+                    self.diagnostics.push(diagnostic);
+                }
+            }
+            StmtKind::Load(_) | StmtKind::Save(_) => self.diagnostics.push(Diagnostic::new(
+                ast[id].span,
+                "Only the interpreter loads and saves files, compiled programs hold inserted rows",
+            )),
+            _ => walk_stmt(self, ast, id),
+        }
+    }
+
+    /// Leaves the value of the expression in `rax`, or in `xmm0` for floats.
+    fn visit_expr(&mut self, ast: &AST, id: ExprId) {
+        match &ast[id].kind {
+            ExprKind::Literal(Literals::NUMBER(value, int_type)) => {
+                self.output += &format!("    mov  rax, {}\n", int_type.parse(value));
+            }
+            ExprKind::Literal(Literals::FLOAT(value, float_type)) => {
+                let label = self.constant(value, *float_type);
+                self.mov(self.types[id], &format!("{label}[rip]"), true, "");
+            }
+            ExprKind::Literal(Literals::STR(_)) => self.diagnostics.push(Diagnostic::new(
+                ast[id].span,
+                "Strings cannot be compiled yet",
+            )),
+            ExprKind::Variable(_) if self.types[id] == Type::Str => self.diagnostics.push(
+                Diagnostic::new(ast[id].span, "Strings cannot be compiled yet"),
+            ),
+            ExprKind::Variable(var) => {
+                let offset = var.stack_offset + get_var_size(&ast[id]);
+                let comment = format!(" # {}", var.identifier);
+                self.mov(self.types[id], &format!("[rbp-{offset}]"), true, &comment);
+            }
+            ExprKind::Binary(op, lhs, rhs) => match self.types[id] {
+                Type::Int(int_type) => {
+                    self.visit_expr(ast, *lhs);
+                    self.output += "    push rax\n";
+                    self.visit_expr(ast, *rhs);
+                    self.output += "    mov  rcx, rax\n";
+                    self.output += "    pop  rax\n";
+                    self.output += match op {
+                        BinaryOp::Add => "    add  rax, rcx\n",
+                        BinaryOp::Sub => "    sub  rax, rcx\n",
+                        BinaryOp::Mul => "    imul rax, rcx\n",
+                        BinaryOp::Div if int_type.is_signed() => "    cqo\n    idiv rcx\n",
+                        BinaryOp::Div => "    xor  edx, edx\n    div  rcx\n",
+                    };
+                    self.narrow(int_type);
+                }
+                Type::Float(float_type) => {
+                    let ty = self.types[id];
+                    self.visit_expr(ast, *lhs);
+                    self.output += "    sub  rsp, 8\n";
+                    self.mov(ty, "[rsp]", false, "");
+                    self.visit_expr(ast, *rhs);
+                    self.output += "    movaps xmm1, xmm0\n";
+                    self.mov(ty, "[rsp]", true, "");
+                    self.output += "    add  rsp, 8\n";
+
+                    let instruction = match op {
+                        BinaryOp::Add => "add",
+                        BinaryOp::Sub => "sub",
+                        BinaryOp::Mul => "mul",
+                        BinaryOp::Div => "div",
+                    };
+                    let (suffix, _) = sse(float_type);
+                    self.output += &format!("    {instruction}{suffix} xmm0, xmm1\n");
+                }
+                Type::Str => unreachable!("The type checker rejects operators on strings"),
+            },
+            ExprKind::Cast(value, to) => {
+                self.visit_expr(ast, *value);
+
+                self.output += match (self.types[*value], *to) {
+                    (Type::Int(_), Type::Float(FloatType::F64)) => "    cvtsi2sd xmm0, rax\n",
+                    (Type::Int(_), Type::Float(FloatType::F32)) => "    cvtsi2ss xmm0, rax\n",
+                    (Type::Float(FloatType::F64), Type::Int(_)) => "    cvttsd2si rax, xmm0\n",
+                    (Type::Float(FloatType::F32), Type::Int(_)) => "    cvttss2si rax, xmm0\n",
+                    (Type::Float(FloatType::F64), Type::Float(FloatType::F32)) => {
+                        "    cvtsd2ss xmm0, xmm0\n"
+                    }
+                    (Type::Float(FloatType::F32), Type::Float(FloatType::F64)) => {
+                        "    cvtss2sd xmm0, xmm0\n"
+                    }
+                    _ => "",
+                };
+                if let Type::Int(int_type) = *to {
+                    self.narrow(int_type);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+    use crate::symbols::CodeScope;
+    use crate::tokenizer::Tokenizer;
+    use crate::typeck::TypeChecker;
+
+    use std::path::Path;
+    use std::process::Command;
+
+    use crate::interpreter::{Interpreter, Value};
+
+    use super::Assembler;
+
+    #[test]
+    fn computes_floats_in_sse_registers() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser
+            .parse(Tokenizer::new("let a = 3;\nlet b = a as f64 * 1.5;\n"))
+            .unwrap();
+        let types = TypeChecker::new().check(&ast).unwrap();
+
+        let mut assembler = Assembler::new("test");
+        assembler.assemble_ast(&ast, types).unwrap();
+        let listing = assembler.finish();
+
+        assert!(listing.contains("    cvtsi2sd xmm0, rax\n"));
+        assert!(listing.contains("    movsd xmm0, QWORD PTR .LC0[rip]\n"));
+        assert!(listing.contains("    mulsd xmm0, xmm1\n"));
+        assert!(listing.contains("    movsd QWORD PTR [rbp-32], xmm0 # b\n"));
+        assert!(listing.ends_with(".section .rodata\n.LC0:\n    .double 1.5\n"));
+    }
+
+    #[test]
+    fn reports_strings_instead_of_compiling_them() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser
+            .parse(Tokenizer::new("let s = \"abc\";\nlet t = s;\n"))
+            .unwrap();
+        let types = TypeChecker::new().check(&ast).unwrap();
+
+        let errors = Assembler::new("test")
+            .assemble_ast(&ast, types)
+            .unwrap_err();
+        let errors: Vec<(usize, &str)> = errors
+            .iter()
+            .map(|error| (error.span.start, error.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (8, "Strings cannot be compiled yet"),
+                (23, "Strings cannot be compiled yet"),
+            ]
+        );
+    }
+
+    #[test]
+    fn wraps_integers_at_their_width() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser
+            .parse(Tokenizer::new(
+                "let a = 200u8;\nlet b = a + 100u8;\nlet c = 7u64 / 2u64;\n",
+            ))
+            .unwrap();
+        let types = TypeChecker::new().check(&ast).unwrap();
+
+        let mut assembler = Assembler::new("test");
+        assembler.assemble_ast(&ast, types).unwrap();
+        let listing = assembler.finish();
+
+        assert!(listing.contains("    add  rax, rcx\n    movzx eax, al\n"));
+        assert!(listing.contains("    xor  edx, edx\n    div  rcx\n"));
+    }
+
+    #[test]
+    fn defines_field_offsets_of_records() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser
+            .parse(Tokenizer::new(
+                "Career: Name: str Wage: i64\nPerson: Name: str Age: i64 Career\nDraft: Title\n",
+            ))
+            .unwrap();
+        let types = TypeChecker::new().check(&ast).unwrap();
+
+        let mut assembler = Assembler::new("test");
+        assembler.assemble_ast(&ast, types).unwrap();
+        let listing = assembler.finish();
+
+        assert!(listing.contains(
+            "    .set Person.Name, 0\n    .set Person.Age, 16\n    .set Person.Career, 24\n    .set Person_size, 48\n"
+        ));
+        assert!(listing.contains("    .set Career.Wage, 16\n"));
+        assert!(!listing.contains("Draft"));
+    }
+
+    #[test]
+    fn loops_over_collections_a_column_at_a_time() {
+        let data = "Sale: Region: str Units: i64 Price: f64\nSales -> North 3 2.5, South 10 4.25, North 3 2.5\n\n";
+        let source =
+            format!("{data}get sum units of sales whose region is North and price < 3.0\n");
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(&source))
+            .unwrap();
+        let types = TypeChecker::new().check(&ast).unwrap();
+
+        let mut assembler = Assembler::new("test");
+        assembler.assemble_ast(&ast, types).unwrap();
+        let listing = assembler.finish();
+
+        assert!(listing.contains(
+            "    mov  eax, DWORD PTR Sales.Region[r12*4]\n    cmp  BYTE PTR .LC0[rax], 0\n    je   .L2\n"
+        ));
+        assert!(listing.contains(
+            "    movsd xmm0, QWORD PTR Sales.Price[r12*8]\n    ucomisd xmm0, QWORD PTR .LC1[rip]\n    jae  .L2\n"
+        ));
+        assert!(listing.contains(
+            "    mov  rax, QWORD PTR Sales.Units[r12*8]\n    add  QWORD PTR [rbx+0], rax\n    inc  r13\n"
+        ));
+        assert!(listing.contains(".LC0:\n    .byte 1, 0\n.LC1:\n    .double 3.0\n"));
+        assert!(listing.contains("Sales.Region:\n    .long 0, 1, 0\n"));
+        assert!(listing.contains("Sales.Units:\n    .quad 3, 10, 3\n"));
+        assert!(listing.contains(
+            "Sales.Price:\n    .quad 0x4004000000000000, 0x4011000000000000, 0x4004000000000000\n"
+        ));
+        assert!(listing.contains(".section .bss\n    .p2align 3\n.LR0:\n    .zero 8\n"));
+
+        let source = format!("{data}get units of sales sorted by price\n");
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(&source))
+            .unwrap();
+        let types = TypeChecker::new().check(&ast).unwrap();
+        let error = Assembler::new("test")
+            .assemble_ast(&ast, types)
+            .unwrap_err()
+            .remove(0);
+        assert_eq!(
+            error.message,
+            "Only filters and aggregates of a single collection can be compiled yet"
+        );
+
+        let source = "load \"samples/countries.csv\" as Countries\n";
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(source))
+            .unwrap();
+        let types = TypeChecker::new().check(&ast).unwrap();
+        let error = Assembler::new("test")
+            .assemble_ast(&ast, types)
+            .unwrap_err()
+            .remove(0);
+        assert_eq!(
+            error.message,
+            "Only the interpreter loads and saves files, compiled programs hold inserted rows"
+        );
+    }
+
+    /// Assembles and runs programs of generated rows, each value of the rows a query gives
+    /// written as 8 bytes, and compares them with the interpreter's.
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn compiled_queries_give_the_rows_of_the_interpreter() {
+        let regions = ["North", "South", "East", "West", "Midlands"];
+        let mut seed: u64 = 42;
+        let mut random = |range: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % range
+        };
+        let mut data = String::from("Sale: Region: str Units: i64 Price: f64 Weight: f32\n");
+        for _ in 0..8 {
+            let rows: Vec<String> = (0..50)
+                .map(|_| {
+                    let region = regions[random(5) as usize];
+                    let units = random(2000) as i64 - 500;
+                    let price = random(4000) as f64 / 8.0;
+                    let weight = random(100) as f32 / 4.0 + 0.25;
+                    format!("{region} {units} {price:?} {weight:?}f32")
+                })
+                .collect();
+            data += &format!("Sales -> {}\n", rows.join(", "));
+        }
+
+        let queries = [
+            "get count, sum units, avg units, min units, max units of sales",
+            "get avg price, min price, max price, sum weight, avg weight, min weight of sales whose region is North",
+            "get units, price, weight of sales whose units > 900 and (region is East or price < 100.5)",
+            "get count, max weight of sales whose region starts with M or region ends with th and weight <= 12.5f32",
+            "get sum units, count of sales whose price >= 400.0 and region != West",
+            "get max units, count of sales whose units < -1000",
+            "get count of sales whose units < -1000",
+        ];
+        for (index, query) in queries.iter().enumerate() {
+            let source = format!("{data}\n{query}\n");
+            let ast = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(&source))
+                .unwrap();
+
+            let Some((_, Value::Rows(_, rows))) = Interpreter::new().run(&ast).unwrap().pop()
+            else {
+                panic!("{query} gives no rows");
+            };
+            let expected: Vec<u8> = rows
+                .iter()
+                .flatten()
+                .flat_map(|value| match value {
+                    Value::Number(value, _) => value.to_le_bytes(),
+                    Value::Float(value) => value.to_bits().to_le_bytes(),
+                    Value::Float32(value) => (value.to_bits() as u64).to_le_bytes(),
+                    value => panic!("{query} gives {value}"),
+                })
+                .collect();
+
+            let path = std::env::temp_dir().join(format!("x-rs-query-{index}"));
+            let types = TypeChecker::new().check(&ast).unwrap();
+            let mut assembler = Assembler::new(path.with_extension("asm").to_str().unwrap());
+            assembler.assemble_ast(&ast, types).unwrap();
+            assembler.build().unwrap();
+            let run = |program: &str, args: &[&std::path::Path]| {
+                let output = Command::new(program).args(args).output().unwrap();
+                assert!(output.status.success(), "{program} failed for {query}");
+                output.stdout
+            };
+            let (asm, object) = (path.with_extension("asm"), path.with_extension("o"));
+            run("as", &[&asm, Path::new("-o"), &object]);
+            run("ld", &[&object, Path::new("-o"), &path]);
+            let output = run(path.to_str().unwrap(), &[]);
+            for file in [&asm, &object, &path] {
+                std::fs::remove_file(file).unwrap();
+            }
+
+            assert!(!expected.is_empty() || index >= 5, "{query} keeps no rows");
+            assert_eq!(output, expected, "{query}");
+        }
     }
 }
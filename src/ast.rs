@@ -0,0 +1,280 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+
+use crate::declarations::{Expr, ExprKind, Filter, Item, SaveStmt, Stmt, StmtKind, TableDecl};
+
+/// Typed index into one of the `AST` arenas.
+pub struct Id<T> {
+    index: u32,
+    marker: PhantomData<fn() -> T>,
+}
+
+pub type ItemId = Id<Item>;
+pub type StmtId = Id<Stmt>;
+pub type ExprId = Id<Expr>;
+
+impl<T> Id<T> {
+    pub fn new(index: usize) -> Self {
+        Self {
+            index: index as u32,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+}
+
+// Derives would require `T` to implement these traits as well
+impl<T> Clone for Id<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Id<T> {}
+
+impl<T> PartialEq for Id<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Id<T> {}
+
+impl<T> std::hash::Hash for Id<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Id<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.index)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Arena<T> {
+    nodes: Vec<T>,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self { nodes: vec![] }
+    }
+}
+
+impl<T> Arena<T> {
+    pub fn alloc(&mut self, node: T) -> Id<T> {
+        self.nodes.push(node);
+        Id::new(self.nodes.len() - 1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Id<T>, &T)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (Id::new(index), node))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Id<T>, &mut T)> {
+        self.nodes
+            .iter_mut()
+            .enumerate()
+            .map(|(index, node)| (Id::new(index), node))
+    }
+
+    /// Id the next allocated node gets, every node allocated so far has a smaller one.
+    pub fn next_id(&self) -> Id<T> {
+        Id::new(self.nodes.len())
+    }
+
+    pub fn swap(&mut self, a: Id<T>, b: Id<T>) {
+        self.nodes.swap(a.index(), b.index());
+    }
+}
+
+/// Root of the tree: `Program -> Item -> Stmt -> Expr`.
+/// Nodes live in one arena per kind and point at each other through typed ids.
+#[derive(Debug, PartialEq, Default)]
+pub struct AST {
+    pub items: Vec<ItemId>,
+    pub item_arena: Arena<Item>,
+    pub stmt_arena: Arena<Stmt>,
+    pub expr_arena: Arena<Expr>,
+}
+
+impl AST {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+macro_rules! arena_index {
+    ($node:ty, $arena:ident) => {
+        impl Index<Id<$node>> for AST {
+            type Output = $node;
+
+            fn index(&self, id: Id<$node>) -> &Self::Output {
+                &self.$arena.nodes[id.index()]
+            }
+        }
+
+        impl IndexMut<Id<$node>> for AST {
+            fn index_mut(&mut self, id: Id<$node>) -> &mut Self::Output {
+                &mut self.$arena.nodes[id.index()]
+            }
+        }
+    };
+}
+
+arena_index!(Item, item_arena);
+arena_index!(Stmt, stmt_arena);
+arena_index!(Expr, expr_arena);
+
+// VISITORS
+
+/// Read-only traversal. Override the `visit_*` methods you care about and call the
+/// matching `walk_*` function to keep descending.
+pub trait Visitor: Sized {
+    fn visit_program(&mut self, ast: &AST) {
+        walk_program(self, ast);
+    }
+
+    fn visit_item(&mut self, ast: &AST, id: ItemId) {
+        walk_item(self, ast, id);
+    }
+
+    fn visit_stmt(&mut self, ast: &AST, id: StmtId) {
+        walk_stmt(self, ast, id);
+    }
+
+    fn visit_expr(&mut self, ast: &AST, id: ExprId) {
+        walk_expr(self, ast, id);
+    }
+}
+
+pub fn walk_program<V: Visitor>(visitor: &mut V, ast: &AST) {
+    for item in &ast.items {
+        visitor.visit_item(ast, *item);
+    }
+}
+
+pub fn walk_item<V: Visitor>(visitor: &mut V, ast: &AST, id: ItemId) {
+    match ast[id] {
+        Item::Stmt(stmt) => visitor.visit_stmt(ast, stmt),
+    }
+}
+
+pub fn walk_stmt<V: Visitor>(visitor: &mut V, ast: &AST, id: StmtId) {
+    match &ast[id].kind {
+        StmtKind::Let(decl) => visitor.visit_expr(ast, decl.value),
+        StmtKind::Block(block) => {
+            for child in &block.children {
+                visitor.visit_stmt(ast, *child);
+            }
+        }
+        StmtKind::Expr(expr) => visitor.visit_expr(ast, *expr),
+        StmtKind::Record(_) | StmtKind::Load(_) => {}
+        StmtKind::Query(query) | StmtKind::Save(SaveStmt { query, .. }) => {
+            for value in query.filter.iter().flat_map(Filter::values) {
+                visitor.visit_expr(ast, value);
+            }
+        }
+        StmtKind::Insert(insert) | StmtKind::Table(TableDecl { insert, .. }) => {
+            for value in insert.rows.iter().flatten() {
+                visitor.visit_expr(ast, *value);
+            }
+        }
+    }
+}
+
+pub fn walk_expr<V: Visitor>(visitor: &mut V, ast: &AST, id: ExprId) {
+    match &ast[id].kind {
+        ExprKind::Literal(_) | ExprKind::Variable(_) => {}
+        ExprKind::Binary(_, lhs, rhs) => {
+            visitor.visit_expr(ast, *lhs);
+            visitor.visit_expr(ast, *rhs);
+        }
+        ExprKind::Cast(value, _) => visitor.visit_expr(ast, *value),
+    }
+}
+
+/// Traversal which may rewrite nodes in place.
+pub trait MutVisitor: Sized {
+    fn visit_program(&mut self, ast: &mut AST) {
+        walk_program_mut(self, ast);
+    }
+
+    fn visit_item(&mut self, ast: &mut AST, id: ItemId) {
+        walk_item_mut(self, ast, id);
+    }
+
+    fn visit_stmt(&mut self, ast: &mut AST, id: StmtId) {
+        walk_stmt_mut(self, ast, id);
+    }
+
+    fn visit_expr(&mut self, ast: &mut AST, id: ExprId) {
+        walk_expr_mut(self, ast, id);
+    }
+}
+
+pub fn walk_program_mut<V: MutVisitor>(visitor: &mut V, ast: &mut AST) {
+    for item in ast.items.clone() {
+        visitor.visit_item(ast, item);
+    }
+}
+
+pub fn walk_item_mut<V: MutVisitor>(visitor: &mut V, ast: &mut AST, id: ItemId) {
+    match ast[id] {
+        Item::Stmt(stmt) => visitor.visit_stmt(ast, stmt),
+    }
+}
+
+pub fn walk_stmt_mut<V: MutVisitor>(visitor: &mut V, ast: &mut AST, id: StmtId) {
+    match &ast[id].kind {
+        StmtKind::Let(decl) => {
+            let value = decl.value;
+            visitor.visit_expr(ast, value);
+        }
+        StmtKind::Block(block) => {
+            for child in block.children.clone() {
+                visitor.visit_stmt(ast, child);
+            }
+        }
+        StmtKind::Expr(expr) => {
+            let expr = *expr;
+            visitor.visit_expr(ast, expr);
+        }
+        StmtKind::Record(_) | StmtKind::Load(_) => {}
+        StmtKind::Query(query) | StmtKind::Save(SaveStmt { query, .. }) => {
+            for value in query
+                .filter
+                .iter()
+                .flat_map(Filter::values)
+                .collect::<Vec<_>>()
+            {
+                visitor.visit_expr(ast, value);
+            }
+        }
+        StmtKind::Insert(insert) | StmtKind::Table(TableDecl { insert, .. }) => {
+            for value in insert.rows.concat() {
+                visitor.visit_expr(ast, value);
+            }
+        }
+    }
+}
+
+pub fn walk_expr_mut<V: MutVisitor>(visitor: &mut V, ast: &mut AST, id: ExprId) {
+    match ast[id].kind {
+        ExprKind::Literal(_) | ExprKind::Variable(_) => {}
+        ExprKind::Binary(_, lhs, rhs) => {
+            visitor.visit_expr(ast, lhs);
+            visitor.visit_expr(ast, rhs);
+        }
+        ExprKind::Cast(value, _) => visitor.visit_expr(ast, value),
+    }
+}
@@ -0,0 +1,1132 @@
+use crate::{
+    ast::{ExprId, Id, StmtId, AST},
+    declarations::{
+        Aggregate, BinaryOp, CompoundStmt, Expr, ExprKind, FieldDecl, FieldRef, FieldType, Filter,
+        Function, InsertStmt, Item, Join, Literals, LoadStmt, Order, Predicate, Projection, Query,
+        RecordDecl, SaveStmt, Sort, Stmt, StmtKind, TableDecl, Test, Type, VariableDecl,
+        VariableRef,
+    },
+    json::Json,
+    tokenizer::{FloatType, IntType, Span},
+};
+
+fn item_stmts(ast: &AST) -> Vec<StmtId> {
+    ast.items
+        .iter()
+        .map(|item| match ast[*item] {
+            Item::Stmt(stmt) => stmt,
+        })
+        .collect()
+}
+
+fn field_label(field: &FieldDecl) -> String {
+    match &field.ty {
+        Some(ty) => format!("{}: {}", field.name, ty.name()),
+        None => field.name.clone(),
+    }
+}
+
+fn literal_label(literal: &Literals) -> String {
+    match literal {
+        Literals::NUMBER(value, int_type) => format!("Number {value}{}", int_type.suffix()),
+        Literals::FLOAT(value, float_type) => format!("Float {value} {}", float_type.name()),
+        Literals::STR(value) => format!("Str {value:?}"),
+    }
+}
+
+// TREE
+
+pub fn tree(ast: &AST) -> String {
+    let mut out = String::from("Program\n");
+    let stmts = item_stmts(ast);
+    for (i, stmt) in stmts.iter().enumerate() {
+        tree_stmt(ast, *stmt, "", i + 1 == stmts.len(), &mut out);
+    }
+    out
+}
+
+/// Start of the line of a tree node and the prefix of the lines of its children.
+pub fn branches(prefix: &str, is_last: bool) -> (String, String) {
+    let branch = if is_last { "└── " } else { "├── " };
+    let child_prefix = if is_last { "    " } else { "│   " };
+    (
+        format!("{prefix}{branch}"),
+        format!("{prefix}{child_prefix}"),
+    )
+}
+
+fn tree_stmt(ast: &AST, id: StmtId, prefix: &str, is_last: bool, out: &mut String) {
+    let (line, child_prefix) = branches(prefix, is_last);
+
+    match &ast[id].kind {
+        StmtKind::Let(decl) => {
+            *out += &format!("{line}Let {} offset={}", decl.identifier, decl.stack_offset);
+            if let Some(doc) = &decl.doc {
+                *out += &format!(" doc={doc:?}");
+            }
+            *out += "\n";
+            tree_expr(ast, decl.value, &child_prefix, true, out);
+        }
+        StmtKind::Block(block) => {
+            *out += &format!(
+                "{line}Block scope={} offset={}\n",
+                block.scope, block.stack_offset
+            );
+            for (i, child) in block.children.iter().enumerate() {
+                let is_last = i + 1 == block.children.len();
+                tree_stmt(ast, *child, &child_prefix, is_last, out);
+            }
+        }
+        StmtKind::Expr(expr) => {
+            *out += &format!("{line}Expr\n");
+            tree_expr(ast, *expr, &child_prefix, true, out);
+        }
+        StmtKind::Insert(insert) => {
+            *out += &format!(
+                "{line}Insert {} record={}\n",
+                insert.collection, insert.record
+            );
+            tree_rows(ast, insert, &child_prefix, out);
+        }
+        StmtKind::Record(record) => {
+            *out += &format!("{line}Record {}\n", record.name);
+            tree_fields(record, &child_prefix, true, out);
+        }
+        StmtKind::Query(query) => tree_query(ast, query, &line, &child_prefix, out),
+        StmtKind::Save(save) => {
+            *out += &format!("{line}Save path={:?}\n", save.path);
+            let (line, child_prefix) = branches(&child_prefix, true);
+            tree_query(ast, &save.query, &line, &child_prefix, out);
+        }
+        StmtKind::Load(load) => {
+            *out += &format!(
+                "{line}Load {} record={} path={:?}\n",
+                load.collection, load.record, load.path
+            );
+        }
+        StmtKind::Table(table) => {
+            *out += &format!(
+                "{line}Table {} record={}\n",
+                table.insert.collection, table.record.name
+            );
+            tree_fields(
+                &table.record,
+                &child_prefix,
+                table.insert.rows.is_empty(),
+                out,
+            );
+            tree_rows(ast, &table.insert, &child_prefix, out);
+        }
+    }
+}
+
+fn tree_query(ast: &AST, query: &Query, line: &str, child_prefix: &str, out: &mut String) {
+    *out += &format!("{line}Query {} record={}\n", query.collection, query.record);
+    let children = query.fields.len()
+        + query.joins.len()
+        + [
+            query.with.is_some(),
+            query.filter.is_some(),
+            query.group.is_some(),
+            query.sort.is_some(),
+        ]
+        .iter()
+        .filter(|given| **given)
+        .count();
+    let mut child = 0;
+    let mut next = || {
+        child += 1;
+        branches(child_prefix, child == children)
+    };
+
+    for field in &query.fields {
+        let (line, _) = next();
+        *out += &format!("{line}Get {}\n", field.label());
+    }
+    for join in &query.joins {
+        let (line, _) = next();
+        *out += &format!("{line}Join {} record={}", join.collection, join.record);
+        if let Some((lhs, rhs)) = &join.on {
+            *out += &format!(" on {} = {}", lhs.word, rhs.word);
+        }
+        *out += "\n";
+    }
+    if let Some(with) = &query.with {
+        let (line, _) = next();
+        *out += &format!("{line}With {}\n", with.label());
+    }
+    if let Some(filter) = &query.filter {
+        let (line, where_prefix) = next();
+        *out += &format!("{line}Where\n");
+        tree_filter(ast, filter, &where_prefix, true, out);
+    }
+    if let Some(group) = &query.group {
+        let (line, _) = next();
+        *out += &format!("{line}Group {}\n", group.word);
+    }
+    if let Some(sort) = &query.sort {
+        let (line, _) = next();
+        *out += &format!("{line}Sort {}", sort.field.word);
+        if let Some(order) = sort.order {
+            *out += &format!(" {}", order.name());
+        }
+        *out += "\n";
+    }
+}
+
+fn tree_filter(ast: &AST, filter: &Filter, prefix: &str, is_last: bool, out: &mut String) {
+    let (line, child_prefix) = branches(prefix, is_last);
+
+    match filter {
+        Filter::Test(test) => {
+            *out += &format!(
+                "{line}Test {} {}\n",
+                test.field.word,
+                test.predicate.symbol()
+            );
+            tree_expr(ast, test.value, &child_prefix, true, out);
+        }
+        Filter::And(lhs, rhs) | Filter::Or(lhs, rhs) => {
+            let label = if matches!(filter, Filter::And(..)) {
+                "And"
+            } else {
+                "Or"
+            };
+            *out += &format!("{line}{label}\n");
+            tree_filter(ast, lhs, &child_prefix, false, out);
+            tree_filter(ast, rhs, &child_prefix, true, out);
+        }
+    }
+}
+
+fn tree_fields(record: &RecordDecl, prefix: &str, last: bool, out: &mut String) {
+    for (i, field) in record.fields.iter().enumerate() {
+        let (line, _) = branches(prefix, last && i + 1 == record.fields.len());
+        *out += &format!("{line}Field {}\n", field_label(field));
+    }
+}
+
+fn tree_rows(ast: &AST, insert: &InsertStmt, prefix: &str, out: &mut String) {
+    for (i, row) in insert.rows.iter().enumerate() {
+        let (line, row_prefix) = branches(prefix, i + 1 == insert.rows.len());
+        *out += &format!("{line}Row\n");
+        for (j, value) in row.iter().enumerate() {
+            tree_expr(ast, *value, &row_prefix, j + 1 == row.len(), out);
+        }
+    }
+}
+
+fn tree_expr(ast: &AST, id: ExprId, prefix: &str, is_last: bool, out: &mut String) {
+    let (line, child_prefix) = branches(prefix, is_last);
+
+    match &ast[id].kind {
+        ExprKind::Literal(literal) => *out += &format!("{line}{}\n", literal_label(literal)),
+        ExprKind::Variable(var) => {
+            *out += &format!(
+                "{line}Variable {} offset={}\n",
+                var.identifier, var.stack_offset
+            );
+        }
+        ExprKind::Binary(op, lhs, rhs) => {
+            *out += &format!("{line}Binary {}\n", op.symbol());
+            tree_expr(ast, *lhs, &child_prefix, false, out);
+            tree_expr(ast, *rhs, &child_prefix, true, out);
+        }
+        ExprKind::Cast(value, ty) => {
+            *out += &format!("{line}Cast {}\n", ty.name());
+            tree_expr(ast, *value, &child_prefix, true, out);
+        }
+    }
+}
+
+// S-EXPRESSIONS
+
+pub fn sexp(ast: &AST) -> String {
+    let mut out = String::from("(program");
+    for stmt in item_stmts(ast) {
+        out += "\n  ";
+        sexp_stmt(ast, stmt, &mut out);
+    }
+    out += ")\n";
+    out
+}
+
+fn sexp_stmt(ast: &AST, id: StmtId, out: &mut String) {
+    match &ast[id].kind {
+        StmtKind::Let(decl) => {
+            *out += &format!("(let {} :offset {} ", decl.identifier, decl.stack_offset);
+            if let Some(doc) = &decl.doc {
+                *out += &format!(":doc {doc:?} ");
+            }
+            sexp_expr(ast, decl.value, out);
+            *out += ")";
+        }
+        StmtKind::Block(block) => {
+            *out += &format!(
+                "(block :scope {} :offset {}",
+                block.scope, block.stack_offset
+            );
+            for child in &block.children {
+                *out += " ";
+                sexp_stmt(ast, *child, out);
+            }
+            *out += ")";
+        }
+        StmtKind::Expr(expr) => {
+            *out += "(expr ";
+            sexp_expr(ast, *expr, out);
+            *out += ")";
+        }
+        StmtKind::Insert(insert) => {
+            *out += &format!("(insert {} :record {}", insert.collection, insert.record);
+            sexp_rows(ast, insert, out);
+            *out += ")";
+        }
+        StmtKind::Record(record) => {
+            *out += &format!("(record {}", record.name);
+            sexp_fields(record, out);
+            *out += ")";
+        }
+        StmtKind::Query(query) => sexp_query(ast, query, out),
+        StmtKind::Save(save) => {
+            *out += &format!("(save :path {:?} ", save.path);
+            sexp_query(ast, &save.query, out);
+            *out += ")";
+        }
+        StmtKind::Load(load) => {
+            *out += &format!(
+                "(load {} :record {} :path {:?})",
+                load.collection, load.record, load.path
+            );
+        }
+        StmtKind::Table(table) => {
+            *out += &format!(
+                "(table {} :record {}",
+                table.insert.collection, table.record.name
+            );
+            sexp_fields(&table.record, out);
+            sexp_rows(ast, &table.insert, out);
+            *out += ")";
+        }
+    }
+}
+
+fn sexp_query(ast: &AST, query: &Query, out: &mut String) {
+    *out += &format!("(query {} :record {}", query.collection, query.record);
+    for field in &query.fields {
+        *out += &match field {
+            Projection::Field(field) => format!(" (get {})", field.word),
+            Projection::Aggregate(aggregate) => {
+                format!(" (get ({}))", aggregate.label())
+            }
+        };
+    }
+    for join in &query.joins {
+        *out += &format!(" (join {} :record {}", join.collection, join.record);
+        if let Some((lhs, rhs)) = &join.on {
+            *out += &format!(" :on ({} {})", lhs.word, rhs.word);
+        }
+        *out += ")";
+    }
+    if let Some(with) = &query.with {
+        *out += &format!(" (with ({}))", with.label());
+    }
+    if let Some(filter) = &query.filter {
+        *out += " (where ";
+        sexp_filter(ast, filter, out);
+        *out += ")";
+    }
+    if let Some(group) = &query.group {
+        *out += &format!(" (group {})", group.word);
+    }
+    if let Some(sort) = &query.sort {
+        *out += &format!(" (sort {}", sort.field.word);
+        if let Some(order) = sort.order {
+            *out += &format!(" {}", order.name());
+        }
+        *out += ")";
+    }
+    *out += ")";
+}
+
+/// Predicates are written with `-` in place of spaces, as `starts-with`.
+fn sexp_filter(ast: &AST, filter: &Filter, out: &mut String) {
+    let (label, lhs, rhs) = match filter {
+        Filter::Test(test) => {
+            let symbol = test.predicate.symbol().replace(' ', "-");
+            *out += &format!("({symbol} {} ", test.field.word);
+            sexp_expr(ast, test.value, out);
+            *out += ")";
+            return;
+        }
+        Filter::And(lhs, rhs) => ("and", lhs, rhs),
+        Filter::Or(lhs, rhs) => ("or", lhs, rhs),
+    };
+    *out += &format!("({label} ");
+    sexp_filter(ast, lhs, out);
+    *out += " ";
+    sexp_filter(ast, rhs, out);
+    *out += ")";
+}
+
+fn sexp_fields(record: &RecordDecl, out: &mut String) {
+    for field in &record.fields {
+        *out += &match &field.ty {
+            Some(FieldType::Scalar(ty)) => format!(" (field {} {})", field.name, ty.name()),
+            Some(FieldType::Record(name)) => format!(" (field {} :record {name})", field.name),
+            None => format!(" (field {})", field.name),
+        };
+    }
+}
+
+fn sexp_rows(ast: &AST, insert: &InsertStmt, out: &mut String) {
+    for row in &insert.rows {
+        *out += " (row";
+        for value in row {
+            *out += " ";
+            sexp_expr(ast, *value, out);
+        }
+        *out += ")";
+    }
+}
+
+fn sexp_expr(ast: &AST, id: ExprId, out: &mut String) {
+    match &ast[id].kind {
+        ExprKind::Literal(Literals::NUMBER(value, int_type)) => {
+            *out += &format!("(number {value}{})", int_type.suffix())
+        }
+        ExprKind::Literal(Literals::FLOAT(value, float_type)) => {
+            *out += &format!("({} {value})", float_type.name())
+        }
+        ExprKind::Literal(Literals::STR(value)) => *out += &format!("(str {value:?})"),
+        ExprKind::Variable(var) => {
+            *out += &format!("(var {} :offset {})", var.identifier, var.stack_offset)
+        }
+        ExprKind::Binary(op, lhs, rhs) => {
+            *out += &format!("({} ", op.symbol());
+            sexp_expr(ast, *lhs, out);
+            *out += " ";
+            sexp_expr(ast, *rhs, out);
+            *out += ")";
+        }
+        ExprKind::Cast(value, ty) => {
+            *out += "(as ";
+            sexp_expr(ast, *value, out);
+            *out += &format!(" {})", ty.name());
+        }
+    }
+}
+
+// JSON
+
+fn id_json<T>(id: Id<T>) -> Json {
+    Json::Number(id.index() as f64)
+}
+
+fn span_json(span: Span) -> Json {
+    Json::Array(vec![
+        Json::Number(span.start as f64),
+        Json::Number(span.len as f64),
+    ])
+}
+
+/// Mirrors the arenas one to one, so that `from_json` gives back an equal AST.
+fn record_json(record: &RecordDecl) -> Json {
+    let fields = record
+        .fields
+        .iter()
+        .map(|field| {
+            let mut entries = vec![
+                ("name", Json::str(&field.name)),
+                ("span", span_json(field.span)),
+            ];
+            match &field.ty {
+                Some(FieldType::Scalar(ty)) => entries.push(("type", Json::str(ty.name()))),
+                Some(FieldType::Record(name)) => entries.push(("record", Json::str(name))),
+                None => {}
+            }
+            Json::object(entries)
+        })
+        .collect();
+    Json::object(vec![
+        ("name", Json::str(&record.name)),
+        ("fields", Json::Array(fields)),
+    ])
+}
+
+fn insert_json(insert: &InsertStmt) -> Json {
+    let rows = insert
+        .rows
+        .iter()
+        .map(|row| Json::Array(row.iter().map(|value| id_json(*value)).collect()))
+        .collect();
+    Json::object(vec![
+        ("collection", Json::str(&insert.collection)),
+        ("record", Json::str(&insert.record)),
+        ("rows", Json::Array(rows)),
+    ])
+}
+
+fn field_ref_json(field: &FieldRef) -> Json {
+    Json::object(vec![
+        ("word", Json::str(&field.word)),
+        ("span", span_json(field.span)),
+    ])
+}
+
+fn join_json(join: &Join) -> Json {
+    let mut entries = vec![
+        ("collection", Json::str(&join.collection)),
+        ("record", Json::str(&join.record)),
+        ("span", span_json(join.span)),
+    ];
+    if let Some((lhs, rhs)) = &join.on {
+        entries.push((
+            "on",
+            Json::Array(vec![field_ref_json(lhs), field_ref_json(rhs)]),
+        ));
+    }
+    Json::object(entries)
+}
+
+fn aggregate_json(aggregate: &Aggregate) -> Json {
+    let mut entries = vec![
+        ("function", Json::str(aggregate.function.name())),
+        ("span", span_json(aggregate.span)),
+    ];
+    if let Some(field) = &aggregate.field {
+        entries.push(("field", field_ref_json(field)));
+    }
+    Json::object(entries)
+}
+
+/// Fields as their word and span, aggregates nested under `aggregate`.
+fn projection_json(projection: &Projection) -> Json {
+    match projection {
+        Projection::Field(field) => field_ref_json(field),
+        Projection::Aggregate(aggregate) => {
+            Json::object(vec![("aggregate", aggregate_json(aggregate))])
+        }
+    }
+}
+
+fn filter_json(filter: &Filter) -> Json {
+    match filter {
+        Filter::Test(test) => Json::object(vec![(
+            "test",
+            Json::object(vec![
+                ("field", field_ref_json(&test.field)),
+                ("predicate", Json::str(test.predicate.symbol())),
+                ("value", id_json(test.value)),
+            ]),
+        )]),
+        Filter::And(lhs, rhs) => Json::object(vec![(
+            "and",
+            Json::Array(vec![filter_json(lhs), filter_json(rhs)]),
+        )]),
+        Filter::Or(lhs, rhs) => Json::object(vec![(
+            "or",
+            Json::Array(vec![filter_json(lhs), filter_json(rhs)]),
+        )]),
+    }
+}
+
+fn query_json(query: &Query) -> Json {
+    let mut entries = vec![
+        ("collection", Json::str(&query.collection)),
+        ("record", Json::str(&query.record)),
+        (
+            "fields",
+            Json::Array(query.fields.iter().map(projection_json).collect()),
+        ),
+    ];
+    if !query.joins.is_empty() {
+        entries.push((
+            "joins",
+            Json::Array(query.joins.iter().map(join_json).collect()),
+        ));
+    }
+    if let Some(with) = &query.with {
+        entries.push(("with", aggregate_json(with)));
+    }
+    if let Some(filter) = &query.filter {
+        entries.push(("filter", filter_json(filter)));
+    }
+    if let Some(group) = &query.group {
+        entries.push(("group", field_ref_json(group)));
+    }
+    if let Some(sort) = &query.sort {
+        let mut sort_entries = vec![("field", field_ref_json(&sort.field))];
+        if let Some(order) = sort.order {
+            sort_entries.push(("order", Json::str(order.name())));
+        }
+        entries.push(("sort", Json::object(sort_entries)));
+    }
+    Json::object(entries)
+}
+
+pub fn to_json(ast: &AST) -> Json {
+    let items = ast.items.iter().map(|item| id_json(*item)).collect();
+
+    let item_arena = ast
+        .item_arena
+        .iter()
+        .map(|(_, item)| match item {
+            Item::Stmt(stmt) => Json::object(vec![("stmt", id_json(*stmt))]),
+        })
+        .collect();
+
+    let stmt_arena = ast
+        .stmt_arena
+        .iter()
+        .map(|(_, stmt)| {
+            let kind = match &stmt.kind {
+                StmtKind::Let(decl) => {
+                    let mut fields = vec![
+                        ("stack_offset", Json::Number(decl.stack_offset as f64)),
+                        ("identifier", Json::str(&decl.identifier)),
+                        ("value", id_json(decl.value)),
+                    ];
+                    if let Some(doc) = &decl.doc {
+                        fields.push(("doc", Json::str(doc)));
+                    }
+                    ("let", Json::object(fields))
+                }
+                StmtKind::Block(block) => (
+                    "block",
+                    Json::object(vec![
+                        ("scope", Json::Number(block.scope as f64)),
+                        ("stack_offset", Json::Number(block.stack_offset as f64)),
+                        (
+                            "children",
+                            Json::Array(block.children.iter().map(|c| id_json(*c)).collect()),
+                        ),
+                    ]),
+                ),
+                StmtKind::Expr(expr) => ("expr", id_json(*expr)),
+                StmtKind::Insert(insert) => ("insert", insert_json(insert)),
+                StmtKind::Record(record) => ("record", record_json(record)),
+                StmtKind::Query(query) => ("query", query_json(query)),
+                StmtKind::Load(load) => (
+                    "load",
+                    Json::object(vec![
+                        ("path", Json::str(&load.path)),
+                        ("collection", Json::str(&load.collection)),
+                        ("record", Json::str(&load.record)),
+                    ]),
+                ),
+                StmtKind::Save(save) => (
+                    "save",
+                    Json::object(vec![
+                        ("path", Json::str(&save.path)),
+                        ("query", query_json(&save.query)),
+                    ]),
+                ),
+                StmtKind::Table(table) => (
+                    "table",
+                    Json::object(vec![
+                        ("record", record_json(&table.record)),
+                        ("insert", insert_json(&table.insert)),
+                    ]),
+                ),
+            };
+            Json::object(vec![("span", span_json(stmt.span)), kind])
+        })
+        .collect();
+
+    let expr_arena = ast
+        .expr_arena
+        .iter()
+        .map(|(_, expr)| {
+            let kind = match &expr.kind {
+                ExprKind::Literal(Literals::NUMBER(value, int_type)) => {
+                    let mut fields = vec![("number", Json::str(value))];
+                    if *int_type != IntType::I64 {
+                        fields.push(("type", Json::str(int_type.name())));
+                    }
+                    ("literal", Json::object(fields))
+                }
+                ExprKind::Literal(Literals::FLOAT(value, float_type)) => (
+                    "literal",
+                    Json::object(vec![(float_type.name(), Json::str(value))]),
+                ),
+                ExprKind::Literal(Literals::STR(value)) => {
+                    ("literal", Json::object(vec![("str", Json::str(value))]))
+                }
+                ExprKind::Variable(var) => (
+                    "variable",
+                    Json::object(vec![
+                        ("identifier", Json::str(&var.identifier)),
+                        ("stack_offset", Json::Number(var.stack_offset as f64)),
+                    ]),
+                ),
+                ExprKind::Binary(op, lhs, rhs) => (
+                    "binary",
+                    Json::object(vec![
+                        ("op", Json::str(op.symbol())),
+                        ("lhs", id_json(*lhs)),
+                        ("rhs", id_json(*rhs)),
+                    ]),
+                ),
+                ExprKind::Cast(value, ty) => (
+                    "cast",
+                    Json::object(vec![
+                        ("value", id_json(*value)),
+                        ("type", Json::str(ty.name())),
+                    ]),
+                ),
+            };
+            Json::object(vec![("span", span_json(expr.span)), kind])
+        })
+        .collect();
+
+    Json::object(vec![
+        ("items", Json::Array(items)),
+        ("item_arena", Json::Array(item_arena)),
+        ("stmt_arena", Json::Array(stmt_arena)),
+        ("expr_arena", Json::Array(expr_arena)),
+    ])
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, String> {
+    json.get(key)
+        .ok_or_else(|| format!("missing field '{key}'"))
+}
+
+fn array<'a>(json: &'a Json, key: &str) -> Result<&'a [Json], String> {
+    field(json, key)?
+        .as_array()
+        .ok_or_else(|| format!("'{key}' is not an array"))
+}
+
+fn string(json: &Json, key: &str) -> Result<String, String> {
+    field(json, key)?
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| format!("'{key}' is not a string"))
+}
+
+fn number(json: &Json, key: &str) -> Result<u64, String> {
+    field(json, key)?
+        .as_u64()
+        .ok_or_else(|| format!("'{key}' is not a positive integer"))
+}
+
+fn stack_offset(json: &Json) -> Result<u16, String> {
+    u16::try_from(number(json, "stack_offset")?)
+        .map_err(|_| String::from("'stack_offset' is not a valid u16"))
+}
+
+/// Reads an id and checks it points into an arena of `len` nodes.
+fn id<T>(json: &Json, len: usize) -> Result<Id<T>, String> {
+    json.as_u64()
+        .map(|index| index as usize)
+        .filter(|index| *index < len)
+        .map(Id::new)
+        .ok_or_else(|| format!("{json} is not a valid node id"))
+}
+
+fn span(json: &Json) -> Result<Span, String> {
+    match array(json, "span")? {
+        [start, len] => match (start.as_u64(), len.as_u64()) {
+            (Some(start), Some(len)) => Ok(Span {
+                start: start as usize,
+                len: len as usize,
+            }),
+            _ => Err(String::from("'span' is not a [start, len] pair")),
+        },
+        _ => Err(String::from("'span' is not a [start, len] pair")),
+    }
+}
+
+fn record_from_json(record: &Json) -> Result<RecordDecl, String> {
+    let mut fields = vec![];
+    for field in array(record, "fields")? {
+        let ty = match (field.get("type"), field.get("record")) {
+            (Some(_), _) => {
+                let ty = string(field, "type")?;
+                let scalar =
+                    Type::from_field_name(&ty).ok_or_else(|| format!("unknown type {ty}"))?;
+                Some(FieldType::Scalar(scalar))
+            }
+            (None, Some(_)) => Some(FieldType::Record(string(field, "record")?)),
+            (None, None) => None,
+        };
+        fields.push(FieldDecl {
+            name: string(field, "name")?,
+            ty,
+            span: span(field)?,
+        });
+    }
+    Ok(RecordDecl {
+        name: string(record, "name")?,
+        fields,
+    })
+}
+
+fn insert_from_json(insert: &Json, exprs: usize) -> Result<InsertStmt, String> {
+    let mut rows = vec![];
+    for row in array(insert, "rows")? {
+        let values = row
+            .as_array()
+            .ok_or("'rows' holds a row which is not an array")?;
+        let values = values
+            .iter()
+            .map(|value| id(value, exprs))
+            .collect::<Result<_, _>>()?;
+        rows.push(values);
+    }
+    Ok(InsertStmt {
+        collection: string(insert, "collection")?,
+        record: string(insert, "record")?,
+        rows,
+    })
+}
+
+fn field_ref_from_json(field: &Json) -> Result<FieldRef, String> {
+    Ok(FieldRef {
+        word: string(field, "word")?,
+        span: span(field)?,
+    })
+}
+
+fn join_from_json(join: &Json) -> Result<Join, String> {
+    let on = match join.get("on") {
+        Some(_) => match array(join, "on")? {
+            [lhs, rhs] => Some((field_ref_from_json(lhs)?, field_ref_from_json(rhs)?)),
+            _ => return Err(String::from("'on' is not a pair of fields")),
+        },
+        None => None,
+    };
+    Ok(Join {
+        collection: string(join, "collection")?,
+        record: string(join, "record")?,
+        on,
+        span: span(join)?,
+    })
+}
+
+fn filter_from_json(filter: &Json, exprs: usize) -> Result<Filter, String> {
+    if let Some(test) = filter.get("test") {
+        let symbol = string(test, "predicate")?;
+        return Ok(Filter::Test(Test {
+            field: field_ref_from_json(field(test, "field")?)?,
+            predicate: Predicate::from_symbol(&symbol)
+                .ok_or_else(|| format!("unknown predicate {symbol}"))?,
+            value: id(field(test, "value")?, exprs)?,
+        }));
+    }
+
+    let key = if filter.get("and").is_some() {
+        "and"
+    } else {
+        "or"
+    };
+    let [lhs, rhs] = array(filter, key)? else {
+        return Err(format!("'{key}' needs two filters"));
+    };
+    let (lhs, rhs) = (
+        Box::new(filter_from_json(lhs, exprs)?),
+        Box::new(filter_from_json(rhs, exprs)?),
+    );
+    Ok(match key {
+        "and" => Filter::And(lhs, rhs),
+        _ => Filter::Or(lhs, rhs),
+    })
+}
+
+fn aggregate_from_json(aggregate: &Json) -> Result<Aggregate, String> {
+    let function = string(aggregate, "function")?;
+    Ok(Aggregate {
+        function: Function::from_word(&function)
+            .ok_or_else(|| format!("unknown function {function}"))?,
+        field: match aggregate.get("field") {
+            Some(field) => Some(field_ref_from_json(field)?),
+            None => None,
+        },
+        span: span(aggregate)?,
+    })
+}
+
+fn query_from_json(query: &Json, exprs: usize) -> Result<Query, String> {
+    let fields = array(query, "fields")?
+        .iter()
+        .map(|field| match field.get("aggregate") {
+            Some(aggregate) => aggregate_from_json(aggregate).map(Projection::Aggregate),
+            None => field_ref_from_json(field).map(Projection::Field),
+        })
+        .collect::<Result<_, _>>()?;
+    let joins = match query.get("joins") {
+        Some(_) => array(query, "joins")?
+            .iter()
+            .map(join_from_json)
+            .collect::<Result<_, _>>()?,
+        None => vec![],
+    };
+    let with = match query.get("with") {
+        Some(with) => Some(aggregate_from_json(with)?),
+        None => None,
+    };
+    let group = match query.get("group") {
+        Some(group) => Some(field_ref_from_json(group)?),
+        None => None,
+    };
+    let sort = match query.get("sort") {
+        Some(sort) => {
+            let order = match sort.get("order") {
+                Some(_) => {
+                    let order = string(sort, "order")?;
+                    Some(Order::from_name(&order).ok_or_else(|| format!("unknown order {order}"))?)
+                }
+                None => None,
+            };
+            Some(Sort {
+                field: field_ref_from_json(field(sort, "field")?)?,
+                order,
+            })
+        }
+        None => None,
+    };
+    let filter = match query.get("filter") {
+        Some(filter) => Some(filter_from_json(filter, exprs)?),
+        None => None,
+    };
+    Ok(Query {
+        fields,
+        collection: string(query, "collection")?,
+        record: string(query, "record")?,
+        joins,
+        with,
+        filter,
+        group,
+        sort,
+    })
+}
+
+pub fn from_json(json: &Json) -> Result<AST, String> {
+    let mut ast = AST::new();
+
+    let items = array(json, "items")?;
+    let item_arena = array(json, "item_arena")?;
+    let stmt_arena = array(json, "stmt_arena")?;
+    let expr_arena = array(json, "expr_arena")?;
+
+    for expr in expr_arena {
+        let kind = if let Some(literal) = expr.get("literal") {
+            if let Some(value) = literal.get("number").and_then(Json::as_str) {
+                let int_type = match literal.get("type").and_then(Json::as_str) {
+                    Some(name) => IntType::from_suffix(name)
+                        .ok_or_else(|| format!("Unknown integer type {name}"))?,
+                    None => IntType::I64,
+                };
+                ExprKind::Literal(Literals::NUMBER(value.to_string(), int_type))
+            } else if let Some(value) = literal.get("f64").and_then(Json::as_str) {
+                ExprKind::Literal(Literals::FLOAT(value.to_string(), FloatType::F64))
+            } else if let Some(value) = literal.get("f32").and_then(Json::as_str) {
+                ExprKind::Literal(Literals::FLOAT(value.to_string(), FloatType::F32))
+            } else if let Some(value) = literal.get("str").and_then(Json::as_str) {
+                ExprKind::Literal(Literals::STR(value.to_string()))
+            } else {
+                return Err(String::from("unknown literal"));
+            }
+        } else if let Some(var) = expr.get("variable") {
+            ExprKind::Variable(VariableRef {
+                identifier: string(var, "identifier")?,
+                stack_offset: stack_offset(var)?,
+            })
+        } else if let Some(binary) = expr.get("binary") {
+            let op = string(binary, "op")?;
+            let op = BinaryOp::from_symbol(&op).ok_or_else(|| format!("unknown operator {op}"))?;
+            ExprKind::Binary(
+                op,
+                id(field(binary, "lhs")?, expr_arena.len())?,
+                id(field(binary, "rhs")?, expr_arena.len())?,
+            )
+        } else if let Some(cast) = expr.get("cast") {
+            let ty = string(cast, "type")?;
+            let ty = Type::from_name(&ty).ok_or_else(|| format!("unknown type {ty}"))?;
+            ExprKind::Cast(id(field(cast, "value")?, expr_arena.len())?, ty)
+        } else {
+            return Err(String::from("unknown expression"));
+        };
+
+        ast.expr_arena.alloc(Expr {
+            kind,
+            span: span(expr)?,
+        });
+    }
+
+    for stmt in stmt_arena {
+        let kind = if let Some(decl) = stmt.get("let") {
+            StmtKind::Let(VariableDecl {
+                stack_offset: stack_offset(decl)?,
+                identifier: string(decl, "identifier")?,
+                value: id(field(decl, "value")?, expr_arena.len())?,
+                doc: decl.get("doc").and_then(Json::as_str).map(String::from),
+            })
+        } else if let Some(block) = stmt.get("block") {
+            let scope = number(block, "scope")? as usize;
+            let mut compound = CompoundStmt::new(scope, stack_offset(block)?);
+            for child in array(block, "children")? {
+                compound.children.push(id(child, stmt_arena.len())?);
+            }
+            StmtKind::Block(compound)
+        } else if let Some(expr) = stmt.get("expr") {
+            StmtKind::Expr(id(expr, expr_arena.len())?)
+        } else if let Some(insert) = stmt.get("insert") {
+            StmtKind::Insert(insert_from_json(insert, expr_arena.len())?)
+        } else if let Some(record) = stmt.get("record") {
+            StmtKind::Record(record_from_json(record)?)
+        } else if let Some(query) = stmt.get("query") {
+            StmtKind::Query(query_from_json(query, expr_arena.len())?)
+        } else if let Some(load) = stmt.get("load") {
+            StmtKind::Load(LoadStmt {
+                path: string(load, "path")?,
+                collection: string(load, "collection")?,
+                record: string(load, "record")?,
+            })
+        } else if let Some(save) = stmt.get("save") {
+            StmtKind::Save(SaveStmt {
+                query: query_from_json(field(save, "query")?, expr_arena.len())?,
+                path: string(save, "path")?,
+            })
+        } else if let Some(table) = stmt.get("table") {
+            StmtKind::Table(TableDecl {
+                record: record_from_json(field(table, "record")?)?,
+                insert: insert_from_json(field(table, "insert")?, expr_arena.len())?,
+            })
+        } else {
+            return Err(String::from("unknown statement"));
+        };
+
+        ast.stmt_arena.alloc(Stmt {
+            kind,
+            span: span(stmt)?,
+        });
+    }
+
+    for item in item_arena {
+        let stmt = id(field(item, "stmt")?, stmt_arena.len())?;
+        ast.item_arena.alloc(Item::Stmt(stmt));
+    }
+
+    for item in items {
+        ast.items.push(id(item, item_arena.len())?);
+    }
+
+    Ok(ast)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::ast::AST;
+    use crate::json::Json;
+    use crate::parser::Parser;
+    use crate::symbols::CodeScope;
+    use crate::tokenizer::Tokenizer;
+
+    use super::{from_json, sexp, to_json, tree};
+
+    fn parse(text: &str) -> AST {
+        let mut parser = Parser::new(CodeScope::global());
+        parser.parse(Tokenizer::new(text)).unwrap()
+    }
+
+    #[test]
+    fn tree_nests_children() {
+        let ast = parse("let x = 123;\n{ let y = x * 2 + 1; }\n");
+
+        let expected = [
+            "Program",
+            "├── Let x offset=0",
+            "│   └── Number 123",
+            "└── Block scope=1 offset=16",
+            "    └── Let y offset=16",
+            "        └── Binary +",
+            "            ├── Binary *",
+            "            │   ├── Variable x offset=0",
+            "            │   └── Number 2",
+            "            └── Number 1",
+            "",
+        ];
+
+        assert_eq!(tree(&ast), expected.join("\n"));
+    }
+
+    #[test]
+    fn sexp_nests_children() {
+        let ast = parse("let x = 123;\n{ let y = x * 2 + 1; }\n");
+
+        let expected = [
+            "(program",
+            "  (let x :offset 0 (number 123))",
+            "  (block :scope 1 :offset 16 (let y :offset 16 (+ (* (var x :offset 0) (number 2)) (number 1)))))",
+            "",
+        ];
+
+        assert_eq!(sexp(&ast), expected.join("\n"));
+    }
+
+    #[test]
+    fn json_round_trips() {
+        for sample in [
+            "samples/main.x",
+            "samples/simple.x",
+            "samples/over_rust.x",
+            "samples/careers.x",
+        ] {
+            let source = fs::read_to_string(sample).unwrap();
+            let ast = parse(&source);
+
+            let text = to_json(&ast).pretty();
+            let parsed = from_json(&Json::parse(&text).unwrap()).unwrap();
+
+            assert_eq!(parsed, ast);
+        }
+
+        let ast = parse(
+            "Career: Name Wage: f32\nPerson: Name: str Career\nPersons -> Ola Intern 0.5, Ala CTO 9.0\n",
+        );
+        let text = to_json(&ast).pretty();
+        assert_eq!(from_json(&Json::parse(&text).unwrap()).unwrap(), ast);
+        assert_eq!(
+            sexp(&ast),
+            "(program\n  (record Career (field Name) (field Wage f32))\n  (record Person (field Name str) (field Career :record Career))\n  (insert Persons :record Person (row (str \"Ola\") (str \"Intern\") (f64 0.5)) (row (str \"Ala\") (str \"CTO\") (f64 9.0))))\n"
+        );
+
+        let ast = parse(
+            "Boxes:\nSize: i64 Label\n2   small\n\nget labels from boxes whose size > 1 and (label is big or label contains a)\n",
+        );
+        let text = to_json(&ast).pretty();
+        assert_eq!(from_json(&Json::parse(&text).unwrap()).unwrap(), ast);
+        assert!(sexp(&ast).ends_with("(where (and (> size (number 1)) (or (= label (str \"big\")) (contains label (str \"a\")))))))\n"));
+
+        let ast = parse("Boxes:\nSize: i64 Label\n2   small\n");
+        let text = to_json(&ast).pretty();
+        assert_eq!(from_json(&Json::parse(&text).unwrap()).unwrap(), ast);
+        assert_eq!(
+            sexp(&ast),
+            "(program\n  (table Boxes :record Box (field Size i64) (field Label) (row (number 2) (str \"small\"))))\n"
+        );
+
+        let ast = parse("load \"boxes.tsv\" as Boxes\nsave labels from boxes to \"labels.csv\"\n");
+        let text = to_json(&ast).pretty();
+        assert_eq!(from_json(&Json::parse(&text).unwrap()).unwrap(), ast);
+        assert_eq!(
+            sexp(&ast),
+            "(program\n  (load Boxes :record Box :path \"boxes.tsv\")\n  (save :path \"labels.csv\" (query Boxes :record Box (get labels))))\n"
+        );
+    }
+
+    #[test]
+    fn json_rejects_dangling_ids() {
+        let json = Json::parse(
+            r#"{"items": [0], "item_arena": [{"stmt": 3}], "stmt_arena": [], "expr_arena": []}"#,
+        )
+        .unwrap();
+
+        assert!(from_json(&json).is_err());
+    }
+}
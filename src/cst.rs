@@ -0,0 +1,395 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::diagnostic::Diagnostic;
+use crate::tokenizer::{LosslessToken, TokenType, Tokenizer, Trivia, TriviaKind};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    Program,
+    /// `{ ... }`, braces included
+    Block,
+    /// Tokens up to and including `;`, or up to a brace or a line starting another statement
+    Statement,
+    /// `Name: Field Field` up to the end of the line
+    Record,
+    /// `Collection -> values, values` up to the end of a line not ending with `,`
+    Insert,
+    /// `Collection:`, then a header line and a row per line up to a blank line
+    Table,
+    /// `get`, `save` or `load` up to the end of the line. The lines right under it, up to
+    /// a blank line, are what it prints, as the samples show it
+    Query,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element<'src> {
+    Node(Node<'src>),
+    Token(LosslessToken<'src>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node<'src> {
+    pub kind: NodeKind,
+    pub children: Vec<Element<'src>>,
+}
+
+/// Concrete syntax tree which keeps every byte of the source, so printing it gives
+/// back the input unchanged.
+/// Only braces, `;` and the line breaks ending the statements written line by line shape
+/// the tree, which lets it hold sources the parser rejects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cst<'src> {
+    pub root: Node<'src>,
+    /// Trivia after the last token
+    pub rest: Vec<Trivia<'src>>,
+}
+
+type Tokens<'src> = VecDeque<LosslessToken<'src>>;
+
+impl<'src> Cst<'src> {
+    pub fn parse(source: &'src str) -> Result<Self, Diagnostic> {
+        let mut lossless = Tokenizer::new(source).lossless();
+        let tokens: Vec<LosslessToken> = lossless.by_ref().collect::<Result<_, _>>()?;
+        let children = items(&mut tokens.into(), false);
+
+        Ok(Cst {
+            root: Node {
+                kind: NodeKind::Program,
+                children,
+            },
+            rest: lossless.rest().to_vec(),
+        })
+    }
+
+    /// Indented tree of nodes and tokens with their trivia.
+    pub fn dump(&self) -> String {
+        let mut text = String::new();
+        dump_node(&self.root, 0, &mut text);
+        if !self.rest.is_empty() {
+            text += &format!("rest {:?}\n", trivia_text(&self.rest));
+        }
+        text
+    }
+}
+
+/// Line breaks between a token and the one before it.
+pub fn line_breaks(token: &LosslessToken) -> usize {
+    token
+        .leading
+        .iter()
+        .filter(|trivia| trivia.kind == TriviaKind::Newline)
+        .count()
+}
+
+fn is_brace(token: &LosslessToken) -> bool {
+    matches!(
+        token.token.token_type,
+        TokenType::LeftBrace | TokenType::RightBrace
+    )
+}
+
+/// Kind of the statement starting at the next token, told apart by its first tokens as
+/// the parser does.
+fn statement_kind(tokens: &Tokens) -> NodeKind {
+    let (Some(first), Some(second)) = (tokens.front(), tokens.get(1)) else {
+        return NodeKind::Statement;
+    };
+    let same_line = line_breaks(second) == 0;
+
+    match (&first.token.token_type, &second.token.token_type) {
+        (TokenType::IDENT(_), TokenType::COLON) => match tokens.get(2) {
+            Some(third) if line_breaks(third) > 0 => NodeKind::Table,
+            _ => NodeKind::Record,
+        },
+        (TokenType::IDENT(_), TokenType::ARROW) => NodeKind::Insert,
+        (TokenType::IDENT(word), TokenType::IDENT(_))
+            if same_line
+                && (word.eq_ignore_ascii_case("get") || word.eq_ignore_ascii_case("save")) =>
+        {
+            NodeKind::Query
+        }
+        (TokenType::IDENT(word), TokenType::STRING(_))
+            if same_line && word.eq_ignore_ascii_case("load") =>
+        {
+            NodeKind::Query
+        }
+        _ => NodeKind::Statement,
+    }
+}
+
+fn items<'src>(tokens: &mut Tokens<'src>, in_block: bool) -> Vec<Element<'src>> {
+    let mut children = Vec::new();
+
+    while let Some(token) = tokens.front() {
+        match token.token.token_type {
+            TokenType::RightBrace if in_block => break,
+            // Unmatched, kept on its own
+            TokenType::RightBrace => children.push(Element::Token(tokens.pop_front().unwrap())),
+            TokenType::LeftBrace => children.push(Element::Node(block(tokens))),
+            _ => children.push(Element::Node(statement(tokens))),
+        }
+    }
+
+    children
+}
+
+fn block<'src>(tokens: &mut Tokens<'src>) -> Node<'src> {
+    let mut children = vec![Element::Token(tokens.pop_front().unwrap())];
+    children.extend(items(tokens, true));
+    // A block left open runs to the end of the file
+    if let Some(closing) = tokens.pop_front() {
+        children.push(Element::Token(closing));
+    }
+
+    Node {
+        kind: NodeKind::Block,
+        children,
+    }
+}
+
+fn statement<'src>(tokens: &mut Tokens<'src>) -> Node<'src> {
+    let kind = statement_kind(tokens);
+    let mut children = Vec::new();
+    let mut last: Option<TokenType> = None;
+
+    while let Some(token) = tokens.front() {
+        let breaks = line_breaks(token);
+        let continues = match kind {
+            _ if is_brace(token) || last == Some(TokenType::SEMICOLON) => false,
+            _ if children.is_empty() => true,
+            NodeKind::Statement => breaks == 0 || !starts_statement(tokens, &last),
+            NodeKind::Record => breaks == 0,
+            NodeKind::Insert => breaks == 0 || last == Some(TokenType::COMMA),
+            NodeKind::Table => {
+                breaks == 0 || (breaks == 1 && token.token.token_type != TokenType::SEMICOLON)
+            }
+            NodeKind::Query => breaks == 0 || (breaks == 1 && is_printed(tokens)),
+            NodeKind::Program | NodeKind::Block => unreachable!("Not a statement"),
+        };
+        if !continues {
+            break;
+        }
+
+        let token = tokens.pop_front().unwrap();
+        last = Some(token.token.token_type.clone());
+        children.push(Element::Token(token));
+    }
+
+    Node { kind, children }
+}
+
+/// Whether the next token, first on its line, starts a statement rather than continuing the
+/// one ending with `last`. Doc comments are continued by the `let` they document.
+fn starts_statement(tokens: &Tokens, last: &Option<TokenType>) -> bool {
+    let starts_let = tokens.front().is_some_and(|token| {
+        matches!(
+            token.token.token_type,
+            TokenType::LET | TokenType::COMMENT(_)
+        )
+    });
+    statement_kind(tokens) != NodeKind::Statement
+        || (starts_let && !matches!(last, Some(TokenType::COMMENT(_))))
+}
+
+/// Whether the line starting at the next token is printed by the query above, rather than
+/// a statement of its own.
+fn is_printed(tokens: &Tokens) -> bool {
+    tokens.front().is_some_and(|token| {
+        matches!(
+            token.token.token_type,
+            TokenType::IDENT(_)
+                | TokenType::NUMBER(..)
+                | TokenType::FLOAT(..)
+                | TokenType::STRING(_)
+                | TokenType::MINUS
+        )
+    }) && statement_kind(tokens) == NodeKind::Statement
+}
+
+fn trivia_text(trivia: &[Trivia]) -> String {
+    trivia.iter().map(|trivia| trivia.text).collect()
+}
+
+fn dump_node(node: &Node, depth: usize, text: &mut String) {
+    *text += &format!("{}{:?}\n", "  ".repeat(depth), node.kind);
+
+    for child in &node.children {
+        match child {
+            Element::Node(node) => dump_node(node, depth + 1, text),
+            Element::Token(token) => {
+                *text += &format!("{}{:?}", "  ".repeat(depth + 1), token.token.token_type);
+                if !token.leading.is_empty() {
+                    *text += &format!(" leading {:?}", trivia_text(&token.leading));
+                }
+                if !token.trailing.is_empty() {
+                    *text += &format!(" trailing {:?}", trivia_text(&token.trailing));
+                }
+                text.push('\n');
+            }
+        }
+    }
+}
+
+impl fmt::Display for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                Element::Node(node) => write!(f, "{node}")?,
+                Element::Token(token) => {
+                    for trivia in &token.leading {
+                        write!(f, "{}", trivia.text)?;
+                    }
+                    write!(f, "{}", token.text)?;
+                    for trivia in &token.trailing {
+                        write!(f, "{}", trivia.text)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints the source back, byte for byte.
+impl fmt::Display for Cst<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.root)?;
+        for trivia in &self.rest {
+            write!(f, "{}", trivia.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::tokenizer::{TokenType, TriviaKind};
+
+    use super::{Cst, Element, NodeKind};
+
+    #[test]
+    fn round_trips_every_sample() {
+        let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples");
+        let mut count = 0;
+
+        for entry in fs::read_dir(samples).unwrap() {
+            let path = entry.unwrap().path();
+            let source = fs::read_to_string(&path).unwrap();
+
+            assert_eq!(Cst::parse(&source).unwrap().to_string(), source, "{path:?}");
+            count += 1;
+        }
+
+        assert!(count >= 4);
+    }
+
+    #[test]
+    fn round_trips_unusual_trivia() {
+        let sources = [
+            "",
+            "   \n\n",
+            "let a = 1;",
+            "let a = 1; # trailing\r\n\t#[ block\n #[ nested ]# ]#let b = 2;  \n",
+            "## doc\nlet a = 1 # no semicolon",
+            "{ { let a = 1; }",
+            "} let a = 1; }}",
+            "let s = \"  # not a comment \";",
+        ];
+
+        for source in sources {
+            assert_eq!(Cst::parse(source).unwrap().to_string(), source);
+        }
+    }
+
+    #[test]
+    fn attaches_trivia_up_to_the_end_of_line() {
+        let cst = Cst::parse("# header\n\nlet a = 1; # one\n  let b = 2;\n").unwrap();
+
+        let Element::Node(first) = &cst.root.children[0] else {
+            panic!("Expected statement");
+        };
+        let Element::Token(keyword) = &first.children[0] else {
+            panic!("Expected token");
+        };
+        let Element::Token(semicolon) = first.children.last().unwrap() else {
+            panic!("Expected token");
+        };
+
+        assert_eq!(keyword.token.token_type, TokenType::LET);
+        let kinds: Vec<TriviaKind> = keyword.leading.iter().map(|trivia| trivia.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                TriviaKind::Comment,
+                TriviaKind::Newline,
+                TriviaKind::Newline
+            ]
+        );
+        assert_eq!(semicolon.trailing[1].text, "# one");
+        assert_eq!(semicolon.trailing.len(), 2);
+        assert_eq!(cst.rest[0].kind, TriviaKind::Newline);
+    }
+
+    #[test]
+    fn ends_line_statements_with_their_line() {
+        let source = "Career: Name Wage\nCareers -> Driver 2400,\n  Architect 5000\nlet a = 1\nCountries:\nName GDP\nPoland 1000\n\nget names from countries\nPoland\n\nsave names from careers to \"out.csv\";\n";
+        let cst = Cst::parse(source).unwrap();
+
+        let statements: Vec<(NodeKind, usize)> = cst
+            .root
+            .children
+            .iter()
+            .map(|child| match child {
+                Element::Node(node) => (node.kind, node.children.len()),
+                Element::Token(_) => panic!("Expected statement"),
+            })
+            .collect();
+        assert_eq!(
+            statements,
+            [
+                (NodeKind::Record, 4),
+                (NodeKind::Insert, 7),
+                (NodeKind::Statement, 4),
+                (NodeKind::Table, 6),
+                (NodeKind::Query, 5),
+                (NodeKind::Query, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn nests_blocks_and_statements() {
+        let cst = Cst::parse("{\n    let y = 2;\n    { let c = 10; }\n}\n").unwrap();
+
+        assert_eq!(
+            cst.dump(),
+            "\
+Program
+  Block
+    LeftBrace
+    Statement
+      LET leading \"\\n    \" trailing \" \"
+      IDENT(\"y\") trailing \" \"
+      EQUAL trailing \" \"
+      NUMBER(2, None)
+      SEMICOLON
+    Block
+      LeftBrace leading \"\\n    \" trailing \" \"
+      Statement
+        LET trailing \" \"
+        IDENT(\"c\") trailing \" \"
+        EQUAL trailing \" \"
+        NUMBER(10, None)
+        SEMICOLON trailing \" \"
+      RightBrace
+    RightBrace leading \"\\n\"
+rest \"\\n\"
+"
+        );
+        assert_eq!(cst.root.kind, NodeKind::Program);
+    }
+}
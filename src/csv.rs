@@ -0,0 +1,518 @@
+use std::fs;
+
+use crate::{
+    declarations::{FieldDecl, FieldType, Literals, RecordDecl, Type},
+    tokenizer::{FloatType, IntType, Span},
+    unicode::{is_ident_continue, is_ident_start, nfc},
+};
+
+/// Comma separated values quoted as in RFC 4180, or the same with tabs for `.tsv` files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Tsv,
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Self {
+        let path = path.to_lowercase();
+        if path.ends_with(".tsv") || path.ends_with(".tab") {
+            Format::Tsv
+        } else {
+            Format::Csv
+        }
+    }
+
+    fn separator(self) -> char {
+        match self {
+            Format::Csv => ',',
+            Format::Tsv => '\t',
+        }
+    }
+
+    fn line_end(self) -> &'static str {
+        match self {
+            Format::Csv => "\r\n",
+            Format::Tsv => "\n",
+        }
+    }
+}
+
+/// Value of a line as written. Quoted values are text, like string literals, even when
+/// they read as numbers.
+#[derive(Debug, PartialEq)]
+struct Cell {
+    text: String,
+    quoted: bool,
+}
+
+/// Rows of a file with the record type its header declares. Columns take the type of
+/// their first value, as in a table: whole numbers are i64, other numbers f64 and
+/// anything else text. Quoted values are typed the same, unless a quoted value of their
+/// column is text. A header such as `Zip: str` declares the type of its column instead.
+#[derive(Debug, PartialEq)]
+pub struct Sheet {
+    pub record: RecordDecl,
+    pub rows: Vec<Vec<Literals>>,
+}
+
+/// Reads the rows of record `record` from a local file. Its fields are declared at `span`.
+pub fn load(path: &str, record: &str, span: Span) -> Result<Sheet, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("Cannot read {path}: {err}"))?;
+    read(&text, Format::from_path(path), record, span)
+        .map_err(|message| format!("{path} {message}"))
+}
+
+/// Writes a header and rows to a local file.
+pub fn save(path: &str, header: &[String], rows: &[Vec<Literals>]) -> Result<(), String> {
+    let text = write(header, rows, Format::from_path(path));
+    fs::write(path, text).map_err(|err| format!("Cannot write {path}: {err}"))
+}
+
+fn read(text: &str, format: Format, record: &str, span: Span) -> Result<Sheet, String> {
+    let mut lines = parse(text, format)?.into_iter();
+    let Some((line, header)) = lines.next() else {
+        return Err(String::from("has no header line"));
+    };
+
+    let mut names: Vec<String> = vec![];
+    let mut declared: Vec<Option<Type>> = vec![];
+    for (index, cell) in header.iter().enumerate() {
+        let (text, ty) = declared_type(&cell.text);
+        declared.push(ty);
+        let name = field_name(text, index);
+        // Queries name fields in any case
+        if names
+            .iter()
+            .any(|other| other.to_lowercase() == name.to_lowercase())
+        {
+            return Err(format!("line {line}: column {name} appears twice"));
+        }
+        names.push(name);
+    }
+
+    let lines: Vec<(usize, Vec<Cell>)> = lines.collect();
+    for (line, cells) in &lines {
+        if cells.len() != names.len() {
+            return Err(format!(
+                "line {line}: {record} needs {} values ({}), found {}",
+                names.len(),
+                names.join(" "),
+                cells.len()
+            ));
+        }
+    }
+    // Quoted numbers are text next to quoted text, as written by `save`
+    let texts: Vec<bool> = (0..names.len())
+        .map(|index| {
+            lines.iter().any(|(_, cells)| {
+                cells[index].quoted && matches!(literal(&cells[index]), Literals::STR(_))
+            })
+        })
+        .collect();
+
+    let mut types = declared.clone();
+    let mut rows = vec![];
+    for (line, cells) in lines {
+        let row: Vec<Literals> = cells
+            .iter()
+            .zip(&texts)
+            .zip(&declared)
+            .map(|((cell, text), ty)| match (ty, literal(cell)) {
+                (Some(Type::Str), _) if cell.quoted => Literals::STR(cell.text.clone()),
+                (Some(Type::Str), _) => Literals::STR(cell.text.trim().to_string()),
+                (Some(Type::Float(FloatType::F32)), Literals::FLOAT(value, _)) => {
+                    Literals::FLOAT(value, FloatType::F32)
+                }
+                _ if cell.quoted && *text => Literals::STR(cell.text.clone()),
+                (_, value) => value,
+            })
+            .collect();
+        for ((ty, value), name) in types.iter_mut().zip(&row).zip(&names) {
+            let found = literal_type(value);
+            match ty {
+                None => *ty = Some(found),
+                Some(expected) if *expected != found => {
+                    return Err(format!(
+                        "line {line}: {record}.{name} is {}, found {}",
+                        expected.name(),
+                        found.name()
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        rows.push(row);
+    }
+
+    let fields = names
+        .into_iter()
+        .zip(types)
+        .map(|(name, ty)| FieldDecl {
+            name,
+            ty: ty.map(FieldType::Scalar),
+            span,
+        })
+        .collect();
+    Ok(Sheet {
+        record: RecordDecl {
+            name: record.to_string(),
+            fields,
+        },
+        rows,
+    })
+}
+
+/// Values of every line that is not blank, with the number of the line it starts on.
+/// Quoted values may hold separators, line breaks and quotes written twice.
+fn parse(text: &str, format: Format) -> Result<Vec<(usize, Vec<Cell>)>, String> {
+    let separator = format.separator();
+    let mut chars = text.chars().peekable();
+    let mut rows = vec![];
+    let mut row = vec![];
+    let (mut line, mut row_line) = (1, 1);
+
+    loop {
+        let mut cell = Cell {
+            text: String::new(),
+            quoted: false,
+        };
+        if chars.next_if_eq(&'"').is_some() {
+            cell.quoted = true;
+            let opened = line;
+            loop {
+                match chars.next() {
+                    None => return Err(format!("line {opened}: the quote is never closed")),
+                    Some('"') if chars.next_if_eq(&'"').is_some() => cell.text.push('"'),
+                    Some('"') => break,
+                    Some(c) => {
+                        if c == '\n' {
+                            line += 1;
+                        }
+                        cell.text.push(c);
+                    }
+                }
+            }
+            if let Some(c) = chars
+                .peek()
+                .filter(|c| !matches!(c, '\r' | '\n') && **c != separator)
+            {
+                return Err(format!(
+                    "line {line}: expected {separator:?} after the closing quote, found {c:?}"
+                ));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !matches!(c, '\r' | '\n') && *c != separator) {
+                cell.text.push(c);
+            }
+        }
+        row.push(cell);
+
+        match chars.next() {
+            Some(c) if c == separator => continue,
+            Some('\r') => {
+                chars.next_if_eq(&'\n');
+            }
+            _ => {}
+        }
+        let blank = matches!(row.as_slice(), [cell] if !cell.quoted && cell.text.trim().is_empty());
+        let cells = std::mem::take(&mut row);
+        if !blank {
+            rows.push((row_line, cells));
+        }
+        if chars.peek().is_none() {
+            return Ok(rows);
+        }
+        line += 1;
+        row_line = line;
+    }
+}
+
+/// Header text without the type it declares, if any.
+fn declared_type(text: &str) -> (&str, Option<Type>) {
+    match text.rsplit_once(':') {
+        Some((name, ty)) => match Type::from_field_name(ty.trim()) {
+            Some(ty) => (name, Some(ty)),
+            None => (text, None),
+        },
+        None => (text, None),
+    }
+}
+
+/// A line of values, quoted when they hold a separator, a quote or a line break, and when
+/// text would read as a number or a blank line otherwise. Columns of such text are
+/// declared `str` in the header, so they load as text again.
+fn write(header: &[String], rows: &[Vec<Literals>], format: Format) -> String {
+    let header: Vec<Literals> = header
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let numeric = rows.iter().any(|row| match &row[index] {
+                Literals::STR(text) => reads_as_number(text),
+                _ => false,
+            });
+            if numeric {
+                Literals::STR(format!("{name}: str"))
+            } else {
+                Literals::STR(name.clone())
+            }
+        })
+        .collect();
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(rows) {
+        let values: Vec<String> = row.iter().map(|value| write_value(value, format)).collect();
+        out += &values.join(&format.separator().to_string());
+        out += format.line_end();
+    }
+    out
+}
+
+fn write_value(value: &Literals, format: Format) -> String {
+    let text = match value {
+        Literals::NUMBER(value, _) | Literals::FLOAT(value, _) => return value.clone(),
+        Literals::STR(text) => text,
+    };
+    let quoted = text.trim().is_empty()
+        || text.trim() != text
+        || text.contains(['"', '\r', '\n', format.separator()])
+        || reads_as_number(text);
+    if quoted {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.clone()
+    }
+}
+
+/// Header text as a field name, anything but letters, digits and `_` replaced by `_`:
+/// `GDP per capita` becomes `GDP_per_capita`. Columns without a name are numbered.
+fn field_name(text: &str, index: usize) -> String {
+    let mut name = String::new();
+    for c in nfc(text.trim()).chars() {
+        if is_ident_continue(c) {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+
+    let name = name.trim_matches('_');
+    match name.chars().next() {
+        None => format!("Column{}", index + 1),
+        Some(first) if !is_ident_start(first) => format!("_{name}"),
+        Some(_) => name.to_string(),
+    }
+}
+
+fn reads_as_number(text: &str) -> bool {
+    let plain = Cell {
+        text: text.to_string(),
+        quoted: false,
+    };
+    !matches!(literal(&plain), Literals::STR(_))
+}
+
+/// The value of a cell, its text kept as it is when quoted.
+fn literal(cell: &Cell) -> Literals {
+    let text = cell.text.trim();
+    if let Ok(value) = text.parse::<i64>() {
+        return Literals::NUMBER(value.to_string(), IntType::I64);
+    }
+    // Rust also reads words such as `inf` and `NaN` as floats
+    let numeric = text.chars().any(|c| c.is_ascii_digit())
+        && text
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+    match text.parse::<f64>() {
+        Ok(value) if numeric => Literals::FLOAT(format!("{value:?}"), FloatType::F64),
+        _ if cell.quoted => Literals::STR(cell.text.clone()),
+        _ => Literals::STR(text.to_string()),
+    }
+}
+
+fn literal_type(value: &Literals) -> Type {
+    match value {
+        Literals::NUMBER(_, int_type) => Type::Int(*int_type),
+        Literals::FLOAT(_, float_type) => Type::Float(*float_type),
+        Literals::STR(_) => Type::Str,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::declarations::{FieldType, Literals, Type};
+    use crate::tokenizer::{FloatType, IntType, Span};
+
+    use super::{parse, read, write, Format};
+
+    fn texts(text: &str, format: Format) -> Vec<(usize, Vec<String>)> {
+        parse(text, format)
+            .unwrap()
+            .into_iter()
+            .map(|(line, cells)| (line, cells.into_iter().map(|cell| cell.text).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn splits_quoted_values_as_in_rfc_4180() {
+        let text = "Name,Motto\r\nPoland,\"Bóg, Honor, Ojczyzna\"\r\n\r\n\"Say \"\"hi\"\"\",\"two\nlines\"\r\nCzechia,\r\n";
+
+        assert_eq!(
+            texts(text, Format::Csv),
+            [
+                (1, vec!["Name".to_string(), "Motto".to_string()]),
+                (
+                    2,
+                    vec!["Poland".to_string(), "Bóg, Honor, Ojczyzna".to_string()]
+                ),
+                (4, vec!["Say \"hi\"".to_string(), "two\nlines".to_string()]),
+                (6, vec!["Czechia".to_string(), String::new()]),
+            ]
+        );
+        assert_eq!(
+            texts("Name\tGDP\nPoland, Czechia\t1000", Format::Tsv),
+            [
+                (1, vec!["Name".to_string(), "GDP".to_string()]),
+                (2, vec!["Poland, Czechia".to_string(), "1000".to_string()]),
+            ]
+        );
+
+        assert_eq!(
+            parse("Name\n\"Poland\nCzechia", Format::Csv).unwrap_err(),
+            "line 2: the quote is never closed"
+        );
+        assert_eq!(
+            parse("Name\n\"Poland\" PL,1", Format::Csv).unwrap_err(),
+            "line 2: expected ',' after the closing quote, found ' '"
+        );
+    }
+
+    #[test]
+    fn types_columns_like_tables() {
+        let text = "Name,Code,GDP per capita,\nPoland,\"048\",1000,1.5\nCzechia,\" 7\",-2,1e3\n";
+        let sheet = read(text, Format::Csv, "Country", Span { start: 0, len: 4 }).unwrap();
+
+        let fields: Vec<(&str, Option<FieldType>)> = sheet
+            .record
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.ty.clone()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("Name", Some(FieldType::Scalar(Type::Str))),
+                ("Code", Some(FieldType::Scalar(Type::Int(IntType::I64)))),
+                (
+                    "GDP_per_capita",
+                    Some(FieldType::Scalar(Type::Int(IntType::I64)))
+                ),
+                (
+                    "Column4",
+                    Some(FieldType::Scalar(Type::Float(FloatType::F64)))
+                ),
+            ]
+        );
+        assert_eq!(
+            sheet.rows[1],
+            [
+                Literals::STR("Czechia".to_string()),
+                Literals::NUMBER("7".to_string(), IntType::I64),
+                Literals::NUMBER("-2".to_string(), IntType::I64),
+                Literals::FLOAT("1000.0".to_string(), FloatType::F64),
+            ]
+        );
+
+        let errors = [
+            ("", "has no header line"),
+            ("Name,name\n", "line 1: column name appears twice"),
+            (
+                "Name,GDP\nPoland,1000\n\nCzechia,1.5\n",
+                "line 4: Country.GDP is i64, found f64",
+            ),
+            (
+                "Name,GDP\nPoland\n",
+                "line 2: Country needs 2 values (Name GDP), found 1",
+            ),
+        ];
+        for (text, message) in errors {
+            let error = read(text, Format::Csv, "Country", Span { start: 0, len: 4 }).unwrap_err();
+            assert_eq!(error, message, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn types_fully_quoted_files() {
+        let text = "\"Name\",\"Code\",\"GDP\"\r\n\"Poland\",\"12\",\"1000.5\"\r\n\"Czechia\",\"PL\",\"-2\"\r\n";
+        let error = read(text, Format::Csv, "Country", Span::default()).unwrap_err();
+        assert_eq!(error, "line 3: Country.GDP is f64, found i64");
+
+        let text = text.replace("\"-2\"", "\"-2.0\"");
+        let sheet = read(&text, Format::Csv, "Country", Span::default()).unwrap();
+        assert_eq!(
+            sheet.rows,
+            [
+                vec![
+                    Literals::STR("Poland".to_string()),
+                    Literals::STR("12".to_string()),
+                    Literals::FLOAT("1000.5".to_string(), FloatType::F64),
+                ],
+                vec![
+                    Literals::STR("Czechia".to_string()),
+                    Literals::STR("PL".to_string()),
+                    Literals::FLOAT("-2.0".to_string(), FloatType::F64),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let header = ["Name".to_string(), "avg GDP".to_string()];
+        let rows = [
+            vec![
+                Literals::STR("Bóg, \"Honor\"".to_string()),
+                Literals::FLOAT("2.5".to_string(), FloatType::F64),
+            ],
+            vec![
+                Literals::STR("12".to_string()),
+                Literals::FLOAT("-3.0".to_string(), FloatType::F64),
+            ],
+            vec![
+                Literals::STR(String::new()),
+                Literals::FLOAT("7.5".to_string(), FloatType::F64),
+            ],
+        ];
+
+        let text = write(&header, &rows, Format::Csv);
+        assert_eq!(
+            text,
+            "Name: str,avg GDP\r\n\"Bóg, \"\"Honor\"\"\",2.5\r\n\"12\",-3.0\r\n\"\",7.5\r\n"
+        );
+        let sheet = read(&text, Format::Csv, "Row", Span::default()).unwrap();
+        assert_eq!(sheet.rows, rows);
+
+        let text = write(&header, &rows, Format::Tsv);
+        assert_eq!(
+            text,
+            "Name: str\tavg GDP\n\"Bóg, \"\"Honor\"\"\"\t2.5\n\"12\"\t-3.0\n\"\"\t7.5\n"
+        );
+        let sheet = read(&text, Format::Tsv, "Row", Span::default()).unwrap();
+        assert_eq!(sheet.rows, rows);
+
+        // Text which only holds numbers loads as text again
+        let header = ["Zip".to_string(), "City".to_string()];
+        let rows = [vec![
+            Literals::STR("00501".to_string()),
+            Literals::STR("Holtsville".to_string()),
+        ]];
+        let text = write(&header, &rows, Format::Csv);
+        assert_eq!(text, "Zip: str,City\r\n\"00501\",Holtsville\r\n");
+        let sheet = read(&text, Format::Csv, "Zip", Span::default()).unwrap();
+        assert_eq!(sheet.record.fields[0].name, "Zip");
+        assert_eq!(
+            sheet.record.fields[0].ty,
+            Some(FieldType::Scalar(Type::Str))
+        );
+        assert_eq!(sheet.rows, rows);
+    }
+}
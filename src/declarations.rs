@@ -1,56 +1,528 @@
+use crate::{
+    ast::{ExprId, StmtId},
+    tokenizer::{FloatType, IntType, Span, TokenType},
+};
+
+// ITEMS
+
+/// Top level entries of a program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Item {
+    Stmt(StmtId),
+}
+
+// STATEMENTS
+
 #[derive(Debug, PartialEq)]
-pub enum NodeType {
-    Variable(VariableDecl),
-    Stmt(CompoundStmt),
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum StmtKind {
+    Let(VariableDecl),
+    Block(CompoundStmt),
+    Expr(ExprId),
+    Record(RecordDecl),
+    Insert(InsertStmt),
+    Table(TableDecl),
+    Query(Query),
+    Load(LoadStmt),
+    Save(SaveStmt),
+}
+
+/// Statements between `{` and `}`.
 #[derive(Debug, PartialEq)]
 pub struct CompoundStmt {
+    /// Index of the scope opened by this block in `Parser::scopes`
+    pub scope: usize,
     pub stack_offset: u16,
-    pub children: Vec<usize>,
+    pub children: Vec<StmtId>,
 }
 
 impl CompoundStmt {
-    pub fn new(stack_offset: u16) -> Self {
+    pub fn new(scope: usize, stack_offset: u16) -> Self {
         Self {
+            scope,
             stack_offset,
             children: vec![],
         }
     }
 }
 
-// LITERALS
+#[derive(Debug, PartialEq)]
+pub struct VariableDecl {
+    pub stack_offset: u16,
+    pub identifier: String,
+    pub value: ExprId,
+    /// Lines of the `##` comments right above the declaration
+    pub doc: Option<String>,
+}
 
+/// `Person: Name Age Career`, a record type declared at the top level.
 #[derive(Debug, PartialEq)]
-pub enum Literals {
-    NUMBER(String),
-    STR(String),
+pub struct RecordDecl {
+    pub name: String,
+    pub fields: Vec<FieldDecl>,
 }
 
+/// Field of a record, typed as in `Wage: i64`, or left for the values stored in it to decide.
 #[derive(Debug, PartialEq)]
-pub struct LiteralDecl {
-    pub value: Literals,
+pub struct FieldDecl {
+    pub name: String,
+    pub ty: Option<FieldType>,
+    pub span: Span,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldType {
+    Scalar(Type),
+    /// Record stored inline, named explicitly or by naming the field after it
+    Record(String),
+}
+
+impl FieldType {
+    pub fn name(&self) -> &str {
+        match self {
+            FieldType::Scalar(ty) => ty.name(),
+            FieldType::Record(name) => name,
+        }
+    }
+}
+
+/// `Persons -> Jacek 24 Driver 2400, Tomek 30 Architect 5000`, appending rows of records
+/// to a collection. Values fill the fields in order, nested records included.
 #[derive(Debug, PartialEq)]
-pub struct VariableDecl {
-    pub stack_offset: u16,
+pub struct InsertStmt {
+    pub collection: String,
+    /// Record type of the rows, the collection name in the singular
+    pub record: String,
+    /// Literals, bare words are strings
+    pub rows: Vec<Vec<ExprId>>,
+}
+
+/// Header line of column names followed by a row per line, up to a blank line:
+///
+/// ```text
+/// Countries:
+/// Name     Code    GDP
+/// Poland   PLN     1000
+/// ```
+///
+/// Declares the record type of the rows and fills the collection at once.
+#[derive(Debug, PartialEq)]
+pub struct TableDecl {
+    pub record: RecordDecl,
+    pub insert: InsertStmt,
+}
+
+/// `get names from persons sorted by wage`, listing fields of the rows of a collection.
+#[derive(Debug, PartialEq)]
+pub struct Query {
+    /// Every field of the rows when empty, as in `get a country`
+    pub fields: Vec<Projection>,
+    pub collection: String,
+    /// Record type of the rows
+    pub record: String,
+    /// Collections whose rows are added to the matching rows of the collection
+    pub joins: Vec<Join>,
+    /// `with lowest GDP`, keeping the rows which hold the lowest or highest value
+    pub with: Option<Aggregate>,
+    /// Rows the query keeps, all of them without `where` or `whose`
+    pub filter: Option<Filter>,
+    /// `group by age`, a result row for each value of the field
+    pub group: Option<FieldRef>,
+    pub sort: Option<Sort>,
+}
+
+/// `load "countries.csv" as Countries`, declaring the record type of the rows from the
+/// header of the file and filling the collection with the rest of it.
+#[derive(Debug, PartialEq)]
+pub struct LoadStmt {
+    pub path: String,
+    pub collection: String,
+    pub record: String,
+}
+
+/// `save names, GDP from countries to "out.csv"`, writing the rows of a query to a file.
+#[derive(Debug, PartialEq)]
+pub struct SaveStmt {
+    pub query: Query,
+    pub path: String,
+}
+
+impl Projection {
+    /// As written in dumps, `name` or `avg GDP`.
+    pub fn label(&self) -> String {
+        match self {
+            Projection::Field(field) => field.word.clone(),
+            Projection::Aggregate(aggregate) => aggregate.label(),
+        }
+    }
+}
+
+impl Aggregate {
+    pub fn label(&self) -> String {
+        match &self.field {
+            Some(field) => format!("{} {}", self.function.name(), field.word),
+            None => self.function.name().to_string(),
+        }
+    }
+}
+
+impl Query {
+    pub fn aggregates(&self) -> bool {
+        self.fields
+            .iter()
+            .any(|field| matches!(field, Projection::Aggregate(_)))
+    }
+
+    /// Record types of a row, the collection's and those of the joins in order.
+    pub fn records(&self) -> Vec<String> {
+        let joined = self.joins.iter().map(|join| join.record.clone());
+        std::iter::once(self.record.clone()).chain(joined).collect()
+    }
+}
+
+/// `join careers on career = name`, pairing every row with the rows of another collection
+/// whose field holds the same value. Without `on`, a field of the rows named after the
+/// joined record type refers to its first field.
+#[derive(Debug, PartialEq)]
+pub struct Join {
+    pub collection: String,
+    pub record: String,
+    /// Field of the rows so far and field of the joined rows, in either order
+    pub on: Option<(FieldRef, FieldRef)>,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Projection {
+    Field(FieldRef),
+    Aggregate(Aggregate),
+}
+
+/// `average GDP` or `count`, one value out of all the rows of a group.
+#[derive(Debug, PartialEq)]
+pub struct Aggregate {
+    pub function: Function,
+    /// Only `count` goes without one
+    pub field: Option<FieldRef>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Min,
+    Max,
+    Avg,
+    Sum,
+    Count,
+}
+
+impl Function {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Avg => "avg",
+            Function::Sum => "sum",
+            Function::Count => "count",
+        }
+    }
+
+    /// Takes the names in any case, and `lowest`, `highest` and `average` in their place.
+    pub fn from_word(word: &str) -> Option<Self> {
+        match word.to_lowercase().as_str() {
+            "min" | "lowest" => Some(Function::Min),
+            "max" | "highest" => Some(Function::Max),
+            "avg" | "average" => Some(Function::Avg),
+            "sum" => Some(Function::Sum),
+            "count" => Some(Function::Count),
+            _ => None,
+        }
+    }
+}
+
+/// `whose name starts with C and GDP > 1000`, where `and` binds tighter than `or`.
+#[derive(Debug, PartialEq)]
+pub enum Filter {
+    Test(Test),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+impl Filter {
+    /// Values the tests compare against, left to right.
+    pub fn values(&self) -> Vec<ExprId> {
+        match self {
+            Filter::Test(test) => vec![test.value],
+            Filter::And(lhs, rhs) | Filter::Or(lhs, rhs) => [lhs.values(), rhs.values()].concat(),
+        }
+    }
+
+    pub fn tests(&self) -> Vec<&Test> {
+        match self {
+            Filter::Test(test) => vec![test],
+            Filter::And(lhs, rhs) | Filter::Or(lhs, rhs) => [lhs.tests(), rhs.tests()].concat(),
+        }
+    }
+}
+
+/// Field of a row tested against a literal.
+#[derive(Debug, PartialEq)]
+pub struct Test {
+    pub field: FieldRef,
+    pub predicate: Predicate,
+    pub value: ExprId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Predicate {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    StartsWith,
+    EndsWith,
+    Contains,
+}
+
+impl Predicate {
+    pub const ALL: [Predicate; 9] = [
+        Predicate::Eq,
+        Predicate::Ne,
+        Predicate::Lt,
+        Predicate::Le,
+        Predicate::Gt,
+        Predicate::Ge,
+        Predicate::StartsWith,
+        Predicate::EndsWith,
+        Predicate::Contains,
+    ];
+
+    pub fn from_token(token_type: &TokenType) -> Option<Self> {
+        match token_type {
+            TokenType::EQUAL | TokenType::EQ => Some(Predicate::Eq),
+            TokenType::NE => Some(Predicate::Ne),
+            TokenType::LT => Some(Predicate::Lt),
+            TokenType::LE => Some(Predicate::Le),
+            TokenType::GT => Some(Predicate::Gt),
+            TokenType::GE => Some(Predicate::Ge),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Predicate::Eq => "=",
+            Predicate::Ne => "!=",
+            Predicate::Lt => "<",
+            Predicate::Le => "<=",
+            Predicate::Gt => ">",
+            Predicate::Ge => ">=",
+            Predicate::StartsWith => "starts with",
+            Predicate::EndsWith => "ends with",
+            Predicate::Contains => "contains",
+        }
+    }
+
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        Predicate::ALL
+            .into_iter()
+            .find(|predicate| predicate.symbol() == symbol)
+    }
+
+    /// Tests on text, the rest compare values of the same type.
+    pub fn on_text(&self) -> bool {
+        matches!(
+            self,
+            Predicate::StartsWith | Predicate::EndsWith | Predicate::Contains
+        )
+    }
+
+    pub fn orders(&self) -> bool {
+        matches!(
+            self,
+            Predicate::Lt | Predicate::Le | Predicate::Gt | Predicate::Ge
+        )
+    }
+}
+
+/// Field of a record as a query names it, in the singular or plural and in any case.
+/// Resolved against the record type once fields are known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldRef {
+    pub word: String,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Sort {
+    pub field: FieldRef,
+    /// Numbers go from the largest and strings from A when no order is given
+    pub order: Option<Order>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+impl Order {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Order::Asc => "asc",
+            Order::Desc => "desc",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Order::Asc, Order::Desc]
+            .into_iter()
+            .find(|order| order.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// EXPRESSIONS
+
+#[derive(Debug, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExprKind {
+    Literal(Literals),
+    Variable(VariableRef),
+    Binary(BinaryOp, ExprId, ExprId),
+    /// `value as f64`
+    Cast(ExprId, Type),
+}
+
+/// Use of a variable, already resolved to its slot on the stack.
+#[derive(Debug, PartialEq)]
+pub struct VariableRef {
     pub identifier: String,
-    pub literal: LiteralDecl,
+    pub stack_offset: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinaryOp {
+    pub fn from_token(token_type: &TokenType) -> Option<Self> {
+        match token_type {
+            TokenType::PLUS => Some(BinaryOp::Add),
+            TokenType::MINUS => Some(BinaryOp::Sub),
+            TokenType::MULT => Some(BinaryOp::Mul),
+            TokenType::DIV => Some(BinaryOp::Div),
+            _ => None,
+        }
+    }
+
+    /// Operators with higher precedence bind tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Sub => 1,
+            BinaryOp::Mul | BinaryOp::Div => 2,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+        }
+    }
+
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        [BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div]
+            .into_iter()
+            .find(|op| op.symbol() == symbol)
+    }
+}
+
+// TYPES
+
+/// Type of a value, as figured out by `typeck`.
+/// Integers keep the width of their literal suffix, `i64` without one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Int(IntType),
+    Float(FloatType),
+    Str,
+}
+
+impl Type {
+    /// Types numbers can be cast to: integers of any width, `f32` and `f64`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "f32" => Some(Type::Float(FloatType::F32)),
+            "f64" => Some(Type::Float(FloatType::F64)),
+            _ => IntType::from_suffix(name).map(Type::Int),
+        }
+    }
+
+    /// Fields are `i64`, `f32`, `f64` or `str`, which nothing can be cast to.
+    pub fn from_field_name(name: &str) -> Option<Self> {
+        match name {
+            "str" => Some(Type::Str),
+            "i64" | "f32" | "f64" => Type::from_name(name),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Int(int_type) => int_type.name(),
+            Type::Float(float_type) => float_type.name(),
+            Type::Str => "str",
+        }
+    }
+}
+
+// LITERALS
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literals {
+    NUMBER(String, IntType),
+    FLOAT(String, FloatType),
+    STR(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LiteralDecl {
+    pub value: Literals,
 }
 
+#[allow(dead_code)]
 pub struct FunctionDecl {
     pub stack_offset: u16,
     pub identifier: String,
     pub literal: LiteralDecl,
 }
 
+#[allow(dead_code)]
 pub struct IncDecl {
     // Which literal is incremented
     pub literal_index: u32,
     pub literal_scope_index: u32,
 }
 
+#[allow(dead_code)]
 pub struct LoopDecl {
     pub jmp_label_declaration: String, //for(u8 i = 12; i < 100; i++)
     //---------^------------------
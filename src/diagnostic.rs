@@ -0,0 +1,64 @@
+use std::fmt;
+
+use crate::tokenizer::Span;
+
+/// Error pointing at a piece of the source, returned by every stage reading it so the
+/// caller can show the offending line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// `line:column: message` followed by the source line with the span underlined.
+    pub fn render(&self, source: &str) -> String {
+        let (line, column) = self.span.line_col(source);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+
+        let start = self.span.start.min(source.len());
+        let end = self.span.end().min(source.len());
+        let width = source[start..end].chars().count().max(1);
+        let gutter = " ".repeat(line.to_string().len());
+
+        format!(
+            "{line}:{column}: {}\n{gutter} |\n{line} | {text}\n{gutter} | {}{}\n",
+            self.message,
+            " ".repeat(column - 1),
+            "^".repeat(width),
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizer::Span;
+
+    use super::Diagnostic;
+
+    #[test]
+    fn underlines_the_span() {
+        let diagnostic = Diagnostic {
+            message: String::from("bad literal"),
+            span: Span { start: 19, len: 5 },
+        };
+
+        assert_eq!(
+            diagnostic.render("let a = 1;\nlet b = 300u8;\n"),
+            "2:9: bad literal\n  |\n2 | let b = 300u8;\n  |         ^^^^^\n"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{
+    ast::{walk_expr, walk_item, walk_stmt, ExprId, ItemId, StmtId, Visitor, AST},
+    declarations::{ExprKind, Filter, Item, Literals, Projection, SaveStmt, StmtKind},
+    symbols::CodeScope,
+    tokenizer::Span,
+};
+
+/// Renders scopes and AST as a Graphviz graph, e.g. `x-rs --emit=dot main.x | dot -Tsvg`.
+/// Every node carries its source span as `line:column-line:column`.
+pub fn render(scopes: &[CodeScope], ast: &AST, source: &str) -> String {
+    let mut out = String::from("digraph x_rs {\n");
+    out += "    node [shape=box, fontname=\"monospace\"];\n\n";
+
+    scope_cluster(scopes, source, &mut out);
+    out += "\n";
+    ast_cluster(ast, source, &mut out);
+
+    out += "}\n";
+    out
+}
+
+fn span_label(span: Span, source: &str) -> String {
+    let (line, column) = span.line_col(source);
+    let end = Span {
+        start: span.end(),
+        len: 0,
+    };
+    let (end_line, end_column) = end.line_col(source);
+    format!("{line}:{column}-{end_line}:{end_column}")
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn scope_cluster(scopes: &[CodeScope], source: &str, out: &mut String) {
+    out.push_str("    subgraph cluster_scopes {\n");
+    out.push_str("        label=\"scopes\";\n");
+
+    for (index, scope) in scopes.iter().enumerate() {
+        let mut symbols: Vec<_> = scope.symbols.iter().collect();
+        symbols.sort_by_key(|(_, span)| span.start);
+
+        let mut label = format!("scope {index}\n{}", span_label(scope.span, source));
+        for (name, span) in symbols {
+            label += &format!("\n{name} @ {}", span_label(*span, source));
+        }
+
+        *out += &format!("        scope{index} [label=\"{}\"];\n", escape(&label));
+    }
+
+    for (index, scope) in scopes.iter().enumerate() {
+        for nested in scope.nested_scopes() {
+            *out += &format!("        scope{index} -> scope{nested};\n");
+        }
+        if let Some(parent) = scope.parent_scope {
+            *out += &format!(
+                "        scope{index} -> scope{parent} [style=dashed, constraint=false];\n"
+            );
+        }
+    }
+
+    out.push_str("    }\n");
+}
+
+/// Emits one graph node per AST node, with edges from parents to children.
+struct AstGraph<'a> {
+    source: &'a str,
+    out: String,
+}
+
+impl AstGraph<'_> {
+    fn node(&mut self, name: &str, label: &str, span: Span) {
+        let label = format!("{label}\n{}", span_label(span, self.source));
+        self.out += &format!("        {name} [label=\"{}\"];\n", escape(&label));
+    }
+
+    fn edge(&mut self, from: &str, to: &str) {
+        self.out += &format!("        {from} -> {to};\n");
+    }
+}
+
+impl Visitor for AstGraph<'_> {
+    fn visit_item(&mut self, ast: &AST, id: ItemId) {
+        let Item::Stmt(stmt) = ast[id];
+        self.edge("program", &format!("stmt{}", stmt.index()));
+        walk_item(self, ast, id);
+    }
+
+    fn visit_stmt(&mut self, ast: &AST, id: StmtId) {
+        let name = format!("stmt{}", id.index());
+        let stmt = &ast[id];
+
+        match &stmt.kind {
+            StmtKind::Let(decl) => {
+                let label = format!("let {}\noffset {}", decl.identifier, decl.stack_offset);
+                self.node(&name, &label, stmt.span);
+                self.edge(&name, &format!("expr{}", decl.value.index()));
+            }
+            StmtKind::Block(block) => {
+                let label = format!("block\nscope {}", block.scope);
+                self.node(&name, &label, stmt.span);
+                for child in &block.children {
+                    self.edge(&name, &format!("stmt{}", child.index()));
+                }
+            }
+            StmtKind::Expr(expr) => {
+                self.node(&name, "expr", stmt.span);
+                self.edge(&name, &format!("expr{}", expr.index()));
+            }
+            StmtKind::Insert(insert) => {
+                let label = format!("insert {}\nrecord {}", insert.collection, insert.record);
+                self.node(&name, &label, stmt.span);
+                for value in insert.rows.iter().flatten() {
+                    self.edge(&name, &format!("expr{}", value.index()));
+                }
+            }
+            StmtKind::Table(table) => {
+                let fields: Vec<&str> = table
+                    .record
+                    .fields
+                    .iter()
+                    .map(|field| field.name.as_str())
+                    .collect();
+                let label = format!(
+                    "table {}\nrecord {}\n{}",
+                    table.insert.collection,
+                    table.record.name,
+                    fields.join(" ")
+                );
+                self.node(&name, &label, stmt.span);
+                for value in table.insert.rows.iter().flatten() {
+                    self.edge(&name, &format!("expr{}", value.index()));
+                }
+            }
+            StmtKind::Load(load) => {
+                let label = format!(
+                    "load {}\nrecord {}\n{}",
+                    load.collection, load.record, load.path
+                );
+                self.node(&name, &label, stmt.span);
+            }
+            StmtKind::Query(query) | StmtKind::Save(SaveStmt { query, .. }) => {
+                let fields: Vec<String> = query.fields.iter().map(Projection::label).collect();
+                let mut label = format!("query {}\nget {}", query.collection, fields.join(", "));
+                if let StmtKind::Save(save) = &stmt.kind {
+                    label = format!("save {}\n{label}", save.path);
+                }
+                for join in &query.joins {
+                    label += &format!("\njoin {}", join.collection);
+                }
+                if let Some(with) = &query.with {
+                    label += &format!("\nwith {}", with.label());
+                }
+                if let Some(filter) = &query.filter {
+                    let fields: Vec<&str> = filter
+                        .tests()
+                        .into_iter()
+                        .map(|test| test.field.word.as_str())
+                        .collect();
+                    label += &format!("\nwhere {}", fields.join(" "));
+                }
+                if let Some(group) = &query.group {
+                    label += &format!("\ngroup by {}", group.word);
+                }
+                if let Some(sort) = &query.sort {
+                    label += &format!("\nsorted by {}", sort.field.word);
+                }
+                self.node(&name, &label, stmt.span);
+                for value in query.filter.iter().flat_map(Filter::values) {
+                    self.edge(&name, &format!("expr{}", value.index()));
+                }
+            }
+            StmtKind::Record(record) => {
+                let fields: Vec<&str> = record
+                    .fields
+                    .iter()
+                    .map(|field| field.name.as_str())
+                    .collect();
+                let label = format!("record {}\n{}", record.name, fields.join(" "));
+                self.node(&name, &label, stmt.span);
+            }
+        }
+
+        walk_stmt(self, ast, id);
+    }
+
+    fn visit_expr(&mut self, ast: &AST, id: ExprId) {
+        let name = format!("expr{}", id.index());
+        let expr = &ast[id];
+
+        let label = match &expr.kind {
+            ExprKind::Literal(Literals::NUMBER(value, int_type)) => {
+                format!("{value}{}", int_type.suffix())
+            }
+            ExprKind::Literal(Literals::FLOAT(value, float_type)) => {
+                format!("{value}{}", float_type.name())
+            }
+            ExprKind::Literal(Literals::STR(value)) => format!("{value:?}"),
+            ExprKind::Variable(var) => format!("{}\noffset {}", var.identifier, var.stack_offset),
+            ExprKind::Binary(op, lhs, rhs) => {
+                self.edge(&name, &format!("expr{}", lhs.index()));
+                self.edge(&name, &format!("expr{}", rhs.index()));
+                op.symbol().to_string()
+            }
+            ExprKind::Cast(value, ty) => {
+                self.edge(&name, &format!("expr{}", value.index()));
+                format!("as {}", ty.name())
+            }
+        };
+        self.node(&name, &label, expr.span);
+
+        walk_expr(self, ast, id);
+    }
+}
+
+fn ast_cluster(ast: &AST, source: &str, out: &mut String) {
+    out.push_str("    subgraph cluster_ast {\n");
+    out.push_str("        label=\"AST\";\n");
+    out.push_str("        program [label=\"program\"];\n");
+
+    let mut graph = AstGraph {
+        source,
+        out: String::new(),
+    };
+    graph.visit_program(ast);
+    out.push_str(&graph.out);
+
+    out.push_str("    }\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+    use crate::symbols::CodeScope;
+    use crate::tokenizer::Tokenizer;
+
+    use super::render;
+
+    #[test]
+    fn renders_scopes_and_nodes_with_spans() {
+        let source = "let a = 1;\n{\n    let b = 2;\n}\n";
+
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser.parse(Tokenizer::new(source)).unwrap();
+
+        let dot = render(parser.scopes(), &ast, source);
+
+        assert!(dot.starts_with("digraph x_rs {"));
+        assert!(dot.contains("scope0 [label=\"scope 0\\n1:1-5:1\\na @ 1:5-1:6\"];"));
+        assert!(dot.contains("scope1 [label=\"scope 1\\n2:1-4:2\\nb @ 3:9-3:10\"];"));
+        assert!(dot.contains("scope0 -> scope1;"));
+        assert!(dot.contains("scope1 -> scope0 [style=dashed, constraint=false];"));
+        assert!(dot.contains("stmt1 [label=\"let b\\noffset 16\\n3:5-3:14\"];"));
+        assert!(dot.contains("stmt2 -> stmt1;"));
+        assert!(dot.contains("stmt1 -> expr1;"));
+        assert!(dot.contains("expr1 [label=\"2\\n3:13-3:14\"];"));
+        assert!(dot.contains("program -> stmt2;"));
+        assert!(!dot.contains("program -> stmt1;"));
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::{
+    assembly::Assembler,
+    ast::AST,
+    ast_dump,
+    cst::Cst,
+    diagnostic::Diagnostic,
+    dot,
+    fold::ConstFolder,
+    formatter,
+    json::Json,
+    misc::{AstFormat, Emit, Input, Parameters},
+    parser::Parser,
+    plan::{self, Plan},
+    symbols::CodeScope,
+    tokenizer::{Token, Tokenizer},
+    typeck::TypeChecker,
+};
+
+#[derive(Debug)]
+pub enum DriverError {
+    Read(String, io::Error),
+    Write(String, io::Error),
+    Tool(String, String),
+    Unsupported(String),
+    InvalidAst(String),
+    /// Rendered `Diagnostic`s found while reading the source
+    Diagnostic(String),
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::Read(path, err) => write!(f, "failed to read '{path}': {err}"),
+            DriverError::Write(path, err) => write!(f, "failed to write '{path}': {err}"),
+            DriverError::Tool(tool, reason) => write!(f, "'{tool}' failed: {reason}"),
+            DriverError::Unsupported(reason) => write!(f, "{reason}"),
+            DriverError::InvalidAst(reason) => write!(f, "invalid AST dump: {reason}"),
+            DriverError::Diagnostic(text) => write!(f, "{}", text.trim_end()),
+        }
+    }
+}
+
+pub fn read_input(input: &Input) -> Result<String, DriverError> {
+    let mut content = String::new();
+
+    match input {
+        Input::Stdin => io::stdin()
+            .read_to_string(&mut content)
+            .map_err(|err| DriverError::Read(input.to_string(), err))?,
+        Input::File(path) => fs::File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|err| DriverError::Read(path.clone(), err))?,
+    };
+
+    Ok(content)
+}
+
+/// Runs the pipeline for a single input up to the stage requested with `--emit`.
+/// Files ending with `.json` are read as an AST dumped with `--ast-format=json`.
+pub fn compile(input: &Input, params: &Parameters) -> Result<(), DriverError> {
+    let source = read_input(input)?;
+
+    let is_ast_dump = matches!(input, Input::File(path) if path.ends_with(".json"));
+    let mut ast = if is_ast_dump {
+        if matches!(
+            params.emit,
+            Emit::Tokens | Emit::Cst | Emit::Scopes | Emit::Dot
+        ) {
+            return Err(DriverError::Unsupported(String::from(
+                "tokens, trivia, scopes and spans are not stored in AST dumps",
+            )));
+        }
+
+        let json = Json::parse(&source).map_err(|err| DriverError::InvalidAst(err.to_string()))?;
+        ast_dump::from_json(&json).map_err(DriverError::InvalidAst)?
+    } else {
+        let tokenizer = Tokenizer::new(&source);
+
+        if params.emit == Emit::Tokens {
+            let tokens: Vec<Token> = tokenizer
+                .collect::<Result<_, _>>()
+                .map_err(|error| rendered(&source, [error]))?;
+            return write_text(params, &dump_tokens(&tokens));
+        }
+
+        if params.emit == Emit::Cst {
+            let cst = Cst::parse(&source).map_err(|error| rendered(&source, [error]))?;
+            return write_text(params, &cst.dump());
+        }
+
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser
+            .parse(tokenizer)
+            .map_err(|error| rendered(&source, [error]))?;
+
+        if params.emit == Emit::Scopes {
+            return write_text(params, &dump_scopes(parser.scopes()));
+        }
+
+        if params.emit == Emit::Dot {
+            return write_text(params, &dot::render(parser.scopes(), &ast, &source));
+        }
+
+        ast
+    };
+
+    // Spans of a dumped AST point into a source we do not have
+    let spanned_source = if is_ast_dump { "" } else { source.as_str() };
+
+    match params.emit {
+        Emit::Ast => {
+            let text = match params.ast_format {
+                AstFormat::Tree => ast_dump::tree(&ast),
+                AstFormat::Sexp => ast_dump::sexp(&ast),
+                AstFormat::Json => ast_dump::to_json(&ast).pretty() + "\n",
+            };
+            return write_text(params, &text);
+        }
+        Emit::Ir => return write_text(params, &dump_arenas(&ast)),
+        Emit::Plan => {
+            let types = TypeChecker::new()
+                .check(&ast)
+                .map_err(|errors| rendered(spanned_source, errors))?;
+            let plans: Vec<String> = plan::plan_program(&ast, types.records())
+                .iter()
+                .map(Plan::render)
+                .collect();
+            return write_text(params, &plans.join("\n"));
+        }
+        _ => {}
+    }
+
+    let output = match &params.output {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let stem = PathBuf::from(input.stem());
+            match params.emit {
+                Emit::Asm => stem.with_extension("asm"),
+                Emit::Obj => stem.with_extension("o"),
+                _ => stem,
+            }
+        }
+    };
+
+    let asm_path = match params.emit {
+        Emit::Asm => output.clone(),
+        _ => output.with_extension("asm"),
+    };
+
+    let types = TypeChecker::new()
+        .check(&ast)
+        .map_err(|errors| rendered(spanned_source, errors))?;
+
+    ConstFolder::new(&types)
+        .fold(&mut ast)
+        .map_err(|errors| rendered(spanned_source, errors))?;
+
+    let mut assembler = Assembler::new(&asm_path.to_string_lossy());
+    assembler
+        .assemble_ast(&ast, types)
+        .map_err(|errors| rendered(spanned_source, errors))?;
+    assembler
+        .build()
+        .map_err(|err| DriverError::Write(asm_path.to_string_lossy().to_string(), err))?;
+
+    if params.emit == Emit::Asm {
+        return Ok(());
+    }
+
+    let obj_path = match params.emit {
+        Emit::Obj => output.clone(),
+        _ => output.with_extension("o"),
+    };
+
+    let assembled = run_tool(
+        "as",
+        &["--64", "-o", path_str(&obj_path), path_str(&asm_path)],
+    );
+    let _ = fs::remove_file(&asm_path);
+    assembled?;
+
+    if params.emit == Emit::Obj {
+        return Ok(());
+    }
+
+    let linked = run_tool("ld", &["-o", path_str(&output), path_str(&obj_path)]);
+    let _ = fs::remove_file(&obj_path);
+    linked
+}
+
+/// Formats one input, returning whether it changed. Unless only checking, the result goes
+/// to `-o`, back into the input file, or to stdout for stdin.
+pub fn format(input: &Input, params: &Parameters) -> Result<bool, DriverError> {
+    let source = read_input(input)?;
+    let formatted = formatter::format(&source).map_err(|error| rendered(&source, [error]))?;
+    let changed = formatted != source;
+
+    if params.check {
+        return Ok(changed);
+    }
+
+    match (input, &params.output) {
+        (Input::File(path), None) if changed => {
+            fs::write(path, &formatted).map_err(|err| DriverError::Write(path.clone(), err))?
+        }
+        (Input::File(_), None) => {}
+        _ => write_text(params, &formatted)?,
+    }
+
+    Ok(changed)
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap_or_default()
+}
+
+/// Error showing the offending source line of each diagnostic.
+fn rendered(source: &str, diagnostics: impl IntoIterator<Item = Diagnostic>) -> DriverError {
+    let rendered: Vec<String> = diagnostics
+        .into_iter()
+        .map(|diagnostic| diagnostic.render(source))
+        .collect();
+    DriverError::Diagnostic(rendered.join("\n"))
+}
+
+fn run_tool(tool: &str, args: &[&str]) -> Result<(), DriverError> {
+    let output = Command::new(tool)
+        .args(args)
+        .output()
+        .map_err(|err| DriverError::Tool(tool.to_string(), err.to_string()))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DriverError::Tool(
+            tool.to_string(),
+            stderr.trim().to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn write_text(params: &Parameters, text: &str) -> Result<(), DriverError> {
+    match &params.output {
+        Some(path) => fs::write(path, text).map_err(|err| DriverError::Write(path.clone(), err)),
+        None => io::stdout()
+            .write_all(text.as_bytes())
+            .map_err(|err| DriverError::Write(String::from("<stdout>"), err)),
+    }
+}
+
+pub fn dump_tokens(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    for token in tokens {
+        text += &format!(
+            "{:>5}..{:<5} {:?}\n",
+            token.start,
+            token.start + token.len,
+            token.token_type
+        );
+    }
+    text
+}
+
+pub fn dump_ast(ast: &AST) -> String {
+    ast_dump::tree(ast)
+}
+
+/// Lists every arena in allocation order, which is the order passes see the nodes in.
+pub fn dump_arenas(ast: &AST) -> String {
+    let mut text = String::from("items:\n");
+    for item in &ast.items {
+        text += &format!("{:>6?}: {:?}\n", item, ast[*item]);
+    }
+    text += "stmts:\n";
+    for (id, stmt) in ast.stmt_arena.iter() {
+        text += &format!(
+            "{:>6?}: {:?} @{}..{}\n",
+            id,
+            stmt.kind,
+            stmt.span.start,
+            stmt.span.end()
+        );
+    }
+    text += "exprs:\n";
+    for (id, expr) in ast.expr_arena.iter() {
+        text += &format!(
+            "{:>6?}: {:?} @{}..{}\n",
+            id,
+            expr.kind,
+            expr.span.start,
+            expr.span.end()
+        );
+    }
+    text
+}
+
+pub fn dump_scopes(scopes: &[CodeScope]) -> String {
+    let mut text = String::new();
+    for (index, scope) in scopes.iter().enumerate() {
+        text += &format!("scope {index}: {scope:#?}\n");
+    }
+    text
+}
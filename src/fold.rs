@@ -0,0 +1,123 @@
+use crate::{
+    ast::{walk_expr_mut, ExprId, MutVisitor, AST},
+    declarations::{BinaryOp, ExprKind, Literals, Type},
+    diagnostic::Diagnostic,
+    typeck::Types,
+};
+
+/// Replaces arithmetic on number literals with its result, e.g. `2 * 3 + x` becomes `6 + x`.
+/// Runs on a type checked program and only folds operations the checker typed as ints.
+/// Results out of range for their type are errors, division by zero is left alone so that
+/// it still fails at run time.
+pub struct ConstFolder<'a> {
+    types: &'a Types,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> ConstFolder<'a> {
+    pub fn new(types: &'a Types) -> Self {
+        Self {
+            types,
+            diagnostics: vec![],
+        }
+    }
+
+    pub fn fold(&mut self, ast: &mut AST) -> Result<(), Vec<Diagnostic>> {
+        self.visit_program(ast);
+        match std::mem::take(&mut self.diagnostics) {
+            diagnostics if diagnostics.is_empty() => Ok(()),
+            diagnostics => Err(diagnostics),
+        }
+    }
+}
+
+fn number(ast: &AST, id: ExprId) -> Option<i128> {
+    match &ast[id].kind {
+        ExprKind::Literal(Literals::NUMBER(value, _)) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+impl MutVisitor for ConstFolder<'_> {
+    fn visit_expr(&mut self, ast: &mut AST, id: ExprId) {
+        walk_expr_mut(self, ast, id);
+
+        let ExprKind::Binary(op, lhs, rhs) = ast[id].kind else {
+            return;
+        };
+        let Some(Type::Int(int_type)) = self.types.get(id) else {
+            return;
+        };
+
+        let (Some(lhs), Some(rhs)) = (number(ast, lhs), number(ast, rhs)) else {
+            return;
+        };
+
+        let value = match op {
+            BinaryOp::Add => lhs.checked_add(rhs),
+            BinaryOp::Sub => lhs.checked_sub(rhs),
+            BinaryOp::Mul => lhs.checked_mul(rhs),
+            BinaryOp::Div if rhs == 0 => return,
+            BinaryOp::Div => lhs.checked_div(rhs),
+        };
+        let Some(value) = value.filter(|value| int_type.fits(*value)) else {
+            let message = format!("{lhs} {} {rhs} overflows {}", op.symbol(), int_type.name());
+            self.diagnostics
+                .push(Diagnostic::new(ast[id].span, message));
+            return;
+        };
+
+        ast[id].kind = ExprKind::Literal(Literals::NUMBER(value.to_string(), int_type));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast_dump::sexp;
+    use crate::diagnostic::Diagnostic;
+    use crate::parser::Parser;
+    use crate::symbols::CodeScope;
+    use crate::tokenizer::Tokenizer;
+    use crate::typeck::TypeChecker;
+
+    use super::ConstFolder;
+
+    fn fold(source: &str) -> Result<String, Vec<Diagnostic>> {
+        let mut parser = Parser::new(CodeScope::global());
+        let mut ast = parser.parse(Tokenizer::new(source)).unwrap();
+        let types = TypeChecker::new().check(&ast)?;
+        ConstFolder::new(&types).fold(&mut ast)?;
+        Ok(sexp(&ast))
+    }
+
+    #[test]
+    fn folds_literal_arithmetic() {
+        assert_eq!(
+            fold("let x = 2 * 3 + 4 - 20;\n").unwrap(),
+            "(program\n  (let x :offset 0 (number -10)))\n"
+        );
+    }
+
+    #[test]
+    fn folds_at_the_width_of_the_literals() {
+        assert_eq!(
+            fold("let x = 250u8 + 5u8;\nlet y = 18446744073709551615u64;\n").unwrap(),
+            "(program\n  (let x :offset 0 (number 255u8))\n  \
+             (let y :offset 16 (number 18446744073709551615u64)))\n"
+        );
+
+        let error = fold("let x = 200u8 + 100u8;\n").unwrap_err().remove(0);
+        assert_eq!(error.message, "200 + 100 overflows u8");
+        assert_eq!((error.span.start, error.span.len), (8, 13));
+    }
+
+    #[test]
+    fn keeps_variables_and_division_by_zero() {
+        assert_eq!(
+            fold("let x = 1;\nlet y = x + 2 * 3;\nlet z = 1 / 0;\n").unwrap(),
+            "(program\n  (let x :offset 0 (number 1))\n  \
+             (let y :offset 16 (+ (var x :offset 0) (number 6)))\n  \
+             (let z :offset 32 (/ (number 1) (number 0))))\n"
+        );
+    }
+}
@@ -0,0 +1,465 @@
+use crate::cst::{line_breaks, Cst, Element, Node, NodeKind};
+use crate::diagnostic::Diagnostic;
+use crate::tokenizer::{LosslessToken, TokenType, Trivia, TriviaKind};
+
+const INDENT: &str = "    ";
+
+/// Prints the source in the canonical layout: four spaces per `{}` scope, statements on
+/// their own lines ending with `;` and one space between tokens, except before `;`, `,`,
+/// `:` and `)`, after `(` and after unary `-` and `!`.
+/// Line breaks inside a statement and single blank lines are kept, and so are comments.
+/// Records, inserts, tables and queries end with their line instead of `;`, see
+/// `Printer::line_statement`.
+pub fn format(source: &str) -> Result<String, Diagnostic> {
+    let cst = Cst::parse(source)?;
+    let mut printer = Printer::default();
+
+    printer.children(&cst.root, 0);
+    printer.leading(&cst.rest, 0);
+    printer.end_line();
+
+    Ok(printer.out)
+}
+
+/// What the line being printed holds so far.
+#[derive(Debug, Default, PartialEq)]
+enum Line {
+    #[default]
+    Empty,
+    /// Only a block comment, which the next token may follow
+    Comment,
+    Token,
+}
+
+#[derive(Default)]
+struct Printer<'src> {
+    out: String,
+    line: Line,
+    /// Last token of the current statement
+    last: Option<TokenType<'src>>,
+    /// Set by `(` and unary operators, which stick to the next token
+    glue: bool,
+    /// Blank lines right after `{` are dropped
+    after_open: bool,
+}
+
+impl<'src> Printer<'src> {
+    fn end_line(&mut self) {
+        if self.line != Line::Empty {
+            self.out.push('\n');
+            self.line = Line::Empty;
+        }
+    }
+
+    /// Moves to a new line when the source had a line break, or when a token is already
+    /// on this one and `force` is set. Any number of blank lines becomes one.
+    fn place(&mut self, newlines: usize, force: bool) {
+        if newlines == 0 && !(force && self.line == Line::Token) {
+            return;
+        }
+
+        self.end_line();
+        if newlines >= 2 && !self.out.is_empty() && !self.after_open {
+            self.out.push('\n');
+        }
+    }
+
+    fn write(&mut self, indent: usize, text: &str, space: bool) {
+        match self.line {
+            Line::Empty => self.out += &INDENT.repeat(indent),
+            Line::Comment => self.out.push(' '),
+            Line::Token if space => self.out.push(' '),
+            Line::Token => {}
+        }
+        self.out += text;
+        self.after_open = false;
+    }
+
+    /// Writes the comments of leading trivia, returning the line breaks after the last one.
+    fn leading(&mut self, trivia: &[Trivia], indent: usize) -> usize {
+        let mut newlines = 0;
+
+        for piece in trivia {
+            match piece.kind {
+                TriviaKind::Newline => newlines += 1,
+                TriviaKind::Whitespace => {}
+                TriviaKind::Comment | TriviaKind::BlockComment => {
+                    self.place(newlines, true);
+                    self.write(indent, piece.text, true);
+                    self.line = Line::Comment;
+                    if piece.kind == TriviaKind::Comment {
+                        self.end_line();
+                    }
+                    newlines = 0;
+                }
+            }
+        }
+
+        newlines
+    }
+
+    /// Comments in trailing trivia stay on the line of the token before them.
+    fn trailing(&mut self, trivia: &[Trivia], indent: usize) {
+        for piece in trivia {
+            match piece.kind {
+                TriviaKind::Comment => {
+                    self.write(indent, piece.text, true);
+                    self.line = Line::Token;
+                    self.end_line();
+                }
+                TriviaKind::BlockComment => {
+                    self.write(indent, piece.text, true);
+                    self.line = Line::Comment;
+                }
+                TriviaKind::Whitespace | TriviaKind::Newline => {}
+            }
+        }
+    }
+
+    fn token(&mut self, token: &LosslessToken<'src>, indent: usize, space: bool) {
+        self.write(indent, token.text, space);
+        self.line = Line::Token;
+    }
+
+    fn children(&mut self, node: &Node<'src>, depth: usize) {
+        for child in &node.children {
+            match child {
+                Element::Node(node) => self.node(node, depth),
+                // `}` without a matching `{`
+                Element::Token(token) => self.brace(token, depth, depth),
+            }
+        }
+    }
+
+    fn brace(&mut self, token: &LosslessToken<'src>, depth: usize, comment_depth: usize) {
+        let newlines = self.leading(&token.leading, comment_depth);
+        let newlines = match token.token.token_type {
+            TokenType::RightBrace => newlines.min(1),
+            _ => newlines,
+        };
+
+        self.place(newlines, true);
+        self.token(token, depth, true);
+        self.trailing(&token.trailing, depth);
+    }
+
+    fn block(&mut self, node: &Node<'src>, depth: usize) {
+        let Some((Element::Token(open), rest)) = node.children.split_first() else {
+            unreachable!("Blocks start with '{{'");
+        };
+
+        self.brace(open, depth, depth);
+        self.after_open = true;
+
+        for child in rest {
+            match child {
+                Element::Token(close) => self.brace(close, depth, depth + 1),
+                Element::Node(node) => self.node(node, depth + 1),
+            }
+        }
+    }
+
+    fn node(&mut self, node: &Node<'src>, depth: usize) {
+        match node.kind {
+            NodeKind::Block => self.block(node, depth),
+            NodeKind::Statement => self.statement(node, depth),
+            _ => self.line_statement(node, depth),
+        }
+    }
+
+    /// Whether the token goes right after the one before it, without a space.
+    fn glued(&self, token_type: &TokenType) -> bool {
+        self.glue
+            || matches!(
+                token_type,
+                TokenType::SEMICOLON
+                    | TokenType::COMMA
+                    | TokenType::COLON
+                    | TokenType::DOT
+                    | TokenType::RightParenthesis
+            )
+    }
+
+    /// Remembers what the token means for the spacing of the next one.
+    fn follow(&mut self, token_type: &TokenType<'src>) {
+        self.glue = match token_type {
+            TokenType::LeftParenthesis | TokenType::NOT | TokenType::DOT => true,
+            TokenType::MINUS => !self.last.as_ref().is_some_and(ends_operand),
+            _ => false,
+        };
+        self.last = Some(token_type.clone());
+    }
+
+    /// Records, inserts and tables keep the spaces between the tokens of a line, as they
+    /// line up columns and give fields their widths, and so do the lines printed under a
+    /// query. Every line starts at the indent of the statement except rows continued after
+    /// `,` and printed lines, which keep their own.
+    fn line_statement(&mut self, node: &Node<'src>, depth: usize) {
+        let tokens = tokens(node);
+        self.last = None;
+        self.glue = false;
+        // Only the line of a query is spaced like other statements
+        let mut spaced = node.kind == NodeKind::Query;
+
+        for (index, token) in tokens.iter().enumerate() {
+            let token_type = &token.token.token_type;
+
+            if index == 0 || line_breaks(token) > 0 {
+                let newlines = self.leading(&token.leading, depth);
+                self.place(newlines, true);
+
+                let keeps_indent = index > 0
+                    && matches!(node.kind, NodeKind::Insert | NodeKind::Query)
+                    && self.line == Line::Empty;
+                if index > 0 {
+                    spaced = false;
+                }
+                if keeps_indent {
+                    let indent = token
+                        .leading
+                        .last()
+                        .filter(|trivia| trivia.kind == TriviaKind::Whitespace);
+                    self.out += indent.map_or("", |trivia| trivia.text);
+                    self.line = Line::Token;
+                }
+                self.token(token, depth, false);
+            } else {
+                let space = spaced && !self.glued(token_type);
+                self.token(token, depth, space);
+            }
+            self.follow(token_type);
+
+            let next = tokens.get(index + 1);
+            let verbatim = !spaced
+                && next.is_some_and(|next| {
+                    line_breaks(next) == 0 && next.token.token_type != TokenType::SEMICOLON
+                });
+            if verbatim {
+                for piece in &token.trailing {
+                    self.out += piece.text;
+                }
+            } else {
+                self.trailing(&token.trailing, depth);
+            }
+        }
+    }
+
+    fn statement(&mut self, node: &Node<'src>, depth: usize) {
+        let tokens = tokens(node);
+
+        // Empty statements are dropped, keeping their comments
+        if tokens
+            .iter()
+            .all(|token| token.token.token_type == TokenType::SEMICOLON)
+        {
+            for token in tokens {
+                let newlines = self.leading(&token.leading, depth);
+                self.place(newlines, false);
+                self.trailing(&token.trailing, depth);
+            }
+            return;
+        }
+
+        self.last = None;
+        self.glue = false;
+        // Doc comments and the first token after them start lines, the rest continues one
+        let mut head = true;
+
+        for (index, token) in tokens.iter().enumerate() {
+            let token_type = &token.token.token_type;
+            let indent = if head { depth } else { depth + 1 };
+
+            let newlines = self.leading(&token.leading, indent);
+            self.place(newlines, head);
+
+            let space = !self.glued(token_type);
+            self.token(token, indent, space);
+
+            let is_last = index == tokens.len() - 1;
+            let is_doc = matches!(token_type, TokenType::COMMENT(_));
+            if is_last && !is_doc && *token_type != TokenType::SEMICOLON {
+                self.out.push(';');
+            }
+
+            self.follow(token_type);
+
+            self.trailing(&token.trailing, indent);
+            if is_doc {
+                self.end_line();
+            }
+            head = is_doc;
+        }
+    }
+}
+
+/// Tokens of a statement, which holds no nodes.
+fn tokens<'a, 'src>(node: &'a Node<'src>) -> Vec<&'a LosslessToken<'src>> {
+    node.children
+        .iter()
+        .map(|child| match child {
+            Element::Token(token) => token,
+            Element::Node(_) => unreachable!("Statements hold only tokens"),
+        })
+        .collect()
+}
+
+/// Tokens after which `-` is a binary minus.
+fn ends_operand(token_type: &TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::IDENT(_)
+            | TokenType::NUMBER(..)
+            | TokenType::FLOAT(..)
+            | TokenType::STRING(_)
+            | TokenType::RightParenthesis
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::format;
+
+    #[test]
+    fn indents_scopes_and_spaces_operators() {
+        let source =
+            "# header\n{\n  let y=2;\n{let b =8*(y+1)\n\n\n{\n# third scope\n}}{ let c = 10 }\n}";
+
+        assert_eq!(
+            format(source).unwrap(),
+            "\
+# header
+{
+    let y = 2;
+    {
+        let b = 8 * (y + 1);
+
+        {
+            # third scope
+        }
+    }
+    {
+        let c = 10;
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_in_place() {
+        let source = "\n\n## Answer\n##  to everything\nlet   a = 42 ;   # the answer\n\n\n\n#[ block\n   stays ]#\nlet b = a  #[ inline ]#  + 1;;  # after empty\n# last\n\n\n";
+
+        assert_eq!(
+            format(source).unwrap(),
+            "\
+## Answer
+##  to everything
+let a = 42; # the answer
+
+#[ block
+   stays ]#
+let b = a #[ inline ]# + 1; # after empty
+# last
+"
+        );
+
+        // Every line ends with a bare line break, those after comments too
+        assert_eq!(
+            format("## doc\r\nlet b =a+2 ;# c\r\nlet c = b;\r\n").unwrap(),
+            "## doc\nlet b = a + 2; # c\nlet c = b;\n"
+        );
+    }
+
+    #[test]
+    fn continues_broken_lines_with_an_indent() {
+        let source = "{\nlet total = 1 +\n2 as f64\n* -3.5 # why not\n; let u = !(a  ,b) }\n";
+
+        assert_eq!(
+            format(source).unwrap(),
+            "\
+{
+    let total = 1 +
+        2 as f64
+        * -3.5 # why not
+        ;
+    let u = !(a, b);
+}
+"
+        );
+    }
+
+    #[test]
+    fn keeps_the_layout_of_line_statements() {
+        let source = "Career:   Name    Wage  \nPerson: Name Age Career;\nlet a = 1\nPersons -> Jacek 24 Driver    2400,  # first\n           Tomek 30 Architect 5000\nCountries:\nName   GDP   \n  Poland 1000\nCzech  1200 ;\nget   names from persons whose (name is  Jacek)   sorted by wage\nJacek\n\n{\n  get  names from persons\n  Tomek  x\nlet b = -a }\n";
+
+        assert_eq!(
+            format(source).unwrap(),
+            "\
+Career:   Name    Wage
+Person: Name Age Career;
+let a = 1;
+Persons -> Jacek 24 Driver    2400, # first
+           Tomek 30 Architect 5000
+Countries:
+Name   GDP
+Poland 1000
+Czech  1200;
+get names from persons whose (name is Jacek) sorted by wage
+Jacek
+
+{
+    get names from persons
+  Tomek  x
+    let b = -a;
+}
+"
+        );
+    }
+
+    #[test]
+    fn formats_every_sample() {
+        let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples");
+
+        for entry in fs::read_dir(samples).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "x") {
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+
+            // Samples are laid out as the formatter does it, but for a block on one line and
+            // the line breaks at the end
+            let expected = match path.file_name().unwrap().to_str().unwrap() {
+                "main.x" => source.replace(
+                    "    { let c = 10; }\n",
+                    "    {\n        let c = 10;\n    }\n",
+                ),
+                "excel.x" | "over_rust.x" => source.trim_end().to_string() + "\n",
+                _ => source.clone(),
+            };
+            assert_eq!(format(&source).unwrap(), expected, "{path:?}");
+        }
+    }
+
+    #[test]
+    fn is_idempotent_over_samples() {
+        let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples");
+
+        for entry in fs::read_dir(samples).unwrap() {
+            let path = entry.unwrap().path();
+            let once = format(&fs::read_to_string(&path).unwrap()).unwrap();
+
+            assert_eq!(format(&once).unwrap(), once, "{path:?}");
+        }
+    }
+
+    #[test]
+    fn leaves_empty_input_empty() {
+        assert_eq!(format("").unwrap(), "");
+        assert_eq!(format("\n\n  \n").unwrap(), "");
+        assert_eq!(format("let a = 1;\n").unwrap(), "let a = 1;\n");
+    }
+}
@@ -0,0 +1,573 @@
+use std::ops::Range;
+
+use crate::{
+    ast::{StmtId, AST},
+    declarations::{Item, StmtKind},
+    diagnostic::Diagnostic,
+    parser::Parser,
+    symbols::CodeScope,
+    tokenizer::{Span, Tokenizer},
+};
+
+/// How far past its end the tokenizer may look to finish a token, as in `1.5` or `1e+5`.
+const LOOKAHEAD: usize = 2;
+
+/// Replacement of the bytes `start..end` of the previous version with `text`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+impl Edit {
+    /// Where a span of the previous version ends up. Spans around the edit grow or shrink
+    /// with it, the ones after it move, and ends inside the replaced text go to its start.
+    pub fn shift(&self, span: Span) -> Span {
+        let shift = |offset: usize| match offset >= self.end {
+            true => offset + self.text.len() - (self.end - self.start),
+            false => offset.min(self.start),
+        };
+        let start = shift(span.start);
+        Span {
+            start,
+            len: shift(span.end()) - start,
+        }
+    }
+}
+
+/// Token spans of `source`, the new version, reusing `tokens` of the previous one.
+/// Lexing restarts after the last token the edit cannot touch and stops as soon as a
+/// token starts where an old one would after the edit, since from there on the text and
+/// with it the tokens are the same. Also returns which old tokens were replaced.
+pub fn relex(
+    tokens: &[Span],
+    source: &str,
+    edit: &Edit,
+) -> Result<(Vec<Span>, Range<usize>), Diagnostic> {
+    let first = tokens.partition_point(|token| token.end() + LOOKAHEAD <= edit.start);
+    let restart = match first {
+        0 => 0,
+        _ => tokens[first - 1].end(),
+    };
+
+    let mut tokenizer = Tokenizer::new(source);
+    tokenizer.reset_index(restart);
+
+    let edit_end = edit.start + edit.text.len();
+    let mut relexed = vec![];
+    let mut last = tokens.len();
+    for token in tokenizer {
+        let span = token?.span();
+        if span.start >= edit_end {
+            let old = tokens[first..].partition_point(|old| edit.shift(*old).start < span.start);
+            if tokens
+                .get(first + old)
+                .is_some_and(|old| old.start >= edit.end && edit.shift(*old).start == span.start)
+            {
+                last = first + old;
+                break;
+            }
+        }
+        relexed.push(span);
+    }
+
+    let mut result = tokens[..first].to_vec();
+    result.extend(relexed);
+    result.extend(tokens[last..].iter().map(|token| edit.shift(*token)));
+    Ok((result, first..last))
+}
+
+/// Whether the `{` and `}` among `tokens` pair up with each other.
+fn balanced(source: &str, tokens: &[Span]) -> bool {
+    let mut depth = 0;
+    for token in tokens {
+        match &source[token.start..token.end()] {
+            "{" => depth += 1,
+            "}" if depth == 0 => return false,
+            "}" => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// What `Document::edit` had to parse again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reparsed {
+    /// Only trivia changed, spans were moved
+    Nothing,
+    Block(StmtId),
+    Program,
+}
+
+/// Parsed source which is kept up to date with edits, for the language server.
+pub struct Document {
+    source: String,
+    tokens: Vec<Span>,
+    parser: Parser,
+    ast: AST,
+}
+
+impl Document {
+    pub fn new(source: String) -> Result<Self, Diagnostic> {
+        let tokens = Tokenizer::new(&source)
+            .map(|token| token.map(|token| token.span()))
+            .collect::<Result<_, _>>()?;
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser.parse(Tokenizer::new(&source))?;
+
+        Ok(Self {
+            source,
+            tokens,
+            parser,
+            ast,
+        })
+    }
+
+    pub fn ast(&self) -> &AST {
+        &self.ast
+    }
+
+    pub fn scopes(&self) -> &[CodeScope] {
+        self.parser.scopes()
+    }
+
+    /// Applies `edit`, parsing again only the innermost block around the changed tokens, or
+    /// around a changed line break which ends a statement.
+    /// After an error the document is left half updated and has to be built anew.
+    pub fn edit(&mut self, edit: &Edit) -> Result<Reparsed, Diagnostic> {
+        let mut source = self.source.clone();
+        source.replace_range(edit.start..edit.end, &edit.text);
+
+        let (tokens, replaced) = relex(&self.tokens, &source, edit)?;
+        let added = replaced.start..tokens.len() - (self.tokens.len() - replaced.end);
+
+        let block = if replaced.is_empty() && added.is_empty() && !self.moves_line_ends(edit) {
+            None
+        } else if balanced(&self.source, &self.tokens[replaced.clone()])
+            && balanced(&source, &tokens[added])
+        {
+            Some(self.enclosing_block(&replaced))
+        } else {
+            Some(None)
+        };
+
+        self.source = source;
+        self.tokens = tokens;
+
+        let Some(block) = block else {
+            self.shift_spans(edit);
+            return Ok(Reparsed::Nothing);
+        };
+        let Some(block) = block else {
+            *self = Document::new(std::mem::take(&mut self.source))?;
+            return Ok(Reparsed::Program);
+        };
+
+        self.shift_spans(edit);
+        let mut tokenizer = Tokenizer::new(&self.source);
+        tokenizer.reset_index(self.ast[block].span.start);
+        self.parser.reparse_block(tokenizer, &mut self.ast, block)?;
+        Ok(Reparsed::Block(block))
+    }
+
+    /// Whether an edit of trivia adds or removes a line break next to a statement written
+    /// line by line, whose end moves with it.
+    fn moves_line_ends(&self, edit: &Edit) -> bool {
+        if !self.source[edit.start..edit.end].contains('\n') && !edit.text.contains('\n') {
+            return false;
+        }
+
+        // The trivia runs from the end of one token to the start of the next
+        let next = self.tokens.partition_point(|token| token.start < edit.end);
+        let trivia_start = match next {
+            0 => 0,
+            _ => self.tokens[next - 1].end(),
+        };
+        let trivia_end = self
+            .tokens
+            .get(next)
+            .map_or(self.source.len(), |token| token.start);
+
+        self.ast.stmt_arena.iter().any(|(_, stmt)| {
+            matches!(
+                stmt.kind,
+                StmtKind::Record(_)
+                    | StmtKind::Insert(_)
+                    | StmtKind::Table(_)
+                    | StmtKind::Query(_)
+                    | StmtKind::Load(_)
+                    | StmtKind::Save(_)
+            ) && stmt.span.start <= trivia_end
+                && trivia_start <= stmt.span.end()
+        })
+    }
+
+    fn shift_spans(&mut self, edit: &Edit) {
+        for (_, stmt) in self.ast.stmt_arena.iter_mut() {
+            stmt.span = edit.shift(stmt.span);
+        }
+        for (_, expr) in self.ast.expr_arena.iter_mut() {
+            expr.span = edit.shift(expr.span);
+        }
+        self.parser.shift_spans(|span| edit.shift(span));
+    }
+
+    /// Innermost block whose braces are not among the `replaced` tokens but around them.
+    fn enclosing_block(&self, replaced: &Range<usize>) -> Option<StmtId> {
+        let token_at = |offset: usize| {
+            self.tokens
+                .binary_search_by_key(&offset, |token| token.start)
+                .unwrap()
+        };
+        let encloses = |stmt: StmtId| {
+            let span = self.ast[stmt].span;
+            token_at(span.start) < replaced.start && token_at(span.end() - 1) >= replaced.end
+        };
+
+        let mut found = None;
+        let mut stmts: Vec<StmtId> = self
+            .ast
+            .items
+            .iter()
+            .map(|item| match self.ast[*item] {
+                Item::Stmt(stmt) => stmt,
+            })
+            .collect();
+
+        while let Some(&stmt) = stmts
+            .iter()
+            .find(|stmt| matches!(self.ast[**stmt].kind, StmtKind::Block(_)) && encloses(**stmt))
+        {
+            let StmtKind::Block(block) = &self.ast[stmt].kind else {
+                unreachable!();
+            };
+            found = Some(stmt);
+            stmts = block.children.clone();
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{walk_expr, walk_stmt, ExprId, StmtId, Visitor, AST};
+    use crate::ast_dump;
+    use crate::tokenizer::{Span, Tokenizer};
+
+    use super::{relex, Document, Edit, Reparsed};
+
+    /// xorshift, enough to pick edits without a dependency.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    /// Every span in visiting order, since node ids differ between the two parses.
+    #[derive(Default)]
+    struct Spans(Vec<Span>);
+
+    impl Visitor for Spans {
+        fn visit_stmt(&mut self, ast: &AST, id: StmtId) {
+            self.0.push(ast[id].span);
+            walk_stmt(self, ast, id);
+        }
+
+        fn visit_expr(&mut self, ast: &AST, id: ExprId) {
+            self.0.push(ast[id].span);
+            walk_expr(self, ast, id);
+        }
+    }
+
+    fn assert_same(document: &Document, expected: &Document) {
+        assert_eq!(document.tokens, expected.tokens);
+        assert_eq!(
+            ast_dump::sexp(document.ast()),
+            ast_dump::sexp(expected.ast())
+        );
+
+        let (mut spans, mut expected_spans) = (Spans::default(), Spans::default());
+        spans.visit_program(document.ast());
+        expected_spans.visit_program(expected.ast());
+        assert_eq!(spans.0, expected_spans.0);
+
+        assert_eq!(document.scopes(), expected.scopes());
+    }
+
+    /// Program with nested blocks where every use refers to a declaration above it, and
+    /// collections of records declared at the top level.
+    fn program(random: &mut Random) -> String {
+        let mut source = String::new();
+        let mut depth = 0;
+        let mut names = vec![vec![]];
+        // Names and whether rows were added, as queries need
+        let mut collections: Vec<(String, bool)> = vec![];
+
+        for index in 0..12 + random.below(12) {
+            let indent = "    ".repeat(depth);
+            match random.below(9) {
+                6 if depth == 0 => {
+                    source += &format!("R{index}: Name   Size\n");
+                    collections.push((format!("R{index}s"), false));
+                }
+                6 => source += &format!("{indent}# no records in blocks\n"),
+                7 if depth == 0 => {
+                    source += &format!("T{index}s:\nName  Size\na     1\nbc    {index}\n\n");
+                    collections.push((format!("T{index}s"), true));
+                }
+                7 | 8 if !collections.is_empty() => {
+                    let pick = random.below(collections.len());
+                    let (collection, filled) = &mut collections[pick];
+                    source += &match random.below(3) {
+                        0 if *filled => {
+                            format!("{indent}get names from {}\n", collection.to_lowercase())
+                        }
+                        1 => format!("{indent}{collection} -> a 1, b   2\n"),
+                        _ => format!("{indent}{collection} -> a 1,\n{indent}    b   {index}\n"),
+                    };
+                    *filled = true;
+                }
+                7 | 8 => source += &format!("{indent}# no collections yet\n"),
+                0 if depth < 3 => {
+                    source += &format!("{indent}{{\n");
+                    depth += 1;
+                    names.push(vec![]);
+                }
+                1 if depth > 0 => {
+                    source += &format!("{}}}\n", "    ".repeat(depth - 1));
+                    depth -= 1;
+                    names.pop();
+                }
+                2 => source += &format!("{indent}# comment {index}\n"),
+                _ => {
+                    let visible: Vec<&String> = names.iter().flatten().collect();
+                    let value = match visible.len() {
+                        0 => format!("{}", random.below(100)),
+                        len => format!("{} + {}", visible[random.below(len)], random.below(100)),
+                    };
+                    let name = format!("v{index}");
+                    source += &format!("{indent}let {name} = {value};\n");
+                    names.last_mut().unwrap().push(name);
+                }
+            }
+        }
+        for level in (0..depth).rev() {
+            source += &format!("{}}}\n", "    ".repeat(level));
+        }
+        source
+    }
+
+    /// Mostly edits that keep the program valid, now and then anything at all.
+    fn random_edit(random: &mut Random, source: &str) -> Edit {
+        // Offset of one of the `characters` which follows `before`
+        let at = |random: &mut Random, before: &str, characters: &str| {
+            let offsets: Vec<usize> = source
+                .char_indices()
+                .filter(|(offset, char)| {
+                    characters.contains(*char) && source[..*offset].ends_with(before)
+                })
+                .map(|(offset, _)| offset)
+                .collect();
+            match offsets.len() {
+                0 => 0,
+                len => offsets[random.below(len)],
+            }
+        };
+
+        let (start, end, text) = match random.below(7) {
+            0 | 1 => {
+                let start = at(random, "", ";{}") + 1;
+                let text = random.pick(&[" ", "\n", "# note\n", "let z = 3;", "{ let w = 1; }"]);
+                (start, start, text)
+            }
+            2..=4 => {
+                let start = at(random, " ", "0123456789");
+                (start, start + 1, random.pick(&["7", "42", "1.5", "3 * 2"]))
+            }
+            // Line breaks end records, inserts, tables and queries
+            5 => match random.below(2) {
+                0 => {
+                    let start = at(random, "", "\n");
+                    (start, (start + 1).min(source.len()), "")
+                }
+                _ => {
+                    let start = at(random, "", " \n");
+                    (start, start, random.pick(&["\n", "\n\n", "   \n  "]))
+                }
+            },
+            _ => {
+                let boundaries: Vec<usize> = (0..=source.len())
+                    .filter(|index| source.is_char_boundary(*index))
+                    .collect();
+                let start = boundaries[random.below(boundaries.len())];
+                let end = match random.below(3) {
+                    0 => start,
+                    _ => (start + random.below(6)).min(source.len()),
+                };
+                let text = random.pick(&[
+                    "", " ", "\n", "1", "0", "+ 2", "* v0", "v", "e", ".5", "=", ";", "{", "}",
+                    "#", "#[", "]#", "##", "\"s\"", "ż", "(", ")",
+                ]);
+                (start, end, text)
+            }
+        };
+
+        Edit {
+            start,
+            end: if source.is_char_boundary(end) {
+                end
+            } else {
+                start
+            },
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn relexes_only_around_the_edit() {
+        let source = "let a = 1;\nlet b = a + 22;\nlet c = 3;\n";
+        let tokens: Vec<Span> = Tokenizer::new(source)
+            .map(|token| token.unwrap().span())
+            .collect();
+
+        // `22` becomes `2.5`
+        let edit = Edit {
+            start: 23,
+            end: 25,
+            text: String::from("2.5"),
+        };
+        let mut changed = source.to_string();
+        changed.replace_range(23..25, "2.5");
+
+        let (relexed, replaced) = relex(&tokens, &changed, &edit).unwrap();
+        let full: Vec<Span> = Tokenizer::new(&changed)
+            .map(|token| token.unwrap().span())
+            .collect();
+        assert_eq!(relexed, full);
+        // `a` and `+` end close enough to the edit to be lexed again
+        assert_eq!(replaced, 9..11);
+    }
+
+    #[test]
+    fn reparses_the_innermost_block() {
+        let source = "let a = 1;\n{\n    let b = a;\n    { let c = b; }\n    { let d = 2; }\n}\nlet e = a;\n";
+        let mut document = Document::new(source.to_string()).unwrap();
+
+        // `let c = b;` becomes `let c = b * 10;`
+        let at = source.find("b; }").unwrap() + 1;
+        let edit = Edit {
+            start: at,
+            end: at,
+            text: String::from(" * 10"),
+        };
+        assert!(matches!(document.edit(&edit), Ok(Reparsed::Block(_))));
+        assert_same(&document, &Document::new(document.source.clone()).unwrap());
+
+        // Inside a comment nothing is parsed again
+        let edit = Edit {
+            start: 0,
+            end: 0,
+            text: String::from("# let q = 1;\n"),
+        };
+        assert_eq!(document.edit(&edit), Ok(Reparsed::Nothing));
+        assert_same(&document, &Document::new(document.source.clone()).unwrap());
+
+        // A declaration below the block is not visible inside it
+        let at = document.source.find("let d = 2").unwrap() + 8;
+        let edit = Edit {
+            start: at,
+            end: at + 1,
+            text: String::from("e"),
+        };
+        assert_eq!(
+            document.edit(&edit).unwrap_err().message,
+            "Unknown symbol e"
+        );
+    }
+
+    #[test]
+    fn reparses_when_line_breaks_end_statements() {
+        let source = "Career: Name Wage\nCareers -> Driver   2400\n\nCountries:\nName     GDP\nPoland   1000\nCzech    1200\n\nget names from countries\n";
+        let after = |text: &str| source.find(text).unwrap() + text.len();
+
+        let edits = [
+            // A blank line inside the table
+            (after("1000\n"), 0, "\n"),
+            // No blank line ending it
+            (after("1200\n"), 1, ""),
+            (after("Driver   "), 0, "\n  "),
+        ];
+        for (start, removed, text) in edits {
+            let mut document = Document::new(source.to_string()).unwrap();
+            let edit = Edit {
+                start,
+                end: start + removed,
+                text: text.to_string(),
+            };
+            let mut changed = source.to_string();
+            changed.replace_range(edit.start..edit.end, &edit.text);
+
+            match (document.edit(&edit), Document::new(changed)) {
+                (Ok(reparsed), Ok(expected)) => {
+                    assert_ne!(reparsed, Reparsed::Nothing);
+                    assert_same(&document, &expected);
+                }
+                (Err(error), Err(expected)) => assert_eq!(error, expected),
+                (edited, expected) => panic!("{edit:?} gave {edited:?}, not {:?}", expected.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn matches_a_full_reparse_after_random_edits() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        let mut compared = 0;
+
+        for _ in 0..200 {
+            let mut source = program(&mut random);
+            let mut document = Document::new(source.clone()).unwrap();
+
+            for _ in 0..20 {
+                let edit = random_edit(&mut random, &source);
+                source.replace_range(edit.start..edit.end, &edit.text);
+
+                let expected = Document::new(source.clone());
+                let edited = document.edit(&edit);
+
+                match (edited, expected) {
+                    (Ok(_), Ok(expected)) => {
+                        assert_same(&document, &expected);
+                        compared += 1;
+                    }
+                    (Err(_), Err(_)) => break,
+                    (edited, expected) => panic!(
+                        "Edit {edit:?} gave {:?}, a full parse {}:\n{source}",
+                        edited.map_err(|_| "an error"),
+                        if expected.is_ok() {
+                            "succeeded"
+                        } else {
+                            "failed"
+                        },
+                    ),
+                }
+            }
+        }
+
+        assert!(
+            compared > 500,
+            "only {compared} edits kept the program valid"
+        );
+    }
+}
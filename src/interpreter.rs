@@ -0,0 +1,1170 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{
+    ast::{walk_expr, walk_stmt, ExprId, StmtId, Visitor, AST},
+    csv,
+    declarations::{
+        BinaryOp, ExprKind, FieldType, Function, InsertStmt, Literals, Order, Predicate,
+        Projection, Query, SaveStmt, StmtKind, Type,
+    },
+    diagnostic::Diagnostic,
+    plan::{self, Condition, JoinMethod, Plan},
+    records::{Column, Records},
+    tokenizer::{FloatType, IntType},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Integer of the given width, held in 64 bits as `IntType::wrap` leaves it
+    Number(i64, IntType),
+    Float(f64),
+    Float32(f32),
+    Str(String),
+    /// Record type name and the values of its fields
+    Record(String, Vec<Value>),
+    /// Result of a query: the widths its columns were declared with, and the values it
+    /// asked for of every row it kept
+    Rows(Vec<usize>, Vec<Vec<Value>>),
+}
+
+/// Rows appended to a collection, each a `Value::Record`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub record: String,
+    pub rows: Vec<Value>,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(value, int_type) => write!(f, "{}", int_type.read(*value)),
+            Value::Float(value) => write!(f, "{value:?}"),
+            Value::Float32(value) => write!(f, "{value:?}"),
+            Value::Str(value) => write!(f, "\"{value}\""),
+            Value::Record(name, fields) => {
+                let fields: Vec<String> = fields.iter().map(Value::to_string).collect();
+                write!(f, "{name}({})", fields.join(", "))
+            }
+            Value::Rows(widths, rows) => {
+                let rows: Vec<Vec<String>> = rows
+                    .iter()
+                    .map(|row| row.iter().map(Value::to_bare_string).collect())
+                    .collect();
+                if widths.len() == 1 {
+                    return write!(f, "{}", rows.concat().join(", "));
+                }
+
+                // Lined up like a table, two spaces apart at least
+                let widths: Vec<usize> = widths
+                    .iter()
+                    .enumerate()
+                    .map(|(column, width)| {
+                        let widest = rows.iter().map(|row| row[column].chars().count()).max();
+                        (*width).max(widest.unwrap_or(0) + 2)
+                    })
+                    .collect();
+                let lines: Vec<String> = rows
+                    .iter()
+                    .map(|row| {
+                        let mut line = String::new();
+                        for (value, width) in row.iter().zip(&widths) {
+                            line += &format!("{value:width$}");
+                        }
+                        line.trim_end().to_string()
+                    })
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+        }
+    }
+}
+
+impl Value {
+    fn from_literal(literal: &Literals) -> Self {
+        match literal {
+            Literals::NUMBER(value, int_type) => Value::Number(int_type.parse(value), *int_type),
+            Literals::FLOAT(value, FloatType::F64) => {
+                Value::Float(value.parse().expect("Float literal is not a valid f64"))
+            }
+            Literals::FLOAT(value, FloatType::F32) => {
+                Value::Float32(value.parse().expect("Float literal is not a valid f32"))
+            }
+            Literals::STR(value) => Value::Str(value.clone()),
+        }
+    }
+
+    /// Scalars as the literals they would be written with.
+    fn to_literal(&self) -> Literals {
+        match self {
+            Value::Number(value, int_type) => {
+                Literals::NUMBER(int_type.read(*value).to_string(), *int_type)
+            }
+            Value::Float(value) => Literals::FLOAT(format!("{value:?}"), FloatType::F64),
+            Value::Float32(value) => Literals::FLOAT(format!("{value:?}"), FloatType::F32),
+            Value::Str(value) => Literals::STR(value.clone()),
+            value @ (Value::Record(..) | Value::Rows(..)) => panic!("{value} is not a scalar"),
+        }
+    }
+
+    fn ty(&self) -> Type {
+        match self {
+            Value::Number(_, int_type) => Type::Int(*int_type),
+            Value::Float(_) => Type::Float(FloatType::F64),
+            Value::Float32(_) => Type::Float(FloatType::F32),
+            Value::Str(_) => Type::Str,
+            value @ (Value::Record(..) | Value::Rows(..)) => panic!("{value} is not a scalar"),
+        }
+    }
+
+    /// Strings without quotes and whole floats without a fraction, as listed in query
+    /// results.
+    fn to_bare_string(&self) -> String {
+        match self {
+            Value::Str(value) => value.clone(),
+            Value::Float(value) if value.is_finite() && value.fract() == 0.0 => {
+                format!("{value:.0}")
+            }
+            Value::Float32(value) if value.is_finite() && value.fract() == 0.0 => {
+                format!("{value:.0}")
+            }
+            value => value.to_string(),
+        }
+    }
+
+    /// Scalars of the same kind compare, anything else is equal.
+    fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Number(a, int_type), Value::Number(b, _)) => {
+                int_type.read(*a).cmp(&int_type.read(*b))
+            }
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Float32(a), Value::Float32(b)) => a.total_cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+/// Evaluates AST nodes directly instead of compiling them.
+/// Variables live in a map keyed by their stack offset, just like in the assembled frame.
+#[derive(Default)]
+pub struct Interpreter {
+    stack: HashMap<u16, Value>,
+    records: Records,
+    /// Collections by name
+    tables: HashMap<String, Table>,
+    /// Results of the expressions visited so far, consumed by their parents
+    operands: Vec<Value>,
+    /// Results reported back to the caller of `run`
+    bindings: Vec<(String, Value)>,
+    /// Error which stopped the program, nothing runs after it
+    error: Option<Diagnostic>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the program and returns every binding made along the way, in order.
+    /// Top level expressions are reported with an empty name.
+    pub fn run(&mut self, ast: &AST) -> Result<Vec<(String, Value)>, Diagnostic> {
+        self.visit_program(ast);
+        let bindings = std::mem::take(&mut self.bindings);
+        match self.error.take() {
+            Some(error) => {
+                self.operands.clear();
+                Err(error)
+            }
+            None => Ok(bindings),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.operands.pop().expect("Expression produced no value")
+    }
+
+    /// Builds the rows from the values on the operand stack and appends them.
+    fn insert(&mut self, insert: &InsertStmt) {
+        let count = insert.rows.iter().map(Vec::len).sum::<usize>();
+        let mut values = self
+            .operands
+            .split_off(self.operands.len() - count)
+            .into_iter();
+        let rows = insert
+            .rows
+            .iter()
+            .map(|row| values.by_ref().take(row.len()).collect())
+            .collect();
+        self.append(&insert.collection, &insert.record, rows);
+    }
+
+    /// Appends rows of values to a collection as records of type `record`.
+    fn append(&mut self, collection: &str, record: &str, rows: Vec<Vec<Value>>) {
+        // Fields declared without a type take that of their first value, as when checked
+        if let Some(row) = rows.first() {
+            for (column, value) in self.records.columns(record).into_iter().zip(row) {
+                if column.ty.is_none() {
+                    self.records.set_type(record, &column.path, value.ty());
+                }
+            }
+        }
+        let table = self
+            .tables
+            .entry(collection.to_string())
+            .or_insert_with(|| Table {
+                record: record.to_string(),
+                rows: vec![],
+            });
+        for row in rows {
+            let mut row_values = row.into_iter();
+            let value = build(&self.records, record, &mut row_values);
+            if row_values.next().is_some() {
+                panic!("Row of {record} has too many values");
+            }
+            table.rows.push(value);
+        }
+    }
+
+    /// Writes the rows of the query under a header of what it asks for, every column of the
+    /// rows without a list.
+    fn save(&mut self, ast: &AST, save: &SaveStmt) -> Result<(), String> {
+        let query = &save.query;
+        let mut header: Vec<String> = match query.fields.as_slice() {
+            [] => self
+                .records
+                .joined_columns(&query.records())
+                .into_iter()
+                .map(|column| column.name)
+                .collect(),
+            fields => fields
+                .iter()
+                .flat_map(|field| match field {
+                    Projection::Field(field) => self.listed(query, &field.word),
+                    Projection::Aggregate(aggregate) => vec![aggregate.label()],
+                })
+                .collect(),
+        };
+        if let Some(group) = self.unlisted_group(query) {
+            header.insert(0, group.name);
+        }
+        let Value::Rows(_, rows) = self.query(ast, query) else {
+            unreachable!("Queries produce rows");
+        };
+        let rows: Vec<Vec<Literals>> = rows
+            .iter()
+            .map(|row| row.iter().map(Value::to_literal).collect())
+            .collect();
+        csv::save(&save.path, &header, &rows)
+    }
+
+    /// Header of the columns a listed word stands for, the word itself unless it names a
+    /// nested record.
+    fn listed(&self, query: &Query, word: &str) -> Vec<String> {
+        match self.records.listed(&query.records(), word) {
+            Ok(columns) if columns.len() > 1 => {
+                columns.into_iter().map(|column| column.name).collect()
+            }
+            _ => vec![word.to_string()],
+        }
+    }
+
+    /// The column rows are grouped by, unless a listed field holds it already.
+    fn unlisted_group(&self, query: &Query) -> Option<Column> {
+        let records = query.records();
+        let group = self
+            .records
+            .resolve_joined(&records, &query.group.as_ref()?.word)
+            .ok()?;
+        let listed = query.fields.iter().any(|field| match field {
+            Projection::Field(field) => self
+                .records
+                .listed(&records, &field.word)
+                .is_ok_and(|columns| columns.iter().any(|column| column.path == group.path)),
+            Projection::Aggregate(_) => false,
+        });
+        (!listed).then_some(group)
+    }
+
+    fn rows(&self, collection: &str) -> Vec<&Value> {
+        self.tables
+            .get(collection)
+            .map(|table| table.rows.iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Runs the plan of the query, given the rows of each collection so far.
+    fn query(&mut self, ast: &AST, query: &Query) -> Value {
+        // The plan holds the literals the filter tests against
+        let tested = query
+            .filter
+            .as_ref()
+            .map_or(0, |filter| filter.values().len());
+        self.operands.truncate(self.operands.len() - tested);
+
+        let rows = self
+            .tables
+            .iter()
+            .map(|(name, table)| (name.clone(), Some(table.rows.len())))
+            .collect();
+        self.execute(&plan::plan_query(ast, &self.records, &rows, query))
+    }
+
+    /// Values of the outputs of a plan for every row or group it gives, in order.
+    pub fn execute(&self, plan: &Plan) -> Value {
+        let records = plan.records().len();
+        let (columns, rows): (Vec<Option<&Column>>, Vec<Vec<Value>>) = match plan {
+            Plan::Project { input, columns } => {
+                let rows = self
+                    .scan(input, records)
+                    .iter()
+                    .map(|row| {
+                        columns
+                            .iter()
+                            .map(|column| lookup(row, &column.path).clone())
+                            .collect()
+                    })
+                    .collect();
+                (columns.iter().map(Some).collect(), rows)
+            }
+            Plan::Aggregate {
+                input,
+                group,
+                outputs,
+            } => {
+                let rows = self.scan(input, records);
+                let groups = match group {
+                    Some(group) => {
+                        let mut groups: Vec<Vec<&Row>> = vec![];
+                        for row in &rows {
+                            let key = lookup(row, &group.path);
+                            match groups
+                                .iter_mut()
+                                .find(|group_rows| lookup(group_rows[0], &group.path) == key)
+                            {
+                                Some(group_rows) => group_rows.push(row),
+                                None => groups.push(vec![row]),
+                            }
+                        }
+                        groups
+                    }
+                    None => vec![rows.iter().collect()],
+                };
+
+                // Groups without a value for some output give no row, as an average of nothing
+                let rows = groups
+                    .iter()
+                    .filter_map(|group| {
+                        outputs
+                            .iter()
+                            .map(|output| {
+                                let values: Vec<&Value> = group
+                                    .iter()
+                                    .map(|row| match &output.column {
+                                        Some(column) => lookup(row, &column.path),
+                                        None => row.iter().flatten().next().unwrap(),
+                                    })
+                                    .collect();
+                                match output.function {
+                                    Some(function) => aggregate(function, &values),
+                                    None => values.first().map(|value| (*value).clone()),
+                                }
+                            })
+                            .collect()
+                    })
+                    .collect();
+                (
+                    outputs
+                        .iter()
+                        .map(|output| output.column.as_ref())
+                        .collect(),
+                    rows,
+                )
+            }
+            plan => panic!("Plans end with an aggregate or a projection, not {plan:?}"),
+        };
+        let widths = columns
+            .iter()
+            .map(|column| column.map_or(0, |column| column.width))
+            .collect();
+        Value::Rows(widths, rows)
+    }
+
+    /// Rows an operator gives, each with the row of every collection read so far in the
+    /// place of its record.
+    fn scan(&self, plan: &Plan, records: usize) -> Vec<Row<'_>> {
+        match plan {
+            Plan::Scan {
+                collection, index, ..
+            } => self
+                .rows(collection)
+                .into_iter()
+                .map(|value| {
+                    let mut row = vec![None; records];
+                    row[*index] = Some(value);
+                    row
+                })
+                .collect(),
+            Plan::Filter { input, condition } => {
+                let mut rows = self.scan(input, records);
+                rows.retain(|row| passes(condition, row));
+                rows
+            }
+            Plan::Join {
+                left,
+                right,
+                keys,
+                method,
+            } => {
+                let right = self.scan(right, records);
+                let mut rows = vec![];
+                match method {
+                    // Values of different types never meet, the checker makes both sides one type
+                    JoinMethod::Hash => {
+                        let mut table: HashMap<String, Vec<&Row>> = HashMap::new();
+                        for other in &right {
+                            let key = lookup(other, &keys.1.path).to_string();
+                            table.entry(key).or_default().push(other);
+                        }
+                        for row in self.scan(left, records) {
+                            let key = lookup(&row, &keys.0.path).to_string();
+                            for other in table.get(&key).into_iter().flatten() {
+                                rows.push(joined(&row, other));
+                            }
+                        }
+                    }
+                    JoinMethod::NestedLoop => {
+                        for row in self.scan(left, records) {
+                            let key = lookup(&row, &keys.0.path);
+                            for other in &right {
+                                if lookup(other, &keys.1.path) == key {
+                                    rows.push(joined(&row, other));
+                                }
+                            }
+                        }
+                    }
+                }
+                rows
+            }
+            Plan::Sort {
+                input,
+                column,
+                order,
+            } => {
+                let mut rows = self.scan(input, records);
+                rows.sort_by(|a, b| {
+                    ordered(lookup(a, &column.path), lookup(b, &column.path), *order)
+                });
+                rows
+            }
+            Plan::Limit { input, count, ties } => {
+                let mut rows = self.scan(input, records);
+                // Only sorted rows are tied, on the column they are sorted by
+                let tied = match input.as_ref() {
+                    Plan::Sort { column, .. } if *ties => Some(column),
+                    _ => None,
+                };
+                let mut kept = (*count).min(rows.len());
+                if let Some(column) = tied.filter(|_| kept > 0) {
+                    let last = lookup(&rows[kept - 1], &column.path);
+                    while kept < rows.len() && lookup(&rows[kept], &column.path) == last {
+                        kept += 1;
+                    }
+                }
+                rows.truncate(kept);
+                rows
+            }
+            Plan::TopK {
+                input,
+                column,
+                order,
+                count,
+            } => {
+                let mut rows = self.scan(input, records);
+                let mut values: Vec<&Value> =
+                    rows.iter().map(|row| lookup(row, &column.path)).collect();
+                if values.len() > *count && *count > 0 {
+                    let (_, last, _) =
+                        values.select_nth_unstable_by(*count - 1, |a, b| ordered(a, b, *order));
+                    let last = *last;
+                    rows.retain(|row| ordered(lookup(row, &column.path), last, *order).is_le());
+                } else if *count == 0 {
+                    rows.clear();
+                }
+                // Only the rows kept are sorted, ties in the order they came
+                rows.sort_by(|a, b| {
+                    ordered(lookup(a, &column.path), lookup(b, &column.path), *order)
+                });
+                rows
+            }
+            Plan::Aggregate { .. } | Plan::Project { .. } => {
+                panic!("Only the last operator of a plan gives values")
+            }
+        }
+    }
+}
+
+/// Row of a plan: the row of each collection it read, in the order of their records.
+type Row<'a> = Vec<Option<&'a Value>>;
+
+/// Row of both rows, each holding the rows of different collections.
+fn joined<'a>(row: &[Option<&'a Value>], other: &[Option<&'a Value>]) -> Row<'a> {
+    row.iter().zip(other).map(|(a, b)| a.or(*b)).collect()
+}
+
+/// Value of a column in a row of a plan.
+fn lookup<'a>(row: &[Option<&'a Value>], path: &[usize]) -> &'a Value {
+    let read = "Columns are read after the rows holding them";
+    match row {
+        [record] => field(record.expect(read), path),
+        _ => field(row[path[0]].expect(read), &path[1..]),
+    }
+}
+
+fn ordered(a: &Value, b: &Value, order: Order) -> Ordering {
+    match order {
+        Order::Asc => a.compare(b),
+        Order::Desc => a.compare(b).reverse(),
+    }
+}
+
+/// Aggregates the values, none for no values apart from their count. Averages are f64
+/// whatever the values, so those of integers keep their fraction.
+fn aggregate(function: Function, values: &[&Value]) -> Option<Value> {
+    let values = values.iter().map(|value| (*value).clone());
+    match function {
+        Function::Count => Some(Value::Number(values.len() as i64, IntType::I64)),
+        Function::Min => values.reduce(|min, value| match value.compare(&min) {
+            Ordering::Less => value,
+            _ => min,
+        }),
+        Function::Max => values.reduce(|max, value| match value.compare(&max) {
+            Ordering::Greater => value,
+            _ => max,
+        }),
+        Function::Sum => values.reduce(|sum, value| binary(BinaryOp::Add, sum, value)),
+        Function::Avg => {
+            let count = values.len() as f64;
+            let sum = values
+                .map(|value| match cast(value, Type::Float(FloatType::F64)) {
+                    Value::Float(value) => value,
+                    _ => unreachable!("Casts to f64 give floats"),
+                })
+                .reduce(|sum, value| sum + value)?;
+            Some(Value::Float(sum / count))
+        }
+    }
+}
+
+fn passes(condition: &Condition, row: &[Option<&Value>]) -> bool {
+    let (column, predicate, value) = match condition {
+        Condition::And(lhs, rhs) => return passes(lhs, row) && passes(rhs, row),
+        Condition::Or(lhs, rhs) => return passes(lhs, row) || passes(rhs, row),
+        Condition::Test {
+            column,
+            predicate,
+            value,
+            ..
+        } => (column, *predicate, value),
+    };
+
+    let actual = lookup(row, &column.path);
+    let expected = &Value::from_literal(value);
+    match (predicate, actual, expected) {
+        (Predicate::StartsWith, Value::Str(actual), Value::Str(prefix)) => {
+            actual.starts_with(prefix)
+        }
+        (Predicate::EndsWith, Value::Str(actual), Value::Str(suffix)) => actual.ends_with(suffix),
+        (Predicate::Contains, Value::Str(actual), Value::Str(part)) => actual.contains(part),
+        (Predicate::Eq, _, _) => actual == expected,
+        (Predicate::Ne, _, _) => actual != expected,
+        (Predicate::Lt, _, _) => actual.compare(expected).is_lt(),
+        (Predicate::Le, _, _) => actual.compare(expected).is_le(),
+        (Predicate::Gt, _, _) => actual.compare(expected).is_gt(),
+        (Predicate::Ge, _, _) => actual.compare(expected).is_ge(),
+        (predicate, _, _) => panic!("{} tests text, found {actual}", predicate.symbol()),
+    }
+}
+
+/// Value reached through the fields of nested records.
+fn field<'a>(value: &'a Value, path: &[usize]) -> &'a Value {
+    match (value, path) {
+        (_, []) => value,
+        (Value::Record(_, fields), [index, rest @ ..]) => field(&fields[*index], rest),
+        _ => panic!("{value} has no fields"),
+    }
+}
+
+/// Fills the fields of record `name` in order, nested records included.
+fn build(records: &Records, name: &str, values: &mut impl Iterator<Item = Value>) -> Value {
+    let record = records
+        .get(name)
+        .unwrap_or_else(|| panic!("Unknown record {name}"));
+
+    let fields = record
+        .fields
+        .iter()
+        .map(|field| match &field.ty {
+            Some(FieldType::Record(nested)) => build(records, nested, values),
+            _ => values
+                .next()
+                .unwrap_or_else(|| panic!("Row of {name} has too few values")),
+        })
+        .collect();
+    Value::Record(name.to_string(), fields)
+}
+
+fn cast(value: Value, ty: Type) -> Value {
+    let value = match (value, ty) {
+        (Value::Number(value, from), Type::Int(to)) => {
+            return Value::Number(to.wrap(from.read(value)), to)
+        }
+        (Value::Number(value, int_type), _) => int_type.read(value) as f64,
+        (Value::Float(value), _) => value,
+        (Value::Float32(value), _) => value as f64,
+        (Value::Str(_), _) => panic!("Strings cannot be cast to {}", ty.name()),
+        (Value::Record(name, _), _) => {
+            panic!("Records of {name} cannot be cast to {}", ty.name())
+        }
+        (Value::Rows(..), _) => panic!("Query results cannot be cast to {}", ty.name()),
+    };
+
+    match ty {
+        Type::Int(int_type) => Value::Number(int_type.wrap(value as i128), int_type),
+        Type::Float(FloatType::F64) => Value::Float(value),
+        Type::Float(FloatType::F32) => Value::Float32(value as f32),
+        Type::Str => panic!("Numbers cannot be cast to str"),
+    }
+}
+
+/// Integer division by zero is left to the caller, which reports it.
+fn binary(op: BinaryOp, lhs: Value, rhs: Value) -> Value {
+    match (lhs, rhs) {
+        (Value::Number(lhs, int_type), Value::Number(rhs, _)) => {
+            let (lhs, rhs) = (int_type.read(lhs), int_type.read(rhs));
+            let value = match op {
+                BinaryOp::Add => lhs + rhs,
+                BinaryOp::Sub => lhs - rhs,
+                BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                BinaryOp::Div => lhs / rhs,
+            };
+            Value::Number(int_type.wrap(value), int_type)
+        }
+        (Value::Float(lhs), Value::Float(rhs)) => Value::Float(match op {
+            BinaryOp::Add => lhs + rhs,
+            BinaryOp::Sub => lhs - rhs,
+            BinaryOp::Mul => lhs * rhs,
+            BinaryOp::Div => lhs / rhs,
+        }),
+        (Value::Float32(lhs), Value::Float32(rhs)) => Value::Float32(match op {
+            BinaryOp::Add => lhs + rhs,
+            BinaryOp::Sub => lhs - rhs,
+            BinaryOp::Mul => lhs * rhs,
+            BinaryOp::Div => lhs / rhs,
+        }),
+        _ => panic!(
+            "Operator '{}' needs numbers of the same type on both sides",
+            op.symbol()
+        ),
+    }
+}
+
+impl Visitor for Interpreter {
+    fn visit_stmt(&mut self, ast: &AST, id: StmtId) {
+        if self.error.is_some() {
+            return;
+        }
+        walk_stmt(self, ast, id);
+        if self.error.is_some() {
+            return;
+        }
+
+        match &ast[id].kind {
+            StmtKind::Let(decl) => {
+                let value = self.pop();
+                self.stack.insert(decl.stack_offset, value.clone());
+                self.bindings.push((decl.identifier.clone(), value));
+            }
+            StmtKind::Expr(_) => {
+                let value = self.pop();
+                self.bindings.push((String::new(), value));
+            }
+            StmtKind::Record(decl) => self.records.declare(decl),
+            StmtKind::Insert(insert) => self.insert(insert),
+            StmtKind::Table(table) => {
+                self.records.declare(&table.record);
+                self.insert(&table.insert);
+            }
+            StmtKind::Query(query) => {
+                let rows = self.query(ast, query);
+                self.bindings.push((String::new(), rows));
+            }
+            StmtKind::Load(load) => {
+                let sheet = match csv::load(&load.path, &load.record, ast[id].span) {
+                    Ok(sheet) => sheet,
+                    Err(message) => {
+                        self.error = Some(Diagnostic::new(ast[id].span, message));
+                        return;
+                    }
+                };
+                self.records.declare(&sheet.record);
+                let rows = sheet
+                    .rows
+                    .iter()
+                    .map(|row| row.iter().map(Value::from_literal).collect())
+                    .collect();
+                self.append(&load.collection, &load.record, rows);
+            }
+            StmtKind::Save(save) => {
+                if let Err(message) = self.save(ast, save) {
+                    self.error = Some(Diagnostic::new(ast[id].span, message));
+                }
+            }
+            StmtKind::Block(_) => {}
+        }
+    }
+
+    fn visit_expr(&mut self, ast: &AST, id: ExprId) {
+        walk_expr(self, ast, id);
+        if self.error.is_some() {
+            return;
+        }
+
+        let value = match &ast[id].kind {
+            ExprKind::Literal(literal) => Value::from_literal(literal),
+            ExprKind::Variable(var) => self
+                .stack
+                .get(&var.stack_offset)
+                .cloned()
+                .unwrap_or_else(|| panic!("Variable {} has no value", var.identifier)),
+            ExprKind::Binary(op, _, _) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                if *op == BinaryOp::Div && matches!(rhs, Value::Number(0, _)) {
+                    self.error = Some(Diagnostic::new(ast[id].span, "Division by zero"));
+                    return;
+                }
+                binary(*op, lhs, rhs)
+            }
+            ExprKind::Cast(_, ty) => {
+                let value = self.pop();
+                cast(value, *ty)
+            }
+        };
+
+        self.operands.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::declarations::{Item, StmtKind};
+    use crate::parser::Parser;
+    use crate::plan;
+    use crate::symbols::CodeScope;
+    use crate::tokenizer::{IntType, Tokenizer};
+
+    use super::{Interpreter, Value};
+
+    #[test]
+    fn binds_variables_to_stack_slots() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser
+            .parse(Tokenizer::new("let x = 123; let y = 456;"))
+            .unwrap();
+
+        let mut interpreter = Interpreter::new();
+        let bindings = interpreter.run(&ast).unwrap();
+
+        assert_eq!(
+            bindings,
+            [
+                (String::from("x"), Value::Number(123, IntType::I64)),
+                (String::from("y"), Value::Number(456, IntType::I64)),
+            ]
+        );
+        assert_eq!(
+            interpreter.stack.get(&0),
+            Some(&Value::Number(123, IntType::I64))
+        );
+        assert_eq!(
+            interpreter.stack.get(&16),
+            Some(&Value::Number(456, IntType::I64))
+        );
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser
+            .parse(Tokenizer::new(
+                "let x = 1000;\nlet y = 2000;\nlet z = x + 2 * y;\n{ let w = z / 10 - 1; }\n",
+            ))
+            .unwrap();
+
+        let bindings = Interpreter::new().run(&ast).unwrap();
+
+        assert_eq!(
+            bindings[2],
+            (String::from("z"), Value::Number(5000, IntType::I64))
+        );
+        assert_eq!(
+            bindings[3],
+            (String::from("w"), Value::Number(499, IntType::I64))
+        );
+    }
+
+    #[test]
+    fn wraps_integers_at_their_width() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser.parse(Tokenizer::new(
+            "let a = 200u8;\nlet b = a + 100u8;\nlet c = 0i8 - 128 as i8;\nlet d = 18446744073709551615u64;\n",
+        )).unwrap();
+
+        let bindings: Vec<String> = Interpreter::new()
+            .run(&ast)
+            .unwrap()
+            .iter()
+            .map(|(name, value)| format!("{name} = {value}"))
+            .collect();
+
+        assert_eq!(
+            bindings,
+            ["a = 200", "b = 44", "c = -128", "d = 18446744073709551615"]
+        );
+    }
+
+    #[test]
+    fn stores_rows_of_collections() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser.parse(Tokenizer::new(
+            "Career: Name Wage\nPerson: Name Age Career\n\nPersons -> Jacek 24 Driver 2400, Tomek 30 Architect 5000\nPersons -> Ala -1 \"Data scientist\"    7000,\n           Ola 22 Intern 0\n",
+        )).unwrap();
+
+        let mut interpreter = Interpreter::new();
+        assert!(interpreter.run(&ast).unwrap().is_empty());
+
+        let table = &interpreter.tables["Persons"];
+        assert_eq!(table.record, "Person");
+        let rows: Vec<String> = table.rows.iter().map(Value::to_string).collect();
+        assert_eq!(
+            rows,
+            [
+                "Person(\"Jacek\", 24, Career(\"Driver\", 2400))",
+                "Person(\"Tomek\", 30, Career(\"Architect\", 5000))",
+                "Person(\"Ala\", -1, Career(\"Data scientist\", 7000))",
+                "Person(\"Ola\", 22, Career(\"Intern\", 0))",
+            ]
+        );
+
+        // Rows appended again are kept
+        let ast = parser
+            .parse(Tokenizer::new("Persons -> Ola 22 Intern 0\n"))
+            .unwrap();
+        interpreter.run(&ast).unwrap();
+        assert_eq!(interpreter.tables["Persons"].rows.len(), 5);
+
+        let source = std::fs::read_to_string("samples/excel.x").unwrap();
+        let table: String = source
+            .lines()
+            .take(6)
+            .map(|line| line.to_owned() + "\n")
+            .collect();
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(&table))
+            .unwrap();
+        interpreter.run(&ast).unwrap();
+        let rows: Vec<String> = interpreter.tables["Countries"]
+            .rows
+            .iter()
+            .map(Value::to_string)
+            .collect();
+        assert_eq!(rows[3], "Country(\"Hungary\", \"HUN\", 800)");
+    }
+
+    #[test]
+    fn runs_queries_over_collections() {
+        let source = std::fs::read_to_string("samples/over_rust.x").unwrap();
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(&source))
+            .unwrap();
+
+        let mut interpreter = Interpreter::new();
+        let results = interpreter.run(&ast).unwrap();
+        // The sample appends the same persons on one line and across two. Rows appended
+        // twice are held twice, so it lists each of them twice, though its comment expects
+        // `Tomek, Jacek`.
+        let rows = &interpreter.tables["Persons"].rows;
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[..2], rows[2..]);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1.to_string(), "Tomek, Tomek, Jacek, Jacek");
+
+        let cases = [
+            (
+                "get names from persons sorted by wage asc",
+                "Jacek, Jacek, Tomek, Tomek",
+            ),
+            (
+                "Get Name and Age FROM Person sorted by names",
+                "Jacek  24\nJacek  24\nTomek  30\nTomek  30",
+            ),
+            (
+                "get names, wages from persons",
+                "Jacek  2400\nTomek  5000\nJacek  2400\nTomek  5000",
+            ),
+            (
+                "get careers from persons sorted by wage",
+                "Architect  5000\nArchitect  5000\nDriver     2400\nDriver     2400",
+            ),
+        ];
+        for (query, expected) in cases {
+            let mut parser = Parser::new(CodeScope::global());
+            parser.parse(Tokenizer::new(&source)).unwrap();
+            let ast = parser.parse(Tokenizer::new(query)).unwrap();
+            let results = interpreter.run(&ast).unwrap();
+            assert_eq!(results[0].1.to_string(), expected, "{query}");
+        }
+    }
+
+    #[test]
+    fn filters_rows_of_queries() {
+        let source = std::fs::read_to_string("samples/excel.x").unwrap();
+        let table: String = source
+            .lines()
+            .take(6)
+            .map(|line| line.to_owned() + "\n")
+            .collect();
+        let mut parser = Parser::new(CodeScope::global());
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run(&parser.parse(Tokenizer::new(&table)).unwrap())
+            .unwrap();
+
+        let cases = [
+            (
+                "get names from countries whose name starts with C",
+                "Czech, Canada",
+            ),
+            (
+                "get codes from countries where GDP > 1000 sorted by GDP asc",
+                "CZK, CAN",
+            ),
+            (
+                "get name from countries whose code ends with LN and GDP <= 1000",
+                "Poland",
+            ),
+            (
+                "get names from countries whose name contains a or GDP >= 3000 and code = CAN",
+                "Poland, Canada, Hungary",
+            ),
+            (
+                "get names from countries whose (name is Czech or name is Poland) and GDP != 1000",
+                "Czech",
+            ),
+            ("get names from countries whose GDP < -1", ""),
+        ];
+        for (query, expected) in cases {
+            let results = interpreter
+                .run(&parser.parse(Tokenizer::new(query)).unwrap())
+                .unwrap();
+            assert_eq!(results[0].1.to_string(), expected, "{query}");
+        }
+    }
+
+    #[test]
+    fn answers_the_excel_sample() {
+        let source = std::fs::read_to_string("samples/excel.x").unwrap();
+        let mut blocks = source.split("\n\n");
+        let mut parser = Parser::new(CodeScope::global());
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run(
+                &parser
+                    .parse(Tokenizer::new(blocks.next().unwrap()))
+                    .unwrap(),
+            )
+            .unwrap();
+
+        // Each query is followed by the lines it prints
+        for block in blocks {
+            let (query, expected) = block.split_once('\n').unwrap();
+            let results = interpreter
+                .run(&parser.parse(Tokenizer::new(query)).unwrap())
+                .unwrap();
+            assert_eq!(results[0].1.to_string(), expected.trim_end(), "{query}");
+        }
+    }
+
+    #[test]
+    fn aggregates_rows_and_groups() {
+        let mut parser = Parser::new(CodeScope::global());
+        let mut interpreter = Interpreter::new();
+        interpreter.run(&parser.parse(Tokenizer::new(
+            "Persons:\nName   Team  Age  Wage: f64\nJacek  A     24   2400.5\nTomek  B     30   5000.0\nAla    A     30   7000.0\nOla    A     22   0.5\n",
+        )).unwrap()).unwrap();
+
+        let cases = [
+            ("get count of persons", "4"),
+            ("get count of persons whose age > 100", "0"),
+            ("get average wage of persons whose age > 100", ""),
+            ("get sum ages from persons", "106"),
+            ("get average age of persons", "26.5"),
+            ("get average age of persons whose team is B", "30"),
+            (
+                "get average wage of persons whose team is A",
+                "3133.6666666666665",
+            ),
+            (
+                "get lowest name and highest wage from persons",
+                "Ala    7000",
+            ),
+            ("get names from persons with highest age", "Tomek, Ala"),
+            ("get a person with lowest age", "Ola    A     22   0.5"),
+            (
+                "get team, count and max wage of persons group by team",
+                "A     3  7000\nB     1  5000",
+            ),
+            ("get count of persons group by team", "A     3\nB     1"),
+            (
+                "get age and count from persons grouped by age sorted by age asc",
+                "22   1\n24   1\n30   2",
+            ),
+        ];
+        for (query, expected) in cases {
+            let results = interpreter
+                .run(&parser.parse(Tokenizer::new(query)).unwrap())
+                .unwrap();
+            assert_eq!(results[0].1.to_string(), expected, "{query}");
+        }
+    }
+
+    #[test]
+    fn loads_and_saves_files() {
+        let path = std::env::temp_dir().join("x-rs-saved-countries.tsv");
+        let path = path.to_str().unwrap();
+        let source = format!(
+            "load \"samples/countries.csv\" as Countries\nget names from countries whose GDP > 1500\nsave name and GDP of countries whose name contains a to {path:?}\nload {path:?} as Saved\nget a saved with highest GDP\n"
+        );
+
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(&source))
+            .unwrap();
+        let results = Interpreter::new().run(&ast).unwrap();
+        let saved = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(results[0].1.to_string(), "Canada, Korea, South");
+        assert_eq!(
+            saved,
+            "name\tGDP\nPoland\t1000\nCanada\t3200\nHungary\t800\nKorea, South\t1700\n"
+        );
+        assert_eq!(results[1].1.to_string(), "Canada  3200");
+    }
+
+    #[test]
+    fn joins_collections_by_their_fields() {
+        let source = std::fs::read_to_string("samples/careers.x").unwrap();
+        let mut parser = Parser::new(CodeScope::global());
+        let mut interpreter = Interpreter::new();
+        let results = interpreter
+            .run(&parser.parse(Tokenizer::new(&source)).unwrap())
+            .unwrap();
+        assert_eq!(results[0].1.to_string(), "Tomek  5000\nJacek  2400");
+
+        // Ola has no career to join with
+        let cases = [
+            (
+                "get names, wages from persons join careers",
+                "Jacek  2400\nTomek  5000",
+            ),
+            (
+                "get names from persons join careers on Career.Name = career sorted by wage",
+                "Tomek, Jacek",
+            ),
+            (
+                "get names from persons join careers whose wage > 3000",
+                "Tomek",
+            ),
+            ("get sum wages of persons join careers", "7400"),
+            (
+                "get careers join persons on name = career",
+                "Driver     2400  Jacek  24  Driver\nArchitect  5000  Tomek  30  Architect",
+            ),
+            ("get count of persons", "3"),
+        ];
+        for (query, expected) in cases {
+            let results = interpreter
+                .run(&parser.parse(Tokenizer::new(query)).unwrap())
+                .unwrap();
+            assert_eq!(results[0].1.to_string(), expected, "{query}");
+        }
+
+        // The same rows as with careers nested in persons, each person appended once
+        let source = std::fs::read_to_string("samples/over_rust.x")
+            .unwrap()
+            .replace(
+                "Persons -> Jacek 24 Driver 2400, Tomek 30 Architect 5000\n",
+                "",
+            );
+        let nested = Interpreter::new()
+            .run(
+                &Parser::new(CodeScope::global())
+                    .parse(Tokenizer::new(&source))
+                    .unwrap(),
+            )
+            .unwrap();
+        let query = "get names from persons join careers sorted by wage";
+        let joined = interpreter
+            .run(&parser.parse(Tokenizer::new(query)).unwrap())
+            .unwrap();
+        assert_eq!(joined[0].1, nested[0].1);
+    }
+
+    #[test]
+    fn optimized_plans_give_the_rows_of_the_query_as_written() {
+        let mut parser = Parser::new(CodeScope::global());
+        let mut interpreter = Interpreter::new();
+        let source = "Person: Name Career Age\nCareer: Name Wage\nPersons -> Jacek Driver 24, Tomek Architect 30, Ala Driver 30, Ola Pilot 22\nCareers -> Driver 2400, Architect 5000, Pilot 7000, C1 1, C2 2, C3 3, C4 4, C5 5, C6 6\n";
+        interpreter
+            .run(&parser.parse(Tokenizer::new(source)).unwrap())
+            .unwrap();
+
+        let queries = [
+            "get names, wages from persons join careers whose wage > 2000 and age < 30 sorted by name",
+            "get a person with highest age",
+            "get names of persons join careers with lowest wage sorted by name",
+            "get careers join persons on name = career whose age > 23 or wage = 7000",
+            "get count and max age of persons join careers group by wage",
+        ];
+        let rows: HashMap<String, Option<usize>> = interpreter
+            .tables
+            .iter()
+            .map(|(name, table)| (name.clone(), Some(table.rows.len())))
+            .collect();
+        for source in queries {
+            let ast = parser.parse(Tokenizer::new(source)).unwrap();
+            let Item::Stmt(stmt) = ast[ast.items[0]];
+            let StmtKind::Query(query) = &ast[stmt].kind else {
+                panic!("{source} is not a query");
+            };
+            let optimized = plan::plan_query(&ast, &interpreter.records, &rows, query);
+            let written = plan::lower_query(&ast, &interpreter.records, &rows, query);
+            assert_ne!(optimized, written, "{source}");
+
+            let Value::Rows(_, kept) = interpreter.execute(&optimized) else {
+                panic!("Plans give rows");
+            };
+            assert!(!kept.is_empty(), "{source}");
+            assert_eq!(
+                interpreter.execute(&optimized),
+                interpreter.execute(&written),
+                "{source}"
+            );
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use std::fmt;
+
+/// Minimal JSON document model, enough for AST dumps and editor protocols.
+/// Object keys keep their insertion order so the output is stable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct JsonError {
+    pub message: String,
+    pub offset: usize,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Self {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub fn str(value: &str) -> Self {
+        Json::Str(value.to_string())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(value) if *value >= 0.0 && value.fract() == 0.0 => Some(*value as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut reader = Reader {
+            bytes: text.as_bytes(),
+            text,
+            index: 0,
+        };
+
+        let value = reader.value()?;
+        reader.skip_whitespace();
+        if reader.index != reader.bytes.len() {
+            return Err(reader.error("trailing characters"));
+        }
+
+        Ok(value)
+    }
+
+    /// Indented form, two spaces per level.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
+        match self {
+            Json::Array(items) if !items.is_empty() => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    out.push_str(&indent);
+                    item.write_pretty(out, depth + 1);
+                    out.push_str(if i + 1 == items.len() { "\n" } else { ",\n" });
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push(']');
+            }
+            Json::Object(fields) if !fields.is_empty() => {
+                out.push_str("{\n");
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    out.push_str(&indent);
+                    write_escaped(out, key);
+                    out.push_str(": ");
+                    value.write_pretty(out, depth + 1);
+                    out.push_str(if i + 1 == fields.len() { "\n" } else { ",\n" });
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push('}');
+            }
+            _ => out.push_str(&self.to_string()),
+        }
+    }
+}
+
+fn write_escaped(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{value}"),
+            Json::Number(value) => write!(f, "{value}"),
+            Json::Str(value) => {
+                let mut out = String::new();
+                write_escaped(&mut out, value);
+                write!(f, "{out}")
+            }
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    let mut out = String::new();
+                    write_escaped(&mut out, key);
+                    write!(f, "{out}:{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    text: &'a str,
+    index: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError {
+            message: message.to_string(),
+            offset: self.index,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.index < self.bytes.len() && self.bytes[self.index].is_ascii_whitespace() {
+            self.index += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str, value: Json) -> Result<Json, JsonError> {
+        if self.text[self.index..].starts_with(literal) {
+            self.index += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        match self.bytes.get(self.index) {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'n') => self.expect("null", Json::Null),
+            Some(b't') => self.expect("true", Json::Bool(true)),
+            Some(b'f') => self.expect("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::Str(self.string()?)),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.object(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.index;
+        while self.index < self.bytes.len()
+            && matches!(
+                self.bytes[self.index],
+                b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'
+            )
+        {
+            self.index += 1;
+        }
+
+        self.text[start..self.index]
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| JsonError {
+                message: String::from("invalid number"),
+                offset: start,
+            })
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .text
+            .get(self.index..self.index + 4)
+            .ok_or_else(|| self.error("truncated escape"))?;
+        let value = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid escape"))?;
+        self.index += 4;
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        // Skip opening quote
+        self.index += 1;
+        let mut value = String::new();
+
+        loop {
+            let rest = &self.text[self.index..];
+            let c = rest
+                .chars()
+                .next()
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.index += c.len_utf8();
+
+            match c {
+                '"' => return Ok(value),
+                '\\' => {
+                    let escape = self.bytes.get(self.index).copied();
+                    self.index += 1;
+                    match escape {
+                        Some(b'"') => value.push('"'),
+                        Some(b'\\') => value.push('\\'),
+                        Some(b'/') => value.push('/'),
+                        Some(b'b') => value.push('\u{8}'),
+                        Some(b'f') => value.push('\u{c}'),
+                        Some(b'n') => value.push('\n'),
+                        Some(b'r') => value.push('\r'),
+                        Some(b't') => value.push('\t'),
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            // Surrogate pair
+                            if (0xD800..0xDC00).contains(&code)
+                                && self.text[self.index..].starts_with("\\u")
+                            {
+                                self.index += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            value.push(
+                                char::from_u32(code).ok_or_else(|| self.error("invalid escape"))?,
+                            );
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, JsonError> {
+        self.index += 1;
+        let mut items = vec![];
+
+        self.skip_whitespace();
+        if self.bytes.get(self.index) == Some(&b']') {
+            self.index += 1;
+            return Ok(Json::Array(items));
+        }
+
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.bytes.get(self.index) {
+                Some(b',') => self.index += 1,
+                Some(b']') => {
+                    self.index += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, JsonError> {
+        self.index += 1;
+        let mut fields = vec![];
+
+        self.skip_whitespace();
+        if self.bytes.get(self.index) == Some(&b'}') {
+            self.index += 1;
+            return Ok(Json::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.bytes.get(self.index) != Some(&b'"') {
+                return Err(self.error("expected object key"));
+            }
+            let key = self.string()?;
+
+            self.skip_whitespace();
+            if self.bytes.get(self.index) != Some(&b':') {
+                return Err(self.error("expected ':'"));
+            }
+            self.index += 1;
+
+            fields.push((key, self.value()?));
+
+            self.skip_whitespace();
+            match self.bytes.get(self.index) {
+                Some(b',') => self.index += 1,
+                Some(b'}') => {
+                    self.index += 1;
+                    return Ok(Json::Object(fields));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Json;
+
+    #[test]
+    fn parses_nested_documents() {
+        let json =
+            Json::parse(r#" { "a": [1, 2.5, -3e2], "b": {"c": null, "d": true} } "#).unwrap();
+
+        assert_eq!(
+            json,
+            Json::object(vec![
+                (
+                    "a",
+                    Json::Array(vec![
+                        Json::Number(1.0),
+                        Json::Number(2.5),
+                        Json::Number(-300.0)
+                    ])
+                ),
+                (
+                    "b",
+                    Json::object(vec![("c", Json::Null), ("d", Json::Bool(true))])
+                ),
+            ])
+        );
+        assert_eq!(
+            json.get("b").and_then(|b| b.get("d")),
+            Some(&Json::Bool(true))
+        );
+    }
+
+    #[test]
+    fn escapes_round_trip() {
+        let original = Json::str("quote \" backslash \\ tab \t newline \n zażółć 😀 \u{1}");
+
+        let text = original.to_string();
+        assert_eq!(Json::parse(&text).unwrap(), original);
+
+        assert_eq!(
+            Json::parse(r#""\u017c\ud83d\ude00""#).unwrap(),
+            Json::str("ż😀")
+        );
+    }
+
+    #[test]
+    fn pretty_output_parses_back() {
+        let json = Json::object(vec![
+            ("empty", Json::Array(vec![])),
+            ("list", Json::Array(vec![Json::Number(1.0), Json::str("x")])),
+        ]);
+
+        assert_eq!(
+            json.pretty(),
+            "{\n  \"empty\": [],\n  \"list\": [\n    1,\n    \"x\"\n  ]\n}"
+        );
+        assert_eq!(Json::parse(&json.pretty()).unwrap(), json);
+    }
+
+    #[test]
+    fn reports_errors_with_offsets() {
+        assert_eq!(Json::parse("[1, 2").unwrap_err().offset, 5);
+        assert_eq!(Json::parse("{\"a\" 1}").unwrap_err().offset, 5);
+        assert!(Json::parse("\"abc").is_err());
+        assert!(Json::parse("1 2").is_err());
+    }
+}
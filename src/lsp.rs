@@ -0,0 +1,1052 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    ast::{walk_expr, walk_stmt, ExprId, StmtId, Visitor, AST},
+    declarations::{ExprKind, RecordDecl, StmtKind, Type},
+    diagnostic::Diagnostic,
+    incremental::{self, Edit},
+    json::Json,
+    symbols::CodeScope,
+    tokenizer::Span,
+    typeck::{TypeChecker, Types},
+    unicode::nfc,
+};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// LSP enums
+const SEVERITY_ERROR: u8 = 1;
+const MESSAGE_ERROR: u8 = 1;
+const SYMBOL_ARRAY: u8 = 18;
+const SYMBOL_VARIABLE: u8 = 13;
+const SYMBOL_STRUCT: u8 = 23;
+const SYNC_INCREMENTAL: u8 = 2;
+
+type RpcError = (i64, String);
+
+/// Reads one `Content-Length` framed message, `None` at the end of input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "message without Content-Length")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+/// LSP position of a byte offset. Characters are counted in UTF-16 code units.
+fn position(source: &str, offset: usize) -> Json {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+    Json::object(vec![
+        ("line", Json::Number(before.matches('\n').count() as f64)),
+        (
+            "character",
+            Json::Number(before[line_start..].encode_utf16().count() as f64),
+        ),
+    ])
+}
+
+/// Byte offset of an LSP position, clamped to the end of its line.
+fn offset(source: &str, line: usize, character: usize) -> usize {
+    let line_start = match line {
+        0 => 0,
+        _ => source
+            .match_indices('\n')
+            .nth(line - 1)
+            .map_or(source.len(), |(index, _)| index + 1),
+    };
+
+    let mut units = 0;
+    for (index, c) in source[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + index;
+        }
+        units += c.len_utf16();
+    }
+    source.len()
+}
+
+/// Byte offset of an LSP position, none when it lies past the end of its line.
+fn checked_offset(source: &str, line: usize, character: usize) -> Option<usize> {
+    let line_start = match line {
+        0 => 0,
+        _ => source.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+
+    let mut units = 0;
+    for (index, c) in source[line_start..].char_indices() {
+        if units == character {
+            return Some(line_start + index);
+        }
+        if units > character || c == '\n' {
+            return None;
+        }
+        units += c.len_utf16();
+    }
+    (units == character).then_some(source.len())
+}
+
+fn range(source: &str, span: Span) -> Json {
+    Json::object(vec![
+        ("start", position(source, span.start)),
+        ("end", position(source, span.end())),
+    ])
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start <= offset && offset <= span.end()
+}
+
+/// Variable declared with `let`.
+#[derive(Debug)]
+struct Definition {
+    name: String,
+    /// The identifier after `let`
+    span: Span,
+    /// The whole declaration
+    stmt: Span,
+    /// Unknown when the value does not type check
+    ty: Option<Type>,
+    doc: Option<String>,
+}
+
+/// Record type or collection, declared at the top level.
+#[derive(Debug)]
+struct Declaration {
+    name: String,
+    /// The name where it is declared
+    span: Span,
+    stmt: Span,
+    kind: u8,
+    /// Fields of a record, the record type of the rows of a collection
+    detail: String,
+}
+
+/// What the compiler front end knows about one version of a document.
+#[derive(Debug, Default)]
+struct Analysis {
+    errors: Vec<Diagnostic>,
+    definitions: Vec<Definition>,
+    /// Every use of a variable and the index of its definition
+    references: Vec<(Span, usize)>,
+    declarations: Vec<Declaration>,
+}
+
+impl Analysis {
+    fn new(parsed: &incremental::Document) -> Self {
+        let ast = parsed.ast();
+        let (types, errors) = TypeChecker::new().check_partial(ast);
+
+        let mut resolver = Resolver {
+            scopes: parsed.scopes(),
+            types: &types,
+            open: vec![(0, HashMap::new())],
+            definitions: vec![],
+            references: vec![],
+            declarations: vec![],
+        };
+        resolver.visit_program(ast);
+
+        Analysis {
+            errors,
+            definitions: resolver.definitions,
+            references: resolver.references,
+            declarations: resolver.declarations,
+        }
+    }
+
+    /// Moves every span along with `edit`, so the analysis of the last version which
+    /// parsed keeps pointing at the right text.
+    fn shift(&mut self, edit: &Edit) {
+        for definition in &mut self.definitions {
+            definition.span = edit.shift(definition.span);
+            definition.stmt = edit.shift(definition.stmt);
+        }
+        for (span, _) in &mut self.references {
+            *span = edit.shift(*span);
+        }
+        for declaration in &mut self.declarations {
+            declaration.span = edit.shift(declaration.span);
+            declaration.stmt = edit.shift(declaration.stmt);
+        }
+    }
+
+    /// Definition declared or used at `offset`.
+    fn definition_at(&self, offset: usize) -> Option<usize> {
+        self.definitions
+            .iter()
+            .position(|definition| contains(definition.span, offset))
+            .or_else(|| {
+                self.references
+                    .iter()
+                    .find(|(span, _)| contains(*span, offset))
+                    .map(|(_, definition)| *definition)
+            })
+    }
+}
+
+/// Pairs variable uses with their declarations, visiting statements in the order the
+/// parser resolved them, so a use only sees declarations above it.
+struct Resolver<'a> {
+    scopes: &'a [CodeScope],
+    types: &'a Types,
+    /// Scope index and the definitions made so far of every open scope
+    open: Vec<(usize, HashMap<String, usize>)>,
+    definitions: Vec<Definition>,
+    references: Vec<(Span, usize)>,
+    declarations: Vec<Declaration>,
+}
+
+impl Resolver<'_> {
+    fn record(&mut self, decl: &RecordDecl, stmt: Span) {
+        let fields: Vec<&str> = decl
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect();
+        self.declarations.push(Declaration {
+            name: decl.name.clone(),
+            span: self.declared(&decl.name, stmt),
+            stmt,
+            kind: SYMBOL_STRUCT,
+            detail: fields.join(" "),
+        });
+    }
+
+    /// Name of a record type where it was declared, by default the start of `stmt`.
+    fn declared(&self, record: &str, stmt: Span) -> Span {
+        self.scopes[0].records.get(record).copied().unwrap_or(Span {
+            start: stmt.start,
+            len: record.len(),
+        })
+    }
+
+    /// Collections are filled by many statements, only the first one is listed.
+    fn collection(&mut self, name: &str, record: &str, span: Span, stmt: Span) {
+        if self
+            .declarations
+            .iter()
+            .any(|declaration| declaration.kind == SYMBOL_ARRAY && declaration.name == name)
+        {
+            return;
+        }
+        self.declarations.push(Declaration {
+            name: name.to_string(),
+            span,
+            stmt,
+            kind: SYMBOL_ARRAY,
+            detail: record.to_string(),
+        });
+    }
+}
+
+impl Visitor for Resolver<'_> {
+    fn visit_stmt(&mut self, ast: &AST, id: StmtId) {
+        match &ast[id].kind {
+            StmtKind::Block(block) => {
+                self.open.push((block.scope, HashMap::new()));
+                walk_stmt(self, ast, id);
+                self.open.pop();
+            }
+            StmtKind::Let(decl) => {
+                walk_stmt(self, ast, id);
+
+                let name = nfc(&decl.identifier).into_owned();
+                let (scope, defined) = self.open.last_mut().unwrap();
+                defined.insert(name.clone(), self.definitions.len());
+                self.definitions.push(Definition {
+                    span: self.scopes[*scope].symbols[&name],
+                    name,
+                    stmt: ast[id].span,
+                    ty: self.types.get(decl.value),
+                    doc: decl.doc.clone(),
+                });
+            }
+            StmtKind::Record(decl) => self.record(decl, ast[id].span),
+            StmtKind::Insert(insert) => {
+                walk_stmt(self, ast, id);
+
+                let stmt = ast[id].span;
+                let span = Span {
+                    start: stmt.start,
+                    len: insert.collection.len(),
+                };
+                self.collection(&insert.collection, &insert.record, span, stmt);
+            }
+            StmtKind::Table(table) => {
+                walk_stmt(self, ast, id);
+
+                let insert = &table.insert;
+                let stmt = ast[id].span;
+                let span = self.declared(&insert.record, stmt);
+                self.collection(&insert.collection, &insert.record, span, stmt);
+            }
+            StmtKind::Load(load) => {
+                let stmt = ast[id].span;
+                let span = self.declared(&load.record, stmt);
+                self.collection(&load.collection, &load.record, span, stmt);
+            }
+            StmtKind::Expr(_) | StmtKind::Query(_) | StmtKind::Save(_) => walk_stmt(self, ast, id),
+        }
+    }
+
+    fn visit_expr(&mut self, ast: &AST, id: ExprId) {
+        walk_expr(self, ast, id);
+
+        if let ExprKind::Variable(var) = &ast[id].kind {
+            let name = nfc(&var.identifier);
+            let definition = self
+                .open
+                .iter()
+                .rev()
+                .find_map(|(_, defined)| defined.get(name.as_ref()));
+            if let Some(&definition) = definition {
+                self.references.push((ast[id].span, definition));
+            }
+        }
+    }
+}
+
+struct Document {
+    source: String,
+    /// Kept to parse edits incrementally, gone after a parse error
+    parsed: Option<incremental::Document>,
+    analysis: Analysis,
+}
+
+impl Document {
+    /// Parses `source` in full unless `parsed` already holds it. When it does not parse,
+    /// definitions and symbols are taken from the `previous` analysis.
+    fn new(source: String, parsed: Option<incremental::Document>, previous: Analysis) -> Self {
+        let parsed = match parsed {
+            Some(parsed) => Ok(parsed),
+            None => incremental::Document::new(source.clone()),
+        };
+        let (parsed, analysis) = match parsed {
+            Ok(parsed) => {
+                let analysis = Analysis::new(&parsed);
+                (Some(parsed), analysis)
+            }
+            Err(error) => (
+                None,
+                Analysis {
+                    errors: vec![error],
+                    ..previous
+                },
+            ),
+        };
+
+        Document {
+            source,
+            parsed,
+            analysis,
+        }
+    }
+
+    /// Applies `contentChanges`, each either a `range` with its new text or the whole text.
+    /// A range running backwards or past the end of a line rejects the whole notification,
+    /// leaving the document as it was.
+    fn change(&mut self, changes: &[Json]) -> Result<(), String> {
+        // `None` replaces the whole text
+        let mut edits: Vec<Option<Edit>> = vec![];
+        let mut source = self.source.clone();
+
+        for change in changes {
+            let Some(text) = change.get("text").and_then(Json::as_str) else {
+                continue;
+            };
+            let Some(range) = change.get("range") else {
+                source = text.to_string();
+                edits.push(None);
+                continue;
+            };
+
+            let offset_of = |key: &str| {
+                let position = range.get(key)?;
+                let line = position.get("line").and_then(Json::as_u64)?;
+                let character = position.get("character").and_then(Json::as_u64)?;
+                checked_offset(&source, line as usize, character as usize)
+            };
+            let (Some(start), Some(end)) = (offset_of("start"), offset_of("end")) else {
+                return Err(format!("Change of {range} is outside of the document"));
+            };
+            if end < start {
+                return Err(format!("Change of {range} ends before it starts"));
+            }
+
+            source.replace_range(start..end, text);
+            edits.push(Some(Edit {
+                start,
+                end,
+                text: text.to_string(),
+            }));
+        }
+
+        let mut parsed = self.parsed.take();
+        let mut previous = std::mem::take(&mut self.analysis);
+        for edit in edits {
+            let Some(edit) = edit else {
+                (parsed, previous) = (None, Analysis::default());
+                continue;
+            };
+            parsed = parsed.and_then(|mut parsed| parsed.edit(&edit).ok().map(|_| parsed));
+            previous.shift(&edit);
+        }
+
+        *self = Document::new(source, parsed, previous);
+        Ok(())
+    }
+}
+
+/// Language server speaking JSON-RPC with `Content-Length` framing. Documents are
+/// synchronized incrementally, reparsing only the edited block when possible.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles messages until `exit` or the end of input. Returns whether the client
+    /// asked for `shutdown` first, as a clean exit requires.
+    pub fn serve(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<bool> {
+        while let Some(body) = read_message(input)? {
+            let message = match Json::parse(&body) {
+                Ok(message) => message,
+                Err(err) => {
+                    let error = response(Json::Null, Err((PARSE_ERROR, err.to_string())));
+                    write_message(output, &error)?;
+                    continue;
+                }
+            };
+
+            let method = message.get("method").and_then(Json::as_str);
+            let params = message.get("params").unwrap_or(&Json::Null);
+
+            match (method, message.get("id")) {
+                (Some("exit"), None) => return Ok(self.shut_down),
+                (Some(method), Some(id)) => {
+                    let result = self.request(method, params);
+                    write_message(output, &response(id.clone(), result))?;
+                }
+                (Some(method), None) => {
+                    for notification in self.notify(method, params) {
+                        write_message(output, &notification)?;
+                    }
+                }
+                // Responses to requests we never send
+                (None, _) => {}
+            }
+        }
+
+        Ok(self.shut_down)
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, RpcError> {
+        if self.shut_down {
+            return Err((INVALID_REQUEST, String::from("server is shut down")));
+        }
+
+        match method {
+            "initialize" => Ok(Json::object(vec![
+                (
+                    "capabilities",
+                    Json::object(vec![
+                        ("textDocumentSync", Json::Number(SYNC_INCREMENTAL as f64)),
+                        ("definitionProvider", Json::Bool(true)),
+                        ("referencesProvider", Json::Bool(true)),
+                        ("hoverProvider", Json::Bool(true)),
+                        ("documentSymbolProvider", Json::Bool(true)),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Json::object(vec![
+                        ("name", Json::str("x-rs")),
+                        ("version", Json::str(crate::misc::VERSION)),
+                    ]),
+                ),
+            ])),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => {
+                let (uri, document, offset) = self.locate(params)?;
+                let analysis = &document.analysis;
+
+                Ok(match analysis.definition_at(offset) {
+                    Some(index) => {
+                        location(uri, &document.source, analysis.definitions[index].span)
+                    }
+                    None => Json::Null,
+                })
+            }
+            "textDocument/references" => {
+                let (uri, document, offset) = self.locate(params)?;
+                let analysis = &document.analysis;
+                let Some(index) = analysis.definition_at(offset) else {
+                    return Ok(Json::Array(vec![]));
+                };
+
+                let include_declaration = params
+                    .get("context")
+                    .and_then(|context| context.get("includeDeclaration"))
+                    == Some(&Json::Bool(true));
+
+                let mut locations = vec![];
+                if include_declaration {
+                    let span = analysis.definitions[index].span;
+                    locations.push(location(uri, &document.source, span));
+                }
+                for (span, definition) in &analysis.references {
+                    if *definition == index {
+                        locations.push(location(uri, &document.source, *span));
+                    }
+                }
+                Ok(Json::Array(locations))
+            }
+            "textDocument/hover" => {
+                let (_, document, offset) = self.locate(params)?;
+                let analysis = &document.analysis;
+                let Some(index) = analysis.definition_at(offset) else {
+                    return Ok(Json::Null);
+                };
+
+                let definition = &analysis.definitions[index];
+                let mut text = format!("```\n{}\n```", signature(definition));
+                if let Some(doc) = &definition.doc {
+                    text += &format!("\n\n{doc}");
+                }
+
+                Ok(Json::object(vec![(
+                    "contents",
+                    Json::object(vec![
+                        ("kind", Json::str("markdown")),
+                        ("value", Json::Str(text)),
+                    ]),
+                )]))
+            }
+            "textDocument/documentSymbol" => {
+                let uri = document_uri(params)?;
+                let document = self.document(uri)?;
+
+                let analysis = &document.analysis;
+
+                let variables = analysis.definitions.iter().map(|definition| {
+                    let detail = definition.ty.map_or("", |ty| ty.name());
+                    let spans = (definition.stmt, definition.span);
+                    (&definition.name, detail, SYMBOL_VARIABLE, spans)
+                });
+                let declarations = analysis.declarations.iter().map(|declaration| {
+                    let spans = (declaration.stmt, declaration.span);
+                    (
+                        &declaration.name,
+                        declaration.detail.as_str(),
+                        declaration.kind,
+                        spans,
+                    )
+                });
+                let mut symbols: Vec<_> = variables.chain(declarations).collect();
+                symbols.sort_by_key(|(_, _, _, (stmt, _))| stmt.start);
+
+                let symbols = symbols
+                    .into_iter()
+                    .map(|(name, detail, kind, (stmt, span))| {
+                        Json::object(vec![
+                            ("name", Json::str(name)),
+                            ("detail", Json::str(detail)),
+                            ("kind", Json::Number(kind as f64)),
+                            ("range", range(&document.source, stmt)),
+                            ("selectionRange", range(&document.source, span)),
+                        ])
+                    })
+                    .collect();
+                Ok(Json::Array(symbols))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{method}'"))),
+        }
+    }
+
+    /// Updates documents, answering with the notifications to send back.
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let Ok(uri) = document_uri(params) else {
+            return vec![];
+        };
+
+        let document = match method {
+            "textDocument/didOpen" => {
+                let Some(text) = params
+                    .get("textDocument")
+                    .and_then(|document| document.get("text"))
+                    .and_then(Json::as_str)
+                else {
+                    return vec![];
+                };
+                Document::new(text.to_string(), None, Analysis::default())
+            }
+            "textDocument/didChange" => {
+                let (Some(changes), Some(mut document)) = (
+                    params.get("contentChanges").and_then(Json::as_array),
+                    self.documents.remove(uri),
+                ) else {
+                    return vec![];
+                };
+                if let Err(message) = document.change(changes) {
+                    self.documents.insert(uri.to_string(), document);
+                    return vec![log_message(&message)];
+                }
+                document
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, Json::Array(vec![]))];
+            }
+            _ => return vec![],
+        };
+
+        let diagnostics = document
+            .analysis
+            .errors
+            .iter()
+            .map(|error| {
+                Json::object(vec![
+                    ("range", range(&document.source, error.span)),
+                    ("severity", Json::Number(SEVERITY_ERROR as f64)),
+                    ("source", Json::str("x-rs")),
+                    ("message", Json::str(&error.message)),
+                ])
+            })
+            .collect();
+
+        self.documents.insert(uri.to_string(), document);
+        vec![publish_diagnostics(uri, Json::Array(diagnostics))]
+    }
+
+    fn document(&self, uri: &str) -> Result<&Document, RpcError> {
+        self.documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("document '{uri}' is not open")))
+    }
+
+    /// Document and byte offset of a `TextDocumentPositionParams`.
+    fn locate<'p>(&self, params: &'p Json) -> Result<(&'p str, &Document, usize), RpcError> {
+        let uri = document_uri(params)?;
+        let document = self.document(uri)?;
+
+        let position = params.get("position");
+        let field = |name| {
+            position
+                .and_then(|position| position.get(name))
+                .and_then(Json::as_u64)
+                .ok_or_else(|| (INVALID_PARAMS, format!("position without '{name}'")))
+        };
+        let (line, character) = (field("line")?, field("character")?);
+
+        Ok((
+            uri,
+            document,
+            offset(&document.source, line as usize, character as usize),
+        ))
+    }
+}
+
+fn document_uri(params: &Json) -> Result<&str, RpcError> {
+    params
+        .get("textDocument")
+        .and_then(|document| document.get("uri"))
+        .and_then(Json::as_str)
+        .ok_or_else(|| (INVALID_PARAMS, String::from("missing textDocument.uri")))
+}
+
+fn signature(definition: &Definition) -> String {
+    match definition.ty {
+        Some(ty) => format!("let {}: {}", definition.name, ty.name()),
+        None => format!("let {}", definition.name),
+    }
+}
+
+fn location(uri: &str, source: &str, span: Span) -> Json {
+    Json::object(vec![
+        ("uri", Json::str(uri)),
+        ("range", range(source, span)),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Json::object(vec![("uri", Json::str(uri)), ("diagnostics", diagnostics)]),
+        ),
+    ])
+}
+
+fn log_message(message: &str) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("window/logMessage")),
+        (
+            "params",
+            Json::object(vec![
+                ("type", Json::Number(MESSAGE_ERROR as f64)),
+                ("message", Json::str(message)),
+            ]),
+        ),
+    ])
+}
+
+fn response(id: Json, result: Result<Json, RpcError>) -> Json {
+    let outcome = match result {
+        Ok(result) => ("result", result),
+        Err((code, message)) => (
+            "error",
+            Json::object(vec![
+                ("code", Json::Number(code as f64)),
+                ("message", Json::Str(message)),
+            ]),
+        ),
+    };
+
+    Json::object(vec![("jsonrpc", Json::str("2.0")), ("id", id), outcome])
+}
+
+/// Serves stdin and stdout. Exits with an error when the client did not shut down first.
+pub fn run() -> io::Result<bool> {
+    Server::new().serve(&mut io::stdin().lock(), &mut io::stdout().lock())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use crate::json::Json;
+
+    use super::{checked_offset, offset, read_message, Server};
+
+    const URI: &str = "file:///main.x";
+
+    /// Frames `messages` like an editor would, runs the server over them and returns
+    /// everything it sent back.
+    fn session(messages: &[String]) -> (bool, Vec<Json>) {
+        let mut input = String::new();
+        for message in messages {
+            input += &format!("Content-Length: {}\r\n\r\n{message}", message.len());
+        }
+
+        let mut output = vec![];
+        let clean = Server::new()
+            .serve(&mut input.as_bytes(), &mut output)
+            .unwrap();
+
+        let mut reader = BufReader::new(output.as_slice());
+        let mut replies = vec![];
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            replies.push(Json::parse(&body).unwrap());
+        }
+        (clean, replies)
+    }
+
+    fn open(text: &str) -> String {
+        format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{URI}","languageId":"x","version":1,"text":{}}}}}}}"#,
+            Json::str(text)
+        )
+    }
+
+    fn at(id: u32, method: &str, line: u32, character: u32) -> String {
+        format!(
+            r#"{{"jsonrpc":"2.0","id":{id},"method":"{method}","params":{{"textDocument":{{"uri":"{URI}"}},"position":{{"line":{line},"character":{character}}},"context":{{"includeDeclaration":true}}}}}}"#
+        )
+    }
+
+    #[test]
+    fn initializes_and_publishes_diagnostics() {
+        let (clean, replies) = session(&[
+            String::from(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#),
+            String::from(r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#),
+            open("let a = 1;\nlet b = a * 2.5;\n"),
+            format!(
+                r#"{{"jsonrpc":"2.0","method":"textDocument/didChange","params":{{"textDocument":{{"uri":"{URI}","version":2}},"contentChanges":[{{"text":"let a = 1;"}}]}}}}"#
+            ),
+            String::from(r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#),
+            String::from(r#"{"jsonrpc":"2.0","method":"exit"}"#),
+        ]);
+
+        assert!(clean);
+        assert_eq!(replies.len(), 4);
+
+        let capabilities = replies[0]
+            .get("result")
+            .unwrap()
+            .get("capabilities")
+            .unwrap();
+        assert_eq!(capabilities.get("hoverProvider"), Some(&Json::Bool(true)));
+
+        assert_eq!(
+            replies[1].to_string(),
+            r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///main.x","diagnostics":[{"range":{"start":{"line":1,"character":8},"end":{"line":1,"character":15}},"severity":1,"source":"x-rs","message":"Cannot apply '*' to i64 and f64, convert one side with 'as'"}]}}"#
+        );
+        let fixed = replies[2]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap();
+        assert_eq!(fixed, &Json::Array(vec![]));
+        assert_eq!(replies[3].get("result"), Some(&Json::Null));
+    }
+
+    fn change(range: (u32, u32, u32, u32), text: &str) -> String {
+        let (line, character, end_line, end_character) = range;
+        format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didChange","params":{{"textDocument":{{"uri":"{URI}","version":2}},"contentChanges":[{{"range":{{"start":{{"line":{line},"character":{character}}},"end":{{"line":{end_line},"character":{end_character}}}}},"text":{}}}]}}}}"#,
+            Json::str(text)
+        )
+    }
+
+    #[test]
+    fn applies_incremental_changes() {
+        let (_, replies) = session(&[
+            open("let a = 1;\n{\n    let b = a;\n}\n"),
+            change((2, 12, 2, 13), "a as f64 * 2.5"),
+            at(1, "textDocument/hover", 2, 8),
+            change((2, 12, 2, 12), "q + "),
+            change((2, 12, 2, 16), ""),
+            change((3, 1, 3, 1), "\nlet c = b;"),
+        ]);
+
+        let messages = |index: usize| {
+            let diagnostics = replies[index].get("params").unwrap().get("diagnostics");
+            let diagnostics = diagnostics.unwrap().as_array().unwrap();
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.get("message").unwrap().as_str().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(messages(0).is_empty());
+        assert!(messages(1).is_empty());
+        assert_eq!(
+            replies[2].get("result").unwrap().to_string(),
+            r#"{"contents":{"kind":"markdown","value":"```\nlet b: f64\n```"}}"#
+        );
+        assert_eq!(messages(3), ["Unknown symbol q"]);
+        assert!(messages(4).is_empty());
+        // `b` is not visible after its block
+        assert_eq!(messages(5), ["Unknown symbol b"]);
+    }
+
+    #[test]
+    fn keeps_the_last_analysis_which_parsed() {
+        let (_, replies) = session(&[
+            open("let a = 1;\nlet b = a * 2.5;\nlet c = a;\n"),
+            at(1, "textDocument/hover", 2, 4),
+            change((3, 0, 3, 0), "\nlet d = ;"),
+            at(2, "textDocument/hover", 2, 4),
+            at(3, "textDocument/definition", 2, 8),
+        ]);
+
+        let result = |index: usize| replies[index].get("result").unwrap().to_string();
+        let hover = r#"{"contents":{"kind":"markdown","value":"```\nlet c: i64\n```"}}"#;
+
+        // `b` does not type check, `c` still does
+        assert_eq!(result(1), hover);
+        let diagnostics = replies[2]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap();
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(result(3), hover);
+        assert_eq!(
+            result(4),
+            r#"{"uri":"file:///main.x","range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}}}"#
+        );
+    }
+
+    #[test]
+    fn rejects_changes_outside_of_the_document() {
+        let (_, replies) = session(&[
+            open("let a = 1;\n"),
+            change((0, 9, 0, 8), "2"),
+            change((0, 8, 0, 20), "2"),
+            change((3, 0, 3, 0), "let b = 2;"),
+            at(1, "textDocument/hover", 0, 4),
+            change((1, 0, 1, 0), "let b = a;"),
+        ]);
+
+        for reply in &replies[1..4] {
+            assert_eq!(reply.get("method"), Some(&Json::str("window/logMessage")));
+        }
+        assert_eq!(
+            replies[1].get("params").unwrap().get("message"),
+            Some(&Json::str(
+                r#"Change of {"start":{"line":0,"character":9},"end":{"line":0,"character":8}} ends before it starts"#
+            ))
+        );
+        assert_eq!(
+            replies[4].get("result").unwrap().to_string(),
+            r#"{"contents":{"kind":"markdown","value":"```\nlet a: i64\n```"}}"#
+        );
+        let diagnostics = replies[5]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap();
+        assert_eq!(diagnostics, &Json::Array(vec![]));
+    }
+
+    #[test]
+    fn lists_records_and_collections() {
+        let source = "Career: Name Wage\nCareers -> Driver 2400\nCareers -> Architect 5000\nlet n = 1;\nCountries:\nName GDP\nPoland 1000\n";
+        let (_, replies) = session(&[
+            open(source),
+            format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"textDocument/documentSymbol","params":{{"textDocument":{{"uri":"{URI}"}}}}}}"#
+            ),
+        ]);
+
+        let symbols = replies[1].get("result").unwrap().as_array().unwrap();
+        let field = |symbol: &Json, name: &str| symbol.get(name).unwrap().to_string();
+        let listed: Vec<_> = symbols
+            .iter()
+            .map(|symbol| {
+                [
+                    field(symbol, "name"),
+                    field(symbol, "detail"),
+                    field(symbol, "kind"),
+                ]
+                .join(" ")
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                r#""Career" "Name Wage" 23"#,
+                r#""Careers" "Career" 18"#,
+                r#""n" "i64" 13"#,
+                r#""Countries" "Country" 18"#,
+            ]
+        );
+        assert_eq!(
+            field(&symbols[1], "selectionRange"),
+            r#"{"start":{"line":1,"character":0},"end":{"line":1,"character":7}}"#
+        );
+    }
+
+    #[test]
+    fn navigates_between_declarations_and_uses() {
+        let source = "## Share of the pie\nlet część = 0.5;\n{\n    let n = 3;\n    część * n as f64\n}\nlet x = część;\n";
+        let (_, replies) = session(&[
+            open(source),
+            // `n` in the block, then `część` after `let x = `
+            at(1, "textDocument/definition", 4, 13),
+            at(2, "textDocument/references", 6, 10),
+            at(3, "textDocument/hover", 1, 6),
+            format!(
+                r#"{{"jsonrpc":"2.0","id":4,"method":"textDocument/documentSymbol","params":{{"textDocument":{{"uri":"{URI}"}}}}}}"#
+            ),
+            at(5, "textDocument/hover", 2, 0),
+        ]);
+
+        // The first reply publishes the diagnostics of the opened document
+        let result = |index: usize| replies[index].get("result").unwrap().to_string();
+
+        assert_eq!(
+            result(1),
+            r#"{"uri":"file:///main.x","range":{"start":{"line":3,"character":8},"end":{"line":3,"character":9}}}"#
+        );
+        assert_eq!(
+            result(2),
+            r#"[{"uri":"file:///main.x","range":{"start":{"line":1,"character":4},"end":{"line":1,"character":9}}},{"uri":"file:///main.x","range":{"start":{"line":4,"character":4},"end":{"line":4,"character":9}}},{"uri":"file:///main.x","range":{"start":{"line":6,"character":8},"end":{"line":6,"character":13}}}]"#
+        );
+        assert_eq!(
+            result(3),
+            r#"{"contents":{"kind":"markdown","value":"```\nlet część: f64\n```\n\nShare of the pie"}}"#
+        );
+
+        let symbols = replies[4].get("result").unwrap().as_array().unwrap();
+        let names: Vec<_> = symbols
+            .iter()
+            .map(|symbol| symbol.get("name").unwrap().as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["część", "n", "x"]);
+        assert_eq!(symbols[1].get("detail"), Some(&Json::str("i64")));
+
+        assert_eq!(result(5), "null");
+    }
+
+    #[test]
+    fn reports_protocol_errors() {
+        let (clean, replies) = session(&[
+            String::from("{not json"),
+            String::from(r#"{"jsonrpc":"2.0","id":1,"method":"workspace/symbol","params":{}}"#),
+            at(2, "textDocument/hover", 0, 0),
+            String::from(r#"{"jsonrpc":"2.0","method":"exit"}"#),
+        ]);
+
+        assert!(!clean);
+        let code = |index: usize| replies[index].get("error").unwrap().get("code").cloned();
+        assert_eq!(code(0), Some(Json::Number(-32700.0)));
+        assert_eq!(code(1), Some(Json::Number(-32601.0)));
+        assert_eq!(code(2), Some(Json::Number(-32602.0)));
+    }
+
+    #[test]
+    fn converts_utf16_positions() {
+        let source = "let część = 1;\nlet 𝔵 = 2;";
+
+        assert_eq!(offset(source, 0, 9), 12);
+        assert_eq!(offset(source, 0, 100), 17);
+        assert_eq!(checked_offset(source, 0, 100), None);
+        assert_eq!(checked_offset(source, 1, 9), Some(29));
+        assert_eq!(checked_offset(source, 2, 0), None);
+        // Inside of the surrogate pair of `𝔵`
+        assert_eq!(checked_offset(source, 1, 5), None);
+        // `𝔵` takes two UTF-16 units and four bytes
+        assert_eq!(offset(source, 1, 6), 26);
+        assert_eq!(
+            super::position(source, 26).to_string(),
+            r#"{"line":1,"character":6}"#
+        );
+    }
+}
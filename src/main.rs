@@ -1,40 +1,93 @@
 use std::env;
-use std::fs::File;
-use std::io::prelude::*;
+use std::process::ExitCode;
 
 mod assembly;
+mod ast;
+mod ast_dump;
+mod cst;
+mod csv;
 mod declarations;
+mod diagnostic;
+mod dot;
+mod driver;
+mod fold;
+mod formatter;
+mod incremental;
+mod interpreter;
+mod json;
+mod lsp;
 mod misc;
 mod parser;
+mod plan;
+mod records;
+mod repl;
 mod symbols;
 mod tokenizer;
+mod typeck;
+mod unicode;
 
-use assembly::Assembler;
-use misc::Parameters;
-use parser::Parser;
-use symbols::CodeScope;
-use tokenizer::Tokenizer;
+use misc::{Command, Parameters, EXIT_FAILURE, EXIT_USAGE, USAGE, VERSION};
 
-fn main() {
-    let args = env::args();
-    let params = Parameters::from(args);
+fn main() -> ExitCode {
+    let params = match Parameters::parse(env::args()) {
+        Ok(params) => params,
+        Err(err) => {
+            eprintln!("x-rs: {err}\n\n{USAGE}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
 
-    let global_scope = CodeScope::global();
+    match params.command {
+        Command::Help => print!("{USAGE}"),
+        Command::Version => println!("x-rs {VERSION}"),
+        Command::Repl => {
+            if let Err(err) = repl::run() {
+                eprintln!("x-rs: {err}");
+                return ExitCode::from(EXIT_FAILURE);
+            }
+        }
+        Command::Lsp => match lsp::run() {
+            Ok(true) => {}
+            Ok(false) => return ExitCode::from(EXIT_FAILURE),
+            Err(err) => {
+                eprintln!("x-rs: {err}");
+                return ExitCode::from(EXIT_FAILURE);
+            }
+        },
+        Command::Fmt => {
+            let mut failed = false;
+            for input in &params.inputs {
+                match driver::format(input, &params) {
+                    Ok(true) if params.check => {
+                        eprintln!("x-rs: {input}: would be reformatted");
+                        failed = true;
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        eprintln!("x-rs: {input}: {err}");
+                        failed = true;
+                    }
+                }
+            }
 
-    //TODO: better error message
-    let mut file = File::open(params.file_path).expect("Failed to open file!");
+            if failed {
+                return ExitCode::from(EXIT_FAILURE);
+            }
+        }
+        Command::Compile => {
+            let mut failed = false;
+            for input in &params.inputs {
+                if let Err(err) = driver::compile(input, &params) {
+                    eprintln!("x-rs: {input}: {err}");
+                    failed = true;
+                }
+            }
 
-    let mut file_content = String::new();
+            if failed {
+                return ExitCode::from(EXIT_FAILURE);
+            }
+        }
+    }
 
-    file.read_to_string(&mut file_content)
-        .expect("Failed to read file into string");
-
-    let tokenizer = Tokenizer::new(&file_content);
-    let mut parser = Parser::new(global_scope);
-
-    let mut assembly = Assembler::new("out");
-    let ast = parser.parse(tokenizer);
-
-    assembly.from_ast(&ast);
-    assembly.build();
+    ExitCode::SUCCESS
 }
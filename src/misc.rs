@@ -1,19 +1,344 @@
-use std::env::Args;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
 
+pub const USAGE: &str = "\
+Usage: x-rs [options] <input>...
+       x-rs fmt [--check] <input>...
+       x-rs repl
+       x-rs lsp
+
+Inputs:
+    <file>            source file to compile
+    -                 read source from stdin
+
+Options:
+    -o <path>         write output to <path> (only with a single input)
+    --emit=<stage>    stop after <stage>: tokens, cst, ast, scopes, dot, ir, plan,
+                      asm, obj, exe
+                      (default: asm)
+    --ast-format=<f>  format of --emit=ast: tree, sexp, json (default: tree)
+    --check           with fmt: change nothing, fail if an input is not formatted
+    -h, --help        print this message
+    -V, --version     print version
+";
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Exit code used when at least one of the inputs failed to compile.
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code used for invalid command line arguments.
+pub const EXIT_USAGE: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,
+    Cst,
+    Ast,
+    Scopes,
+    Dot,
+    Ir,
+    Plan,
+    Asm,
+    Obj,
+    Exe,
+}
+
+impl FromStr for Emit {
+    type Err = ParamsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Emit::Tokens),
+            "cst" => Ok(Emit::Cst),
+            "ast" => Ok(Emit::Ast),
+            "scopes" => Ok(Emit::Scopes),
+            "dot" => Ok(Emit::Dot),
+            "ir" => Ok(Emit::Ir),
+            "plan" => Ok(Emit::Plan),
+            "asm" => Ok(Emit::Asm),
+            "obj" => Ok(Emit::Obj),
+            "exe" => Ok(Emit::Exe),
+            _ => Err(ParamsError::UnknownEmit(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AstFormat {
+    Tree,
+    Sexp,
+    Json,
+}
+
+impl FromStr for AstFormat {
+    type Err = ParamsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tree" => Ok(AstFormat::Tree),
+            "sexp" => Ok(AstFormat::Sexp),
+            "json" => Ok(AstFormat::Json),
+            _ => Err(ParamsError::UnknownAstFormat(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Stdin,
+    File(String),
+}
+
+impl Input {
+    /// Name used for output files when no `-o` was given.
+    pub fn stem(&self) -> String {
+        match self {
+            Input::Stdin => String::from("out"),
+            Input::File(path) => Path::new(path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| String::from("out")),
+        }
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Stdin => write!(f, "<stdin>"),
+            Input::File(path) => write!(f, "{path}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Compile,
+    /// Formats inputs in place, or only checks them with `--check`
+    Fmt,
+    Repl,
+    /// Language server over stdin and stdout
+    Lsp,
+    Help,
+    Version,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParamsError {
+    NoInput,
+    MissingValue(String),
+    UnknownEmit(String),
+    UnknownAstFormat(String),
+    UnknownOption(String),
+    StdinUsedTwice,
+    OutputWithManyInputs,
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamsError::NoInput => write!(f, "no input files"),
+            ParamsError::MissingValue(option) => write!(f, "option '{option}' needs a value"),
+            ParamsError::UnknownEmit(stage) => write!(f, "unknown emit stage '{stage}'"),
+            ParamsError::UnknownAstFormat(format) => write!(f, "unknown ast format '{format}'"),
+            ParamsError::UnknownOption(option) => write!(f, "unknown option '{option}'"),
+            ParamsError::StdinUsedTwice => write!(f, "stdin ('-') can only be read once"),
+            ParamsError::OutputWithManyInputs => {
+                write!(f, "'-o' cannot be used with more than one input")
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Parameters {
-    pub file_path: String,
+    pub command: Command,
+    pub inputs: Vec<Input>,
+    pub output: Option<String>,
+    pub emit: Emit,
+    pub ast_format: AstFormat,
+    pub check: bool,
 }
 
-impl From<Args> for Parameters {
-    fn from(args: Args) -> Self {
-        let collected_args: Vec<String> = args.collect();
+impl Parameters {
+    /// Parses command line arguments. The first item is expected to be the program name.
+    pub fn parse<I>(args: I) -> Result<Self, ParamsError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut params = Self {
+            command: Command::Compile,
+            inputs: vec![],
+            output: None,
+            emit: Emit::Asm,
+            ast_format: AstFormat::Tree,
+            check: false,
+        };
+
+        let mut args = args.into_iter().skip(1).peekable();
+        match args.peek().map(String::as_str) {
+            Some("repl") => params.command = Command::Repl,
+            Some("lsp") => params.command = Command::Lsp,
+            Some("fmt") => params.command = Command::Fmt,
+            _ => {}
+        }
+        if params.command != Command::Compile {
+            args.next();
+        }
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => params.command = Command::Help,
+                "-V" | "--version" => params.command = Command::Version,
+                "-o" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| ParamsError::MissingValue(arg.clone()))?;
+                    params.output = Some(path);
+                }
+                "--emit" => {
+                    let stage = args
+                        .next()
+                        .ok_or_else(|| ParamsError::MissingValue(arg.clone()))?;
+                    params.emit = stage.parse()?;
+                }
+                "--ast-format" => {
+                    let format = args
+                        .next()
+                        .ok_or_else(|| ParamsError::MissingValue(arg.clone()))?;
+                    params.ast_format = format.parse()?;
+                }
+                "--check" if params.command == Command::Fmt => params.check = true,
+                "-" => {
+                    if params.inputs.contains(&Input::Stdin) {
+                        return Err(ParamsError::StdinUsedTwice);
+                    }
+                    params.inputs.push(Input::Stdin);
+                }
+                _ => {
+                    if let Some(stage) = arg.strip_prefix("--emit=") {
+                        params.emit = stage.parse()?;
+                    } else if let Some(format) = arg.strip_prefix("--ast-format=") {
+                        params.ast_format = format.parse()?;
+                    } else if arg.starts_with('-') {
+                        return Err(ParamsError::UnknownOption(arg));
+                    } else {
+                        params.inputs.push(Input::File(arg));
+                    }
+                }
+            }
+        }
+
+        if !matches!(params.command, Command::Compile | Command::Fmt) {
+            return Ok(params);
+        }
 
-        if collected_args.len() < 2 {
-            panic!("Compiler needs at least one argument");
+        if params.inputs.is_empty() {
+            return Err(ParamsError::NoInput);
         }
 
-        Self {
-            file_path: collected_args[1].clone(),
+        if params.output.is_some() && params.inputs.len() > 1 {
+            return Err(ParamsError::OutputWithManyInputs);
         }
+
+        Ok(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AstFormat, Command, Emit, Input, Parameters, ParamsError};
+
+    fn parse(args: &[&str]) -> Result<Parameters, ParamsError> {
+        let args = std::iter::once("x-rs")
+            .chain(args.iter().copied())
+            .map(String::from);
+        Parameters::parse(args)
+    }
+
+    #[test]
+    fn single_file_defaults_to_asm() {
+        let params = parse(&["main.x"]).unwrap();
+
+        assert_eq!(params.command, Command::Compile);
+        assert_eq!(params.inputs, [Input::File(String::from("main.x"))]);
+        assert_eq!(params.emit, Emit::Asm);
+        assert_eq!(params.output, None);
+    }
+
+    #[test]
+    fn reads_output_and_emit() {
+        let params = parse(&["--emit=tokens", "-o", "tokens.txt", "-"]).unwrap();
+
+        assert_eq!(params.emit, Emit::Tokens);
+        assert_eq!(params.output, Some(String::from("tokens.txt")));
+        assert_eq!(params.inputs, [Input::Stdin]);
+
+        let params = parse(&["--emit", "exe", "a.x", "b.x"]).unwrap();
+
+        assert_eq!(params.emit, Emit::Exe);
+        assert_eq!(params.inputs.len(), 2);
+
+        let params = parse(&["--emit=ast", "--ast-format=sexp", "a.x"]).unwrap();
+
+        assert_eq!(params.ast_format, AstFormat::Sexp);
+
+        assert_eq!(parse(&["--emit=plan", "a.x"]).unwrap().emit, Emit::Plan);
+    }
+
+    #[test]
+    fn help_and_version_do_not_need_input() {
+        assert_eq!(parse(&["--help"]).unwrap().command, Command::Help);
+        assert_eq!(parse(&["-V"]).unwrap().command, Command::Version);
+        assert_eq!(parse(&["repl"]).unwrap().command, Command::Repl);
+        assert_eq!(parse(&["lsp"]).unwrap().command, Command::Lsp);
+    }
+
+    #[test]
+    fn fmt_takes_inputs_and_check() {
+        let params = parse(&["fmt", "--check", "a.x", "b.x"]).unwrap();
+
+        assert_eq!(params.command, Command::Fmt);
+        assert!(params.check);
+        assert_eq!(params.inputs.len(), 2);
+
+        assert_eq!(parse(&["fmt"]), Err(ParamsError::NoInput));
+        assert_eq!(
+            parse(&["--check", "a.x"]),
+            Err(ParamsError::UnknownOption(String::from("--check")))
+        );
+    }
+
+    #[test]
+    fn reports_invalid_arguments() {
+        assert_eq!(parse(&[]), Err(ParamsError::NoInput));
+        assert_eq!(
+            parse(&["a.x", "-o"]),
+            Err(ParamsError::MissingValue(String::from("-o")))
+        );
+        assert_eq!(
+            parse(&["--emit=bytecode", "a.x"]),
+            Err(ParamsError::UnknownEmit(String::from("bytecode")))
+        );
+        assert_eq!(
+            parse(&["--ast-format", "xml", "a.x"]),
+            Err(ParamsError::UnknownAstFormat(String::from("xml")))
+        );
+        assert_eq!(
+            parse(&["--fast", "a.x"]),
+            Err(ParamsError::UnknownOption(String::from("--fast")))
+        );
+        assert_eq!(parse(&["-", "-"]), Err(ParamsError::StdinUsedTwice));
+        assert_eq!(
+            parse(&["-o", "out", "a.x", "b.x"]),
+            Err(ParamsError::OutputWithManyInputs)
+        );
+    }
+
+    #[test]
+    fn output_stem_comes_from_input() {
+        assert_eq!(Input::File(String::from("samples/main.x")).stem(), "main");
+        assert_eq!(Input::Stdin.stem(), "out");
     }
 }
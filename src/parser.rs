@@ -1,225 +1,1525 @@
-use crate::declarations::{CompoundStmt, LiteralDecl, Literals, NodeType, VariableDecl};
+use crate::declarations::{
+    Aggregate, BinaryOp, CompoundStmt, Expr, ExprKind, FieldDecl, FieldRef, FieldType, Filter,
+    Function, InsertStmt, Item, Join, Literals, LoadStmt, Order, Predicate, Projection, Query,
+    RecordDecl, SaveStmt, Sort, Stmt, StmtKind, TableDecl, Test, Type, VariableDecl, VariableRef,
+};
+use crate::diagnostic::Diagnostic;
 
 use std::iter::Peekable;
+use std::vec;
 
 //TODO: move
-pub fn get_var_size(decl: &VariableDecl) -> u16 {
-    match decl.literal.value {
-        Literals::NUMBER(_) => 16, //NOTE: for now all numbers have the same size
-        Literals::STR(_) => 32,
+pub fn get_var_size(value: &Expr) -> u16 {
+    match value.kind {
+        ExprKind::Literal(Literals::STR(_)) => 32,
+        _ => 16, //NOTE: for now all numbers have the same size
     }
 }
 
 use crate::{
+    ast::{ExprId, StmtId, AST},
+    records::{names_match, singular, singular_name},
     symbols::CodeScope,
-    tokenizer::{Token, TokenType, Tokenizer},
+    tokenizer::{unescape, FloatType, IntType, Span, Token, TokenType, Tokenizer},
+    unicode::nfc,
 };
 
-pub struct AST {
-    pub nodes: Vec<NodeType>,
-}
+/// Tokens of the whole input, read before parsing so that tokenizer errors come first.
+type Tokens<'src> = Peekable<vec::IntoIter<Token<'src>>>;
 
+#[derive(Clone)]
 pub struct Parser {
     scopes: Vec<CodeScope>,
+    /// Offsets of the newlines in the source being parsed, for statements ending with the line
+    line_breaks: Vec<usize>,
 }
 
 impl Parser {
     pub fn new(scope: CodeScope) -> Self {
         Self {
             scopes: vec![scope],
+            line_breaks: vec![],
+        }
+    }
+
+    /// How many scopes opened by `tokens` are still waiting for their right brace.
+    /// Negative when there are more right braces than left ones.
+    pub fn brace_depth(tokens: &[Token]) -> isize {
+        tokens
+            .iter()
+            .fold(0, |depth, token| match token.token_type {
+                TokenType::LeftBrace => depth + 1,
+                TokenType::RightBrace => depth - 1,
+                _ => depth,
+            })
+    }
+
+    pub fn scopes(&self) -> &[CodeScope] {
+        &self.scopes
+    }
+
+    /// Finds the stack slot of `identifier`, starting at `scope_index` and going outwards.
+    fn lookup(&self, scope_index: usize, identifier: &str) -> Option<u16> {
+        let mut current_scope_index = Some(scope_index);
+        while let Some(index) = current_scope_index {
+            let scope = &self.scopes[index];
+            if let Some(offset) = scope.stack_offset(identifier) {
+                return Some(offset);
+            }
+            current_scope_index = scope.parent_scope;
         }
+        None
     }
 
-    pub fn parse(&mut self, tokenizer: Tokenizer) -> AST {
-        let mut ast = AST { nodes: vec![] };
+    pub fn parse(&mut self, tokenizer: Tokenizer) -> Result<AST, Diagnostic> {
+        let mut ast = AST::new();
+
+        let source = tokenizer.source();
+        self.line_breaks = line_breaks(source);
+        let tokens: Vec<Token> = tokenizer.collect::<Result<_, _>>()?;
+        let mut iter = tokens.into_iter().peekable();
 
-        let chars = &tokenizer.chars.clone(); //TODO: get rid of this clone!
-        let mut iter = tokenizer.peekable();
-        let mut current_scope_index: Option<usize> = Some(0);
+        let input = Span {
+            start: 0,
+            len: source.len(),
+        };
+        self.scopes[0].span = self.scopes[0].span.to(input);
 
-        let mut new_scope = CodeScope::new(None);
         while let Some(current_token) = iter.next() {
-            //Create new scope
-            if current_token.token_type == TokenType::LeftBrace {
-                new_scope = CodeScope::new(current_scope_index);
-                let scope_index = self.scopes.len();
-                self.scopes.push(new_scope.clone());
-                self.scopes[current_scope_index.unwrap()].add_nested_scope(scope_index);
-
-                current_scope_index = Some(scope_index);
+            if current_token.token_type == TokenType::RightBrace {
+                return Err(Diagnostic::new(current_token.span(), "Unmatched '}'"));
+            }
+
+            if current_token.token_type == TokenType::SEMICOLON {
                 continue;
             }
 
-            //Finalize current scope
-            if current_token.token_type == TokenType::RightBrace {
-                if current_scope_index.is_none() {
-                    panic!("Lone right brace!")
+            let stmt = self.match_stmt(0, current_token, &mut iter, &mut ast)?;
+
+            let item = ast.item_arena.alloc(Item::Stmt(stmt));
+            ast.items.push(item);
+        }
+
+        Ok(ast)
+    }
+
+    /// Moves the spans of scopes and symbols after an edit of the source.
+    pub fn shift_spans(&mut self, shift: impl Fn(Span) -> Span) {
+        for scope in &mut self.scopes {
+            scope.span = shift(scope.span);
+            for span in scope.symbols.values_mut().chain(scope.records.values_mut()) {
+                *span = shift(*span);
+            }
+        }
+        // The global scope covers the whole input, even when text is added at its start
+        let global = &mut self.scopes[0].span;
+        *global = Span {
+            start: 0,
+            len: global.end(),
+        };
+    }
+
+    /// Number of scopes in the tree rooted at `scope_index`. They are numbered in the
+    /// order their blocks start, so they take consecutive indices.
+    fn subtree_len(&self, scope_index: usize) -> usize {
+        1 + self.scopes[scope_index]
+            .nested_scopes()
+            .iter()
+            .map(|nested| self.subtree_len(*nested))
+            .sum::<usize>()
+    }
+
+    /// Removes the symbols declared after `position` from `scope_index` and the scopes
+    /// around it, returning them with their scope and stack slot.
+    fn hide_symbols_after(
+        &mut self,
+        scope_index: usize,
+        position: usize,
+    ) -> Vec<(usize, String, Span, u16)> {
+        let mut hidden = vec![];
+        let mut current_scope_index = Some(scope_index);
+        while let Some(index) = current_scope_index {
+            let scope = &mut self.scopes[index];
+            let later: Vec<String> = scope
+                .symbols
+                .iter()
+                .filter(|(_, span)| span.start > position)
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in later {
+                let span = scope.symbols.remove(&name).unwrap();
+                let offset = scope.stack_offsets.remove(&name).unwrap();
+                hidden.push((index, name, span, offset));
+            }
+            current_scope_index = scope.parent_scope;
+        }
+        hidden
+    }
+
+    /// Parses `block` again after an edit inside it, with `tokenizer` standing at its left
+    /// brace. Its scopes are replaced and the ones after them renumbered. The new block
+    /// takes over the id of the old one, whose nodes stay in the arenas, unreachable.
+    pub fn reparse_block(
+        &mut self,
+        tokenizer: Tokenizer,
+        ast: &mut AST,
+        block: StmtId,
+    ) -> Result<(), Diagnostic> {
+        let span = ast[block].span;
+        let StmtKind::Block(old) = &ast[block].kind else {
+            return Err(Diagnostic::new(span, "Only blocks can be parsed again"));
+        };
+        let (first, stack_offset) = (old.scope, old.stack_offset);
+        let Some(parent) = self.scopes[first].parent_scope else {
+            return Err(Diagnostic::new(
+                span,
+                "Only nested blocks can be parsed again",
+            ));
+        };
+        let old_len = self.subtree_len(first);
+
+        let later = self.scopes.split_off(first).split_off(old_len);
+        self.line_breaks = line_breaks(tokenizer.source());
+        let old_nodes = ast.stmt_arena.next_id();
+
+        // The block starts with the stack pointer its parent had at that point, and does
+        // not see what the scopes around it declare below it
+        let stack_pointer = std::mem::replace(&mut self.scopes[parent].stack_pointer, stack_offset);
+        let mut tokenizer = block_tokens(tokenizer)?.into_iter().peekable();
+        let left_brace = tokenizer
+            .next_if(|token| token.token_type == TokenType::LeftBrace)
+            .ok_or_else(|| Diagnostic::new(span, "Expected '{' at the start of the block"))?;
+        let hidden = self.hide_symbols_after(parent, left_brace.span().start);
+        let new_block = self.match_block(parent, left_brace, &mut tokenizer, ast)?;
+        self.scopes[parent].stack_pointer = stack_pointer;
+        for (index, name, span, offset) in hidden {
+            self.scopes[index].symbols.insert(name.clone(), span);
+            self.scopes[index].stack_offsets.insert(name, offset);
+        }
+
+        let new_end = self.scopes.len();
+        let renumber = |index: usize| match index >= first + old_len {
+            true => index + new_end - first - old_len,
+            false => index,
+        };
+        let renumber_scope = |scope: &mut CodeScope| {
+            scope.parent_scope = scope.parent_scope.map(renumber);
+            for nested in scope.nested_scopes_mut() {
+                *nested = renumber(*nested);
+            }
+        };
+
+        // The new block got the index of the old one, which its parent already lists
+        self.scopes[parent].nested_scopes_mut().pop();
+        for scope in &mut self.scopes[..first] {
+            renumber_scope(scope);
+        }
+        for mut scope in later {
+            renumber_scope(&mut scope);
+            self.scopes.push(scope);
+        }
+
+        for (id, stmt) in ast.stmt_arena.iter_mut() {
+            match &mut stmt.kind {
+                StmtKind::Block(block) if id.index() < old_nodes.index() => {
+                    block.scope = renumber(block.scope)
                 }
+                _ => {}
+            }
+        }
+        ast.stmt_arena.swap(block, new_block);
+        Ok(())
+    }
 
-                let current_scope = &mut self.scopes[current_scope_index.unwrap()];
+    fn match_block(
+        &mut self,
+        parent_scope_index: usize,
+        left_brace: Token,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<StmtId, Diagnostic> {
+        //Create new scope
+        let scope_index = self.scopes.len();
+        let parent_scope = &mut self.scopes[parent_scope_index];
+
+        // Nested variables are placed below the ones of the enclosing scope
+        let mut new_scope = CodeScope::new(Some(parent_scope_index));
+        new_scope.stack_pointer = parent_scope.stack_pointer;
+        new_scope.span = left_brace.span();
+
+        let mut block = CompoundStmt::new(scope_index, new_scope.stack_pointer);
+
+        parent_scope.add_nested_scope(scope_index);
+        self.scopes.push(new_scope);
+
+        loop {
+            let Some(current_token) = tokenizer.next() else {
+                return Err(Diagnostic::new(left_brace.span(), "Unclosed '{'"));
+            };
+
+            //Finalize current scope
+            if current_token.token_type == TokenType::RightBrace {
+                let current_scope = &mut self.scopes[scope_index];
 
                 if current_scope.was_closed {
-                    panic!("Scope was already closed!")
+                    return Err(Diagnostic::new(current_token.span(), "Unmatched '}'"));
                 }
 
                 current_scope.was_closed = true;
-                current_scope_index = current_scope.parent_scope;
+                current_scope.span = current_scope.span.to(current_token.span());
+
+                let span = current_scope.span;
+                return Ok(ast.stmt_arena.alloc(Stmt {
+                    kind: StmtKind::Block(block),
+                    span,
+                }));
+            }
+
+            if current_token.token_type == TokenType::SEMICOLON {
                 continue;
             }
 
-            if (current_token == TokenType::FOR) {
-                let for_loop = self
-                    .match_for_loop(&mut new_scope, current_token, &mut iter, chars, &mut ast)
-                    .expect("Failed to parse for loop");
+            let stmt = self.match_stmt(scope_index, current_token, tokenizer, ast)?;
+            block.children.push(stmt);
+        }
+    }
+
+    fn match_stmt(
+        &mut self,
+        scope_index: usize,
+        current_token: Token,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<StmtId, Diagnostic> {
+        match current_token.token_type {
+            TokenType::LeftBrace => self.match_block(scope_index, current_token, tokenizer, ast),
+            //TODO: parse loop header and body
+            TokenType::FOR => Err(Diagnostic::new(
+                current_token.span(),
+                "'for' loops are not supported yet",
+            )),
+            TokenType::COMMENT(_) => {
+                let mut doc = vec![];
+                let mut doc_span = current_token.span();
+                let mut current_token = current_token;
+
+                while let TokenType::COMMENT(line) = current_token.token_type {
+                    doc.push(line);
+                    doc_span = doc_span.to(current_token.span());
+                    current_token = tokenizer.next().ok_or_else(|| {
+                        Diagnostic::new(doc_span, "Doc comment must be followed by 'let'")
+                    })?;
+                }
+
+                if current_token.token_type != TokenType::LET {
+                    return Err(Diagnostic::new(
+                        doc_span,
+                        "Doc comment must be followed by 'let'",
+                    ));
+                }
+
+                let stmt = self.match_stmt(scope_index, current_token, tokenizer, ast)?;
+                if let StmtKind::Let(decl) = &mut ast[stmt].kind {
+                    decl.doc = Some(doc.join("\n"));
+                }
+                Ok(stmt)
             }
+            TokenType::LET => {
+                let Some(ident_token) = tokenizer.next() else {
+                    return Err(Diagnostic::new(
+                        current_token.span(),
+                        "Expected a name after 'let'",
+                    ));
+                };
+                let Some(identifier) = self.match_ident(&ident_token) else {
+                    return Err(Diagnostic::new(
+                        ident_token.span(),
+                        "Expected a name after 'let'",
+                    ));
+                };
 
-            let stmt = self
-                .match_stmt(&mut new_scope, current_token, &mut iter, chars, &mut ast)
-                .expect("Failed to match statment");
+                // EQUAL sign
+                if tokenizer
+                    .next_if(|token| token.token_type == TokenType::EQUAL)
+                    .is_none()
+                {
+                    return Err(Diagnostic::new(
+                        ident_token.span(),
+                        format!("Expected '=' after {identifier}"),
+                    ));
+                }
+
+                let Some(next_token) = tokenizer.next() else {
+                    return Err(Diagnostic::new(
+                        ident_token.span(),
+                        "Expected a value after '='",
+                    ));
+                };
+
+                let value = self.match_expr(scope_index, next_token, tokenizer, ast)?;
+
+                let span = current_token.span().to(ast[value].span);
+                Self::match_stmt_end(span, tokenizer)?;
+
+                let scope = &mut self.scopes[scope_index];
+                let stack_offset = scope.stack_pointer;
+                scope.add_variable(&ident_token, stack_offset)?;
+                scope.stack_pointer += get_var_size(&ast[value]);
+
+                let decl = VariableDecl {
+                    stack_offset,
+                    identifier,
+                    value,
+                    doc: None,
+                };
 
-            //Add to root node
-            ast.nodes.push(NodeType::Stmt(stmt));
+                Ok(ast.stmt_arena.alloc(Stmt {
+                    kind: StmtKind::Let(decl),
+                    span,
+                }))
+            }
+            TokenType::IDENT(_)
+                if tokenizer
+                    .peek()
+                    .is_some_and(|token| token.token_type == TokenType::COLON) =>
+            {
+                self.match_record(scope_index, current_token, tokenizer, ast)
+            }
+            TokenType::IDENT(_)
+                if tokenizer
+                    .peek()
+                    .is_some_and(|token| token.token_type == TokenType::ARROW) =>
+            {
+                self.match_insert(current_token, tokenizer, ast)
+            }
+            TokenType::IDENT(word)
+                if word.eq_ignore_ascii_case("get")
+                    && tokenizer.peek().is_some_and(|token| {
+                        matches!(token.token_type, TokenType::IDENT(_))
+                            && self.same_line(current_token.span(), token.span())
+                    }) =>
+            {
+                self.match_get(current_token, tokenizer, ast)
+            }
+            TokenType::IDENT(word)
+                if word.eq_ignore_ascii_case("save")
+                    && tokenizer.peek().is_some_and(|token| {
+                        matches!(token.token_type, TokenType::IDENT(_))
+                            && self.same_line(current_token.span(), token.span())
+                    }) =>
+            {
+                self.match_save(current_token, tokenizer, ast)
+            }
+            TokenType::IDENT(word)
+                if word.eq_ignore_ascii_case("load")
+                    && tokenizer.peek().is_some_and(|token| {
+                        matches!(token.token_type, TokenType::STRING(_))
+                            && self.same_line(current_token.span(), token.span())
+                    }) =>
+            {
+                self.match_load(scope_index, current_token, tokenizer, ast)
+            }
+            _ => {
+                let expr = self.match_expr(scope_index, current_token, tokenizer, ast)?;
+                let span = ast[expr].span;
+                Self::match_stmt_end(span, tokenizer)?;
+
+                Ok(ast.stmt_arena.alloc(Stmt {
+                    kind: StmtKind::Expr(expr),
+                    span,
+                }))
+            }
         }
+    }
 
-        ast
+    /// `Name: Field Field: type ...` up to the end of the line, or a table when the line
+    /// ends after the `:`.
+    fn match_record(
+        &mut self,
+        scope_index: usize,
+        name_token: Token,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<StmtId, Diagnostic> {
+        let name = nfc(&self.match_ident(&name_token).unwrap()).into_owned();
+        let colon = tokenizer.next().unwrap().span();
+        let is_table = tokenizer
+            .peek()
+            .is_some_and(|token| !self.same_line(colon, token.span()));
+        if scope_index != 0 {
+            let what = if is_table { "Tables" } else { "Record types" };
+            return Err(Diagnostic::new(
+                name_token.span(),
+                format!("{what} are declared at the top level"),
+            ));
+        }
+        if is_table {
+            return self.match_table(name, name_token.span(), colon, tokenizer, ast);
+        }
+
+        let fields = self.match_fields(&name, self.line(colon.end()), tokenizer)?;
+        if fields.is_empty() {
+            return Err(Diagnostic::new(
+                name_token.span(),
+                format!("Record {name} has no fields"),
+            ));
+        }
+        let end = fields.last().unwrap().span;
+        self.match_line_end(end, tokenizer)?;
+        self.scopes[0].add_record(&name, name_token.span())?;
+
+        let span = name_token.span().to(end);
+        Ok(ast.stmt_arena.alloc(Stmt {
+            kind: StmtKind::Record(RecordDecl { name, fields }),
+            span,
+        }))
     }
 
-    fn match_for_loop(
+    /// Columns named on the line after `Collection:` and a row of values on each following
+    /// line, up to a blank line. The rows are records named after the collection in the
+    /// singular.
+    fn match_table(
         &mut self,
-        scope: &mut CodeScope,
-        current_token: Token,
-        tokenizer: &mut Peekable<Tokenizer>,
-        chars: &Vec<char>,
+        collection: String,
+        name_span: Span,
+        colon: Span,
+        tokenizer: &mut Tokens<'_>,
         ast: &mut AST,
-    ) -> Option<CompoundStmt> {
-        if current_token_token_type != TokenType::FOR {
-            return None;
+    ) -> Result<StmtId, Diagnostic> {
+        let record = singular_name(&collection);
+        let header = self.line(colon.end()) + 1;
+        let fields = self.match_fields(&record, header, tokenizer)?;
+        if fields.is_empty() {
+            return Err(Diagnostic::new(
+                name_span,
+                format!("Table {collection} needs column names on the next line"),
+            ));
+        }
+        let mut end = fields.last().unwrap().span;
+        if let Some(token) = tokenizer
+            .peek()
+            .filter(|token| self.same_line(end, token.span()))
+        {
+            return Err(Diagnostic::new(token.span(), "Expected a column name"));
         }
 
-        //expects space
-        let next_token = tokenizer.next();
-        if (next_token != ' ') {
-            panic!("space expected after for statment");
+        let mut rows: Vec<Vec<ExprId>> = vec![];
+        let mut line = header;
+        while tokenizer.peek().is_some_and(|token| {
+            !matches!(
+                token.token_type,
+                TokenType::SEMICOLON | TokenType::RightBrace
+            ) && self.line(token.start) == line + 1
+        }) {
+            line += 1;
+            let mut row = vec![];
+            while let Some(token) = tokenizer.next_if(|token| {
+                !matches!(
+                    token.token_type,
+                    TokenType::SEMICOLON | TokenType::RightBrace
+                ) && self.line(token.start) == line
+            }) {
+                let value = self.match_value(token, tokenizer, ast)?;
+                end = ast[value].span;
+                row.push(value);
+            }
+            rows.push(row);
         }
 
-        let literal = self.match_literal(current_token, tokenizer, chars, ast);
-        if self.match_digit(&current_token) {
-            let value: String = chars[current_token.start..current_token.start + current_token.len]
-                .into_iter()
-                .collect();
-            return Some(Literals::NUMBER(value));
+        self.match_line_end(end, tokenizer)?;
+        self.scopes[0].add_record(&record, name_span)?;
+        self.scopes[0]
+            .collections
+            .insert(collection.clone(), record.clone());
+
+        Ok(ast.stmt_arena.alloc(Stmt {
+            kind: StmtKind::Table(TableDecl {
+                record: RecordDecl {
+                    name: record.clone(),
+                    fields,
+                },
+                insert: InsertStmt {
+                    collection,
+                    record,
+                    rows,
+                },
+            }),
+            span: name_span.to(end),
+        }))
+    }
+
+    /// `Field Field: type ...` on the given line. A field without a type named after a
+    /// record declared before holds that record.
+    fn match_fields(
+        &self,
+        record: &str,
+        line: usize,
+        tokenizer: &mut Tokens<'_>,
+    ) -> Result<Vec<FieldDecl>, Diagnostic> {
+        let mut fields: Vec<FieldDecl> = vec![];
+        while let Some(field_token) = tokenizer.next_if(|token| {
+            matches!(token.token_type, TokenType::IDENT(_)) && self.line(token.start) == line
+        }) {
+            let field_name = nfc(&self.match_ident(&field_token).unwrap()).into_owned();
+            let mut end = field_token.span();
+            if fields.iter().any(|field| field.name == field_name) {
+                return Err(Diagnostic::new(
+                    end,
+                    format!("Field {field_name} already exists in {record}"),
+                ));
+            }
+
+            let ty = if tokenizer
+                .next_if(|token| token.token_type == TokenType::COLON)
+                .is_some()
+            {
+                let type_token = tokenizer.next_if(|token| self.same_line(end, token.span()));
+                end = type_token.as_ref().map_or(end, Token::span);
+                let type_name = type_token
+                    .and_then(|token| self.match_ident(&token))
+                    .unwrap_or_default();
+                Some(self.field_type(&type_name).ok_or_else(|| {
+                    Diagnostic::new(end, "Expected one of i64, f32, f64, str or a record type")
+                })?)
+            } else {
+                self.record_type(&field_name)
+            };
+
+            fields.push(FieldDecl {
+                name: field_name,
+                ty,
+                span: field_token.span().to(end),
+            });
         }
+        Ok(fields)
+    }
 
-        None
+    /// `Collection -> values, values` up to the end of a line not ending with `,`.
+    fn match_insert(
+        &mut self,
+        collection_token: Token,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<StmtId, Diagnostic> {
+        let collection = nfc(&self.match_ident(&collection_token).unwrap()).into_owned();
+        let record = singular(&collection)
+            .into_iter()
+            .find(|name| self.scopes[0].records.contains_key(name))
+            .ok_or_else(|| {
+                Diagnostic::new(
+                    collection_token.span(),
+                    format!("No record type for {collection}, declare one in the singular"),
+                )
+            })?;
+        let mut end = tokenizer.next().unwrap().span();
+
+        let mut rows: Vec<Vec<ExprId>> = vec![vec![]];
+        loop {
+            // A row may start on the next line after a ','
+            let after_comma = rows.len() > 1 && rows.last().unwrap().is_empty();
+            let Some(token) = tokenizer.next_if(|token| {
+                !matches!(
+                    token.token_type,
+                    TokenType::SEMICOLON | TokenType::RightBrace
+                ) && (after_comma || self.same_line(end, token.span()))
+            }) else {
+                break;
+            };
+
+            end = token.span();
+            if token.token_type == TokenType::COMMA {
+                if rows.last().unwrap().is_empty() {
+                    return Err(Diagnostic::new(end, "Expected values before ','"));
+                }
+                rows.push(vec![]);
+                continue;
+            }
+
+            let value = self.match_value(token, tokenizer, ast)?;
+            end = ast[value].span;
+            rows.last_mut().unwrap().push(value);
+        }
+
+        if rows.last().unwrap().is_empty() {
+            return Err(Diagnostic::new(end, "Expected values"));
+        }
+        self.match_line_end(end, tokenizer)?;
+        self.scopes[0]
+            .collections
+            .insert(collection.clone(), record.clone());
+
+        Ok(ast.stmt_arena.alloc(Stmt {
+            kind: StmtKind::Insert(InsertStmt {
+                collection,
+                record,
+                rows,
+            }),
+            span: collection_token.span().to(end),
+        }))
     }
 
-    fn match_stmt(
+    /// `get field, field and field from collection` and the clauses after it on one line.
+    /// Fields may be aggregates as in `average GDP`, and naming the collection in their place
+    /// lists whole rows, as in `get me a country with lowest GDP`.
+    /// Only the names are taken as written, the rest are matched in any case.
+    fn match_get(
         &mut self,
-        scope: &mut CodeScope,
-        current_token: Token,
-        tokenizer: &mut Peekable<Tokenizer>,
-        chars: &Vec<char>,
+        get_token: Token,
+        tokenizer: &mut Tokens<'_>,
         ast: &mut AST,
-    ) -> Option<CompoundStmt> {
-        let mut stmt = CompoundStmt::new(0);
+    ) -> Result<StmtId, Diagnostic> {
+        let (query, end) = self.match_query(get_token.span(), tokenizer, ast)?;
+        self.match_line_end(end, tokenizer)?;
+        Ok(ast.stmt_arena.alloc(Stmt {
+            kind: StmtKind::Query(query),
+            span: get_token.span().to(end),
+        }))
+    }
 
-        return match current_token.token_type {
-            TokenType::LET => {
-                dbg!("Matched LET. Starting to parse assigment");
-                let next_token = tokenizer.next();
+    /// `save <query> to "out.csv"`, the query written with or without `get`.
+    fn match_save(
+        &mut self,
+        save_token: Token,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<StmtId, Diagnostic> {
+        let mut start = save_token.span();
+        if let Some(token) = tokenizer.next_if(|token| is_word(token, "get")) {
+            start = token.span();
+        }
+        let (query, end) = self.match_query(start, tokenizer, ast)?;
+        let Some(to_token) =
+            tokenizer.next_if(|token| is_word(token, "to") && self.same_line(end, token.span()))
+        else {
+            return Err(Diagnostic::new(
+                end,
+                "Expected 'to' and a file name after the query",
+            ));
+        };
+        let Some(path_token) = tokenizer.next_if(|token| self.same_line(end, token.span())) else {
+            return Err(Diagnostic::new(
+                to_token.span(),
+                "Expected a file name in quotes",
+            ));
+        };
+        let path = self.match_path(&path_token)?;
+        self.match_line_end(path_token.span(), tokenizer)?;
+
+        Ok(ast.stmt_arena.alloc(Stmt {
+            kind: StmtKind::Save(SaveStmt { query, path }),
+            span: save_token.span().to(path_token.span()),
+        }))
+    }
+
+    /// `load "countries.csv" as Countries`, declaring the collection and the record type of
+    /// its rows, named as for tables. Their fields are only known once the file is read.
+    fn match_load(
+        &mut self,
+        scope_index: usize,
+        load_token: Token,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<StmtId, Diagnostic> {
+        let path_token = tokenizer.next().unwrap();
+        let path = self.match_path(&path_token)?;
+        let Some(as_token) = tokenizer.next_if(|token| {
+            (token.token_type == TokenType::AS || is_word(token, "as"))
+                && self.same_line(path_token.span(), token.span())
+        }) else {
+            return Err(Diagnostic::new(
+                path_token.span(),
+                "Expected 'as' and a collection name after the file",
+            ));
+        };
+        let Some(name_token) = tokenizer.next_if(|token| {
+            matches!(token.token_type, TokenType::IDENT(_))
+                && self.same_line(as_token.span(), token.span())
+        }) else {
+            return Err(Diagnostic::new(
+                as_token.span(),
+                "Expected a collection name after 'as'",
+            ));
+        };
+        if scope_index != 0 {
+            return Err(Diagnostic::new(
+                load_token.span(),
+                "Files are loaded at the top level",
+            ));
+        }
+        self.match_line_end(name_token.span(), tokenizer)?;
+
+        let collection = nfc(&self.match_ident(&name_token).unwrap()).into_owned();
+        let record = singular_name(&collection);
+        self.scopes[0].add_record(&record, name_token.span())?;
+        self.scopes[0]
+            .collections
+            .insert(collection.clone(), record.clone());
+
+        Ok(ast.stmt_arena.alloc(Stmt {
+            kind: StmtKind::Load(LoadStmt {
+                path,
+                collection,
+                record,
+            }),
+            span: load_token.span().to(name_token.span()),
+        }))
+    }
+
+    /// Text of a string literal naming a local file.
+    fn match_path(&self, token: &Token) -> Result<String, Diagnostic> {
+        let TokenType::STRING(raw) = token.token_type else {
+            return Err(Diagnostic::new(
+                token.span(),
+                "Expected a file name in quotes",
+            ));
+        };
+        let path = unescape(raw);
+        if path.contains("://") {
+            return Err(Diagnostic::new(
+                token.span(),
+                "Only local files can be loaded or saved",
+            ));
+        }
+        Ok(path)
+    }
+
+    /// Fields and clauses of a query after `start`, and the span of its last token.
+    fn match_query(
+        &mut self,
+        start: Span,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<(Query, Span), Diagnostic> {
+        let mut end = start;
+        if let Some(token) = tokenizer.next_if(|token| is_word(token, "me")) {
+            end = token.span();
+        }
 
-                if next_token.is_none() {
-                    panic!("No tokens after 'let' keyword")
+        let mut fields = vec![];
+        loop {
+            if let Some(token) = tokenizer.next_if(|token| {
+                ["a", "an", "the"]
+                    .iter()
+                    .any(|article| is_word(token, article))
+            }) {
+                end = token.span();
+            }
+            let field = self.match_projection(end, tokenizer)?;
+            end = match &field {
+                Projection::Field(field) => field.span,
+                Projection::Aggregate(aggregate) => aggregate.span,
+            };
+            fields.push(field);
+            if tokenizer
+                .next_if(|token| token.token_type == TokenType::COMMA || is_word(token, "and"))
+                .is_none()
+            {
+                break;
+            }
+        }
+
+        let source = match tokenizer.next_if(|token| {
+            (is_word(token, "from") || is_word(token, "of")) && self.same_line(end, token.span())
+        }) {
+            Some(token) => self.match_field_ref(token.span(), tokenizer)?,
+            None => match fields.as_slice() {
+                [Projection::Field(field)] if self.collection(&field.word).is_some() => {
+                    let Some(Projection::Field(field)) = fields.pop() else {
+                        unreachable!()
+                    };
+                    field
                 }
+                _ => return Err(Diagnostic::new(end, "Expected 'from' after the fields")),
+            },
+        };
+        end = source.span;
+        let Some((collection, record)) = self.collection(&source.word) else {
+            return Err(Diagnostic::new(
+                source.span,
+                format!("No collection named {}", source.word),
+            ));
+        };
 
-                let identifier = self
-                    .match_ident(&next_token.unwrap())
-                    .expect("Failed to match ident!");
+        let mut joins = vec![];
+        while let Some(token) =
+            tokenizer.next_if(|token| is_word(token, "join") && self.same_line(end, token.span()))
+        {
+            let joined = self.match_field_ref(token.span(), tokenizer)?;
+            let Some((collection, record)) = self.collection(&joined.word) else {
+                return Err(Diagnostic::new(
+                    joined.span,
+                    format!("No collection named {}", joined.word),
+                ));
+            };
+            end = joined.span;
 
-                let next_token = tokenizer.next();
+            let on = tokenizer
+                .next_if(|token| is_word(token, "on") && self.same_line(end, token.span()))
+                .map(|on| {
+                    let lhs = self.match_field_ref(on.span(), tokenizer)?;
+                    if tokenizer
+                        .next_if(|token| {
+                            matches!(token.token_type, TokenType::EQUAL | TokenType::EQ)
+                                || is_word(token, "is")
+                        })
+                        .is_none()
+                    {
+                        return Err(Diagnostic::new(
+                            lhs.span,
+                            "Expected '=' and a field after this",
+                        ));
+                    }
+                    let rhs = self.match_field_ref(lhs.span, tokenizer)?;
+                    end = rhs.span;
+                    Ok((lhs, rhs))
+                })
+                .transpose()?;
+            joins.push(Join {
+                collection,
+                record,
+                on,
+                span: token.span().to(end),
+            });
+        }
 
-                if next_token.is_none() {
-                    panic!("No token after identifier!");
+        let (mut with, mut filter, mut group, mut sort) = (None, None, None, None);
+        while let Some(token) = tokenizer.next_if(|token| {
+            ["with", "where", "whose", "group", "grouped", "sorted"]
+                .iter()
+                .any(|word| is_word(token, word))
+                && self.same_line(end, token.span())
+        }) {
+            end = token.span();
+            let word = self.match_ident(&token).unwrap().to_lowercase();
+            let given = match word.as_str() {
+                "with" => with.is_some(),
+                "where" | "whose" => filter.is_some(),
+                "group" | "grouped" => group.is_some(),
+                _ => sort.is_some(),
+            };
+            if given {
+                return Err(Diagnostic::new(
+                    end,
+                    format!("The query already has a '{word}' clause"),
+                ));
+            }
+            if word != "with" && word != "where" && word != "whose" {
+                match tokenizer.next_if(|token| is_word(token, "by")) {
+                    Some(by) => end = by.span(),
+                    None => {
+                        return Err(Diagnostic::new(
+                            end,
+                            format!("Expected 'by' after '{word}'"),
+                        ))
+                    }
                 }
+            }
 
-                // EQUAL sign
-                if next_token.unwrap().token_type != TokenType::EQUAL {
-                    panic!("No '=' found in assigment statment!");
+            match word.as_str() {
+                "with" => {
+                    let aggregate = match self.match_projection(end, tokenizer)? {
+                        Projection::Aggregate(
+                            aggregate @ Aggregate {
+                                function: Function::Min | Function::Max,
+                                field: Some(_),
+                                ..
+                            },
+                        ) => aggregate,
+                        _ => {
+                            return Err(Diagnostic::new(
+                                end,
+                                "Expected lowest or highest and a field after 'with'",
+                            ))
+                        }
+                    };
+                    end = aggregate.span;
+                    with = Some(aggregate);
+                }
+                "where" | "whose" => filter = Some(self.match_filter(&mut end, tokenizer, ast)?),
+                "group" | "grouped" => {
+                    let field = self.match_field_ref(end, tokenizer)?;
+                    end = field.span;
+                    group = Some(field);
+                }
+                _ => {
+                    let field = self.match_field_ref(end, tokenizer)?;
+                    end = field.span;
+                    let order = tokenizer
+                        .next_if(|token| match token.token_type {
+                            TokenType::IDENT(word) => {
+                                Order::from_name(word).is_some()
+                                    && self.same_line(end, token.span())
+                            }
+                            _ => false,
+                        })
+                        .map(|token| {
+                            end = token.span();
+                            Order::from_name(self.match_ident(&token).unwrap().as_str()).unwrap()
+                        });
+                    sort = Some(Sort { field, order });
                 }
+            }
+        }
 
-                let next_token = tokenizer.next()?;
+        let query = Query {
+            fields,
+            collection,
+            record,
+            joins,
+            with,
+            filter,
+            group,
+            sort,
+        };
+        Ok((query, end))
+    }
 
-                let literal_value = self
-                    .match_literal(next_token, tokenizer, chars, ast)
-                    .expect("Failed to match literal");
+    /// Collection named by a word of a query and the record type of its rows. A collection
+    /// written exactly as in the query wins over the plural of another one.
+    fn collection(&self, word: &str) -> Option<(String, String)> {
+        self.scopes[0]
+            .collections
+            .iter()
+            .filter(|(collection, _)| names_match(word, collection))
+            .min_by_key(|(collection, _)| (!collection.eq_ignore_ascii_case(word), *collection))
+            .map(|(collection, record)| (collection.clone(), record.clone()))
+    }
 
-                let literal = LiteralDecl {
-                    value: literal_value,
-                };
+    /// A field, or an aggregate function and the field it takes. `count` takes none when
+    /// `from` or `of` follow it.
+    fn match_projection(
+        &self,
+        after: Span,
+        tokenizer: &mut Tokens<'_>,
+    ) -> Result<Projection, Diagnostic> {
+        let first = self.match_field_ref(after, tokenizer)?;
+        let Some(function) = Function::from_word(&first.word) else {
+            return Ok(Projection::Field(first));
+        };
 
-                let decl = VariableDecl {
-                    stack_offset: scope.stack_pointer,
-                    identifier,
-                    literal,
-                };
+        let next = tokenizer.peek().filter(|token| {
+            matches!(token.token_type, TokenType::IDENT(_))
+                && self.same_line(first.span, token.span())
+        });
+        let field = match next {
+            Some(token)
+                if ["from", "of", "and"]
+                    .iter()
+                    .any(|word| is_word(token, word)) =>
+            {
+                if function != Function::Count {
+                    return Ok(Projection::Field(first));
+                }
+                None
+            }
+            Some(_) => Some(self.match_field_ref(first.span, tokenizer)?),
+            None if function == Function::Count => None,
+            None => return Ok(Projection::Field(first)),
+        };
 
-                scope.stack_pointer += get_var_size(&decl);
+        let span = field
+            .as_ref()
+            .map_or(first.span, |field| first.span.to(field.span));
+        Ok(Projection::Aggregate(Aggregate {
+            function,
+            field,
+            span,
+        }))
+    }
 
-                ast.nodes.push(NodeType::Variable(decl));
-                stmt.children.push(ast.nodes.len() - 1);
+    /// Tests joined by `or`, each a run of tests joined by `and`. `end` follows the last
+    /// token read.
+    fn match_filter(
+        &mut self,
+        end: &mut Span,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<Filter, Diagnostic> {
+        let mut filter = self.match_conjunction(end, tokenizer, ast)?;
+        while let Some(token) = tokenizer.next_if(|token| {
+            (is_word(token, "or") || token.token_type == TokenType::OR)
+                && self.same_line(*end, token.span())
+        }) {
+            *end = token.span();
+            let rhs = self.match_conjunction(end, tokenizer, ast)?;
+            filter = Filter::Or(Box::new(filter), Box::new(rhs));
+        }
+        Ok(filter)
+    }
 
-                return Some(stmt);
+    fn match_conjunction(
+        &mut self,
+        end: &mut Span,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<Filter, Diagnostic> {
+        let mut filter = self.match_test(end, tokenizer, ast)?;
+        while let Some(token) = tokenizer.next_if(|token| {
+            (is_word(token, "and") || token.token_type == TokenType::AND)
+                && self.same_line(*end, token.span())
+        }) {
+            *end = token.span();
+            let rhs = self.match_test(end, tokenizer, ast)?;
+            filter = Filter::And(Box::new(filter), Box::new(rhs));
+        }
+        Ok(filter)
+    }
+
+    /// `(filter)`, or a field, a predicate and a value as in `name starts with C` or
+    /// `GDP > 1000`.
+    fn match_test(
+        &mut self,
+        end: &mut Span,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<Filter, Diagnostic> {
+        if let Some(token) = tokenizer.next_if(|token| {
+            token.token_type == TokenType::LeftParenthesis && self.same_line(*end, token.span())
+        }) {
+            *end = token.span();
+            let filter = self.match_filter(end, tokenizer, ast)?;
+            match tokenizer.next_if(|token| token.token_type == TokenType::RightParenthesis) {
+                Some(token) => *end = token.span(),
+                None => return Err(Diagnostic::new(token.span(), "Unclosed '('")),
             }
-            _ => None,
+            return Ok(filter);
+        }
+
+        let field = self.match_field_ref(*end, tokenizer)?;
+        *end = field.span;
+        let token = tokenizer
+            .next_if(|token| self.same_line(*end, token.span()))
+            .ok_or_else(|| Diagnostic::new(*end, "Expected a test after the field"))?;
+        *end = token.span();
+
+        let predicate = match Predicate::from_token(&token.token_type) {
+            Some(predicate) => Some(predicate),
+            None => Self::match_predicate_words(&token, end, tokenizer)?,
+        };
+        let Some(predicate) = predicate else {
+            return Err(Diagnostic::new(
+                token.span(),
+                "Expected one of =, !=, <, <=, >, >=, is, starts with, ends with or contains",
+            ));
+        };
+
+        let value = tokenizer
+            .next_if(|token| self.same_line(*end, token.span()))
+            .ok_or_else(|| Diagnostic::new(*end, "Expected a value after this"))?;
+        let value = self.match_value(value, tokenizer, ast)?;
+        *end = ast[value].span;
+
+        Ok(Filter::Test(Test {
+            field,
+            predicate,
+            value,
+        }))
+    }
+
+    /// `is`, `contains`, `starts with` or `ends with`, reading the second word if any.
+    fn match_predicate_words(
+        token: &Token,
+        end: &mut Span,
+        tokenizer: &mut Tokens<'_>,
+    ) -> Result<Option<Predicate>, Diagnostic> {
+        let TokenType::IDENT(word) = &token.token_type else {
+            return Ok(None);
+        };
+        let (predicate, second) = match word.to_lowercase().as_str() {
+            "is" => return Ok(Some(Predicate::Eq)),
+            "contains" => return Ok(Some(Predicate::Contains)),
+            "starts" => (Predicate::StartsWith, "with"),
+            "ends" => (Predicate::EndsWith, "with"),
+            _ => return Ok(None),
+        };
+        match tokenizer.next_if(|token| is_word(token, second)) {
+            Some(token) => *end = token.span(),
+            None => {
+                return Err(Diagnostic::new(
+                    *end,
+                    format!("Expected '{second}' after this"),
+                ))
+            }
+        }
+        Ok(Some(predicate))
+    }
+
+    /// Word on the same line as `after`.
+    fn match_field_ref(
+        &self,
+        after: Span,
+        tokenizer: &mut Tokens<'_>,
+    ) -> Result<FieldRef, Diagnostic> {
+        let token = tokenizer
+            .next_if(|token| self.same_line(after, token.span()))
+            .ok_or_else(|| Diagnostic::new(after, "Expected a name after this"))?;
+        let Some(mut word) = self.match_ident(&token) else {
+            return Err(Diagnostic::new(token.span(), "Expected a name"));
+        };
+
+        // `Career.Wage`, written without spaces
+        let mut span = token.span();
+        while let Some(dot) = tokenizer
+            .next_if(|token| token.token_type == TokenType::DOT && token.start == span.end())
+        {
+            let field = tokenizer
+                .next_if(|token| token.start == dot.span().end())
+                .and_then(|token| self.match_ident(&token).map(|name| (name, token.span())));
+            let Some((name, field_span)) = field else {
+                return Err(Diagnostic::new(
+                    dot.span(),
+                    "Expected a field name after '.'",
+                ));
+            };
+            word = format!("{word}.{name}");
+            span = span.to(field_span);
+        }
+
+        Ok(FieldRef {
+            word: nfc(&word).into_owned(),
+            span,
+        })
+    }
+
+    /// Literal stored in a record, where a bare word is a string.
+    fn match_value(
+        &mut self,
+        token: Token,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<ExprId, Diagnostic> {
+        let mut span = token.span();
+        let literal = match token.token_type {
+            TokenType::IDENT(word) => Some(Literals::STR(word.to_string())),
+            TokenType::MINUS => self.match_negative(&mut span, tokenizer)?,
+            _ => self.match_literal(token)?,
         };
+
+        let literal =
+            literal.ok_or_else(|| Diagnostic::new(span, "Expected a word, string or number"))?;
+        Ok(ast.expr_arena.alloc(Expr {
+            kind: ExprKind::Literal(literal),
+            span,
+        }))
+    }
+
+    /// Number written right after the `-` at `span`, which grows to cover it.
+    fn match_negative(
+        &mut self,
+        span: &mut Span,
+        tokenizer: &mut Tokens<'_>,
+    ) -> Result<Option<Literals>, Diagnostic> {
+        let Some(number) = tokenizer.next_if(|number| number.start == span.end()) else {
+            return Ok(None);
+        };
+        *span = span.to(number.span());
+        match number.token_type {
+            TokenType::NUMBER(value, int_type) => {
+                Ok(Some(number_literal(-(value as i128), int_type, *span)?))
+            }
+            TokenType::FLOAT(..) => match self.match_literal(number)? {
+                Some(Literals::FLOAT(value, float_type)) => {
+                    Ok(Some(Literals::FLOAT(format!("-{value}"), float_type)))
+                }
+                _ => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn field_type(&self, name: &str) -> Option<FieldType> {
+        Type::from_field_name(name)
+            .map(FieldType::Scalar)
+            .or_else(|| self.record_type(name))
+    }
+
+    fn record_type(&self, name: &str) -> Option<FieldType> {
+        let name = nfc(name);
+        self.scopes[0]
+            .records
+            .contains_key(name.as_ref())
+            .then(|| FieldType::Record(name.into_owned()))
+    }
+
+    /// Zero based line of an offset into the source.
+    fn line(&self, offset: usize) -> usize {
+        self.line_breaks
+            .partition_point(|newline| *newline < offset)
+    }
+
+    /// Whether no line break separates the two spans.
+    fn same_line(&self, before: Span, after: Span) -> bool {
+        self.line(before.end()) == self.line(after.start)
+    }
+
+    /// Statements written line by line end with the line, with `;`, or before `}`.
+    fn match_line_end(&self, end: Span, tokenizer: &mut Tokens<'_>) -> Result<(), Diagnostic> {
+        match tokenizer.peek() {
+            Some(token) if token.token_type == TokenType::SEMICOLON => {
+                tokenizer.next();
+            }
+            Some(token)
+                if token.token_type != TokenType::RightBrace
+                    && self.same_line(end, token.span()) =>
+            {
+                return Err(Diagnostic::new(
+                    token.span(),
+                    "Expected the end of the line",
+                ))
+            }
+            _ => {}
+        }
+        Ok(())
     }
 
-    fn match_literal(
+    /// Statements end with `;`, or without it right before `}` or the end of input.
+    fn match_stmt_end(end: Span, tokenizer: &mut Tokens<'_>) -> Result<(), Diagnostic> {
+        match tokenizer.peek() {
+            Some(token) if token.token_type == TokenType::SEMICOLON => {
+                tokenizer.next();
+            }
+            Some(token) if token.token_type != TokenType::RightBrace => {
+                return Err(Diagnostic::new(end, "Expected ';' after this"))
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn match_expr(
         &mut self,
+        scope_index: usize,
         current_token: Token,
-        tokenizer: &mut Peekable<Tokenizer>,
-        chars: &Vec<char>,
+        tokenizer: &mut Tokens<'_>,
         ast: &mut AST,
-    ) -> Option<Literals> {
-        if self.match_digit(&current_token) {
-            let value: String = chars[current_token.start..current_token.start + current_token.len]
-                .into_iter()
-                .collect();
-            return Some(Literals::NUMBER(value));
+    ) -> Result<ExprId, Diagnostic> {
+        let lhs = self.match_operand(scope_index, current_token, tokenizer, ast)?;
+        self.match_binary(scope_index, lhs, 0, tokenizer, ast)
+    }
+
+    /// Precedence climbing: folds operators binding at least as tight as `min_precedence`.
+    fn match_binary(
+        &mut self,
+        scope_index: usize,
+        mut lhs: ExprId,
+        min_precedence: u8,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<ExprId, Diagnostic> {
+        let peek_op = |tokenizer: &mut Tokens<'_>| {
+            tokenizer
+                .peek()
+                .and_then(|token| BinaryOp::from_token(&token.token_type))
+        };
+
+        while let Some(op) = peek_op(tokenizer) {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            let op_span = tokenizer.next().unwrap().span();
+
+            let Some(operand_token) = tokenizer.next() else {
+                return Err(Diagnostic::new(
+                    op_span,
+                    format!("Expected a value after '{}'", op.symbol()),
+                ));
+            };
+            let mut rhs = self.match_operand(scope_index, operand_token, tokenizer, ast)?;
+
+            while let Some(next_op) = peek_op(tokenizer) {
+                if next_op.precedence() <= op.precedence() {
+                    break;
+                }
+                rhs = self.match_binary(scope_index, rhs, op.precedence() + 1, tokenizer, ast)?;
+            }
+
+            let span = ast[lhs].span.to(ast[rhs].span);
+            lhs = ast.expr_arena.alloc(Expr {
+                kind: ExprKind::Binary(op, lhs, rhs),
+                span,
+            });
         }
 
-        None
+        Ok(lhs)
     }
 
-    fn match_oper(
+    fn match_operand(
         &mut self,
+        scope_index: usize,
         current_token: Token,
-        tokenizer: &mut Peekable<Tokenizer>,
+        tokenizer: &mut Tokens<'_>,
         ast: &mut AST,
-    ) -> bool {
-        false
+    ) -> Result<ExprId, Diagnostic> {
+        let mut operand = self.match_primary(scope_index, current_token, tokenizer, ast)?;
+
+        // `as` binds tighter than any binary operator
+        while let Some(as_token) = tokenizer.next_if(|token| token.token_type == TokenType::AS) {
+            let Some(type_token) = tokenizer.next() else {
+                return Err(Diagnostic::new(
+                    as_token.span(),
+                    "Expected a type after 'as'",
+                ));
+            };
+            let ty = match type_token.token_type {
+                TokenType::IDENT(name) => Type::from_name(name),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                Diagnostic::new(type_token.span(), "Expected an integer type, f32 or f64")
+            })?;
+
+            let span = ast[operand].span.to(type_token.span());
+            operand = ast.expr_arena.alloc(Expr {
+                kind: ExprKind::Cast(operand, ty),
+                span,
+            });
+        }
+
+        Ok(operand)
     }
 
-    fn match_digit(&mut self, current_token: &Token) -> bool {
-        return match current_token.token_type {
-            TokenType::NUMBER(_) => true,
-            _ => false,
+    fn match_primary(
+        &mut self,
+        scope_index: usize,
+        current_token: Token,
+        tokenizer: &mut Tokens<'_>,
+        ast: &mut AST,
+    ) -> Result<ExprId, Diagnostic> {
+        if current_token.token_type == TokenType::LeftParenthesis {
+            let Some(next_token) = tokenizer.next() else {
+                return Err(Diagnostic::new(current_token.span(), "Unclosed '('"));
+            };
+            let inner = self.match_expr(scope_index, next_token, tokenizer, ast)?;
+
+            let Some(right_parenthesis) =
+                tokenizer.next_if(|token| token.token_type == TokenType::RightParenthesis)
+            else {
+                return Err(Diagnostic::new(current_token.span(), "Unclosed '('"));
+            };
+
+            // Grouping only changes the shape of the tree, the node covers the parentheses
+            let span = current_token.span().to(right_parenthesis.span());
+            ast[inner].span = span;
+            return Ok(inner);
+        }
+
+        let mut span = current_token.span();
+        if current_token.token_type == TokenType::MINUS {
+            let Some(literal) = self.match_negative(&mut span, tokenizer)? else {
+                return Err(Diagnostic::new(span, "Expected a number after '-'"));
+            };
+            return Ok(ast.expr_arena.alloc(Expr {
+                kind: ExprKind::Literal(literal),
+                span,
+            }));
+        }
+
+        let kind = if let Some(identifier) = self.match_ident(&current_token) {
+            let stack_offset = self
+                .lookup(scope_index, &identifier)
+                .ok_or_else(|| Diagnostic::new(span, format!("Unknown symbol {identifier}")))?;
+            ExprKind::Variable(VariableRef {
+                identifier,
+                stack_offset,
+            })
+        } else {
+            let Some(literal) = self.match_literal(current_token)? else {
+                return Err(Diagnostic::new(span, "Expected a value"));
+            };
+            ExprKind::Literal(literal)
         };
+
+        Ok(ast.expr_arena.alloc(Expr { kind, span }))
+    }
+
+    fn match_literal(&mut self, current_token: Token) -> Result<Option<Literals>, Diagnostic> {
+        if let TokenType::STRING(raw) = current_token.token_type {
+            return Ok(Some(Literals::STR(unescape(raw))));
+        }
+
+        if let TokenType::FLOAT(value, float_type) = current_token.token_type {
+            let text = match float_type {
+                FloatType::F32 => format!("{:?}", value as f32),
+                FloatType::F64 => format!("{value:?}"),
+            };
+            return Ok(Some(Literals::FLOAT(text, float_type)));
+        }
+
+        match current_token.token_type {
+            TokenType::NUMBER(value, int_type) => Ok(Some(number_literal(
+                value as i128,
+                int_type,
+                current_token.span(),
+            )?)),
+            _ => Ok(None),
+        }
     }
 
-    fn match_ident(&mut self, current_token: &Token) -> Option<String> {
-        return match &current_token.token_type {
-            TokenType::IDENT(value) => Some(value.clone()),
+    fn match_ident(&self, current_token: &Token) -> Option<String> {
+        match &current_token.token_type {
+            TokenType::IDENT(value) => Some(value.to_string()),
             _ => None,
+        }
+    }
+}
+
+/// Identifiers which only act as keywords inside queries, in any case.
+fn is_word(token: &Token, word: &str) -> bool {
+    matches!(token.token_type, TokenType::IDENT(ident) if ident.eq_ignore_ascii_case(word))
+}
+
+/// Literals are `i64` without a suffix and have to fit their type.
+fn number_literal(
+    value: i128,
+    int_type: Option<IntType>,
+    span: Span,
+) -> Result<Literals, Diagnostic> {
+    let int_type = int_type.unwrap_or(IntType::I64);
+    if !int_type.fits(value) {
+        return Err(Diagnostic::new(
+            span,
+            format!("{value} is out of range for {}", int_type.name()),
+        ));
+    }
+    Ok(Literals::NUMBER(value.to_string(), int_type))
+}
+
+/// Tokens up to the brace closing the first one, the rest of the input is left unread.
+fn block_tokens(tokenizer: Tokenizer) -> Result<Vec<Token>, Diagnostic> {
+    let mut tokens = vec![];
+    let mut depth = 0;
+    for token in tokenizer {
+        let token = token?;
+        depth += match token.token_type {
+            TokenType::LeftBrace => 1,
+            TokenType::RightBrace => -1,
+            _ => 0,
         };
+        tokens.push(token);
+        if depth <= 0 {
+            break;
+        }
     }
+    Ok(tokens)
+}
+
+fn line_breaks(source: &str) -> Vec<usize> {
+    source
+        .bytes()
+        .enumerate()
+        .filter(|(_, byte)| *byte == b'\n')
+        .map(|(offset, _)| offset)
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::declarations::{CompoundStmt, NodeType};
+    use crate::declarations::{
+        BinaryOp, ExprKind, FieldType, Filter, Item, Literals, Order, Predicate, Projection, Query,
+        StmtKind, Test, Type,
+    };
     use crate::symbols::CodeScope;
-    use crate::tokenizer::{Token, Tokenizer};
+    use crate::tokenizer::{FloatType, IntType, Tokenizer};
 
     use super::Parser;
 
@@ -231,15 +1531,700 @@ mod tests {
 
         let mut parser = Parser::new(global_scope);
 
-        let ast = parser.parse(tokenizer);
+        let ast = parser.parse(tokenizer).unwrap();
+
+        // Every statement is stored exactly once
+        assert_eq!(ast.items.len(), 2);
+        assert_eq!(ast.stmt_arena.iter().count(), 2);
+        assert_eq!(ast.expr_arena.iter().count(), 2);
+
+        let Item::Stmt(stmt) = ast[ast.items[1]];
+        let StmtKind::Let(decl) = &ast[stmt].kind else {
+            panic!("Expected let statement");
+        };
+        assert_eq!(decl.identifier, "y");
+        assert_eq!(decl.stack_offset, 16);
+        assert_eq!(
+            ast[decl.value].kind,
+            ExprKind::Literal(Literals::NUMBER(String::from("456"), IntType::I64))
+        );
+    }
+
+    #[test]
+    fn nests_blocks_in_their_parent() {
+        let mut parser = Parser::new(CodeScope::global());
+
+        let ast = parser
+            .parse(Tokenizer::new("{ let a = 1; { let b = 2; } }"))
+            .unwrap();
+
+        assert_eq!(ast.items.len(), 1);
+
+        let Item::Stmt(outer) = ast[ast.items[0]];
+        let StmtKind::Block(outer) = &ast[outer].kind else {
+            panic!("Expected block");
+        };
+        assert_eq!(outer.scope, 1);
+        assert_eq!(outer.children.len(), 2);
+
+        let StmtKind::Block(inner) = &ast[outer.children[1]].kind else {
+            panic!("Expected block");
+        };
+        assert_eq!(inner.scope, 2);
+        assert_eq!(inner.stack_offset, 16);
+    }
+
+    #[test]
+    fn respects_operator_precedence() {
+        let mut parser = Parser::new(CodeScope::global());
+
+        let ast = parser
+            .parse(Tokenizer::new(
+                "let x = 1;\nlet y = 2;\nlet z = x + 2 * y - 3;\n",
+            ))
+            .unwrap();
+
+        let Item::Stmt(stmt) = ast[ast.items[2]];
+        let StmtKind::Let(decl) = &ast[stmt].kind else {
+            panic!("Expected let statement");
+        };
+
+        // (x + (2 * y)) - 3
+        let ExprKind::Binary(BinaryOp::Sub, lhs, _) = ast[decl.value].kind else {
+            panic!("Expected subtraction");
+        };
+        let ExprKind::Binary(BinaryOp::Add, x, product) = ast[lhs].kind else {
+            panic!("Expected addition");
+        };
+        assert!(matches!(&ast[x].kind, ExprKind::Variable(var) if var.stack_offset == 0));
+        assert!(matches!(
+            ast[product].kind,
+            ExprKind::Binary(BinaryOp::Mul, _, _)
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_symbols() {
+        let mut parser = Parser::new(CodeScope::global());
+        let diagnostic = parser
+            .parse(Tokenizer::new("{ let a = 1; } let b = q;"))
+            .unwrap_err();
+
+        assert_eq!(diagnostic.message, "Unknown symbol q");
+        assert_eq!((diagnostic.span.start, diagnostic.span.len), (23, 1));
+    }
+
+    #[test]
+    fn reports_malformed_statements() {
+        let cases = [
+            ("}", "Unmatched '}'", (0, 1)),
+            ("{ let a = 1;", "Unclosed '{'", (0, 1)),
+            ("let", "Expected a name after 'let'", (0, 3)),
+            ("let 1 = 2;", "Expected a name after 'let'", (4, 1)),
+            ("let a 2;", "Expected '=' after a", (4, 1)),
+            ("let a =", "Expected a value after '='", (4, 1)),
+            ("let a = ;", "Expected a value", (8, 1)),
+            ("let a = 1 +", "Expected a value after '+'", (10, 1)),
+            ("let a = (1 + 2;", "Unclosed '('", (8, 1)),
+            ("let a = 1 as", "Expected a type after 'as'", (10, 2)),
+            ("let a = - 1;", "Expected a number after '-'", (8, 1)),
+            ("for", "'for' loops are not supported yet", (0, 3)),
+            (
+                "Point: X:\n",
+                "Expected one of i64, f32, f64, str or a record type",
+                (7, 1),
+            ),
+        ];
+
+        for (source, message, span) in cases {
+            let diagnostic = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(source))
+                .unwrap_err();
+            assert_eq!(diagnostic.message, message, "{source:?}");
+            assert_eq!(
+                (diagnostic.span.start, diagnostic.span.len),
+                span,
+                "{source:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_negative_literals_in_expressions() {
+        let mut parser = Parser::new(CodeScope::global());
+
+        let ast = parser
+            .parse(Tokenizer::new(
+                "let x = -1; let y = 2 * -1.5; let z = -128i8; let w = -9223372036854775808i64;",
+            ))
+            .unwrap();
 
-        assert_eq!(ast.nodes.len(), 8);
+        let values: Vec<_> = ast
+            .stmt_arena
+            .iter()
+            .filter_map(|(_, stmt)| match &stmt.kind {
+                StmtKind::Let(decl) => Some(decl.value),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            ast[values[0]].kind,
+            ExprKind::Literal(Literals::NUMBER("-1".to_string(), IntType::I64))
+        );
+        let ExprKind::Binary(BinaryOp::Mul, _, rhs) = ast[values[1]].kind else {
+            panic!("Expected multiplication");
+        };
+        assert!(
+            matches!(&ast[rhs].kind, ExprKind::Literal(Literals::FLOAT(value, _)) if value == "-1.5")
+        );
+        assert_eq!(
+            ast[values[2]].kind,
+            ExprKind::Literal(Literals::NUMBER("-128".to_string(), IntType::I8))
+        );
         assert_eq!(
-            ast.nodes[0],
-            NodeType::Stmt(CompoundStmt {
-                stack_offset: 0,
-                children: vec![]
+            ast[values[3]].kind,
+            ExprKind::Literal(Literals::NUMBER(
+                "-9223372036854775808".to_string(),
+                IntType::I64
+            ))
+        );
+
+        for (source, message) in [
+            ("let x = 128i8;", "128 is out of range for i8"),
+            (
+                "let x = -129i8;",
+                "Number literal '129i8' is out of range for i8 (max 127)",
+            ),
+        ] {
+            let error = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(source))
+                .unwrap_err();
+            assert_eq!(error.message, message, "{source}");
+        }
+    }
+
+    #[test]
+    fn groups_with_parentheses_without_whitespace() {
+        let mut parser = Parser::new(CodeScope::global());
+
+        let ast = parser
+            .parse(Tokenizer::new("let x=1;let y=(x+2)*3;;"))
+            .unwrap();
+
+        assert_eq!(ast.items.len(), 2);
+        let Item::Stmt(stmt) = ast[ast.items[1]];
+        let StmtKind::Let(decl) = &ast[stmt].kind else {
+            panic!("Expected let statement");
+        };
+
+        let ExprKind::Binary(BinaryOp::Mul, sum, _) = ast[decl.value].kind else {
+            panic!("Expected multiplication");
+        };
+        assert!(matches!(
+            ast[sum].kind,
+            ExprKind::Binary(BinaryOp::Add, _, _)
+        ));
+        assert_eq!((ast[sum].span.start, ast[sum].span.len), (14, 5));
+    }
+
+    #[test]
+    fn requires_semicolon_between_statements() {
+        let diagnostic = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new("let a = 1 let b = 2;"))
+            .unwrap_err();
+        assert_eq!(diagnostic.message, "Expected ';' after this");
+        assert_eq!((diagnostic.span.start, diagnostic.span.len), (0, 9));
+    }
+
+    #[test]
+    fn attaches_doc_comments_to_declarations() {
+        let mut parser = Parser::new(CodeScope::global());
+
+        let ast = parser
+            .parse(Tokenizer::new(
+                "## Monthly wage\n## before taxes\nlet wage = 2400;\nlet other = 1;\n",
+            ))
+            .unwrap();
+
+        let docs: Vec<_> = ast
+            .items
+            .iter()
+            .map(|item| {
+                let Item::Stmt(stmt) = ast[*item];
+                let StmtKind::Let(decl) = &ast[stmt].kind else {
+                    panic!("Expected let statement");
+                };
+                decl.doc.clone()
             })
+            .collect();
+
+        assert_eq!(
+            docs,
+            [Some(String::from("Monthly wage\nbefore taxes")), None]
+        );
+    }
+
+    #[test]
+    fn rejects_doc_comments_without_declaration() {
+        let diagnostic = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new("{ ## dangling\n}"))
+            .unwrap_err();
+        assert_eq!(diagnostic.message, "Doc comment must be followed by 'let'");
+        assert_eq!((diagnostic.span.start, diagnostic.span.len), (2, 11));
+    }
+
+    #[test]
+    fn declares_records_line_by_line() {
+        let mut parser = Parser::new(CodeScope::global());
+
+        let ast = parser
+            .parse(Tokenizer::new(
+                "Career: Name Wage: i64\nPerson: Name: str Age Career\nlet x = 1;\n",
+            ))
+            .unwrap();
+
+        assert_eq!(ast.items.len(), 3);
+        let fields = |index: usize| {
+            let Item::Stmt(stmt) = ast[ast.items[index]];
+            let StmtKind::Record(record) = &ast[stmt].kind else {
+                panic!("Expected record");
+            };
+            record
+                .fields
+                .iter()
+                .map(|field| (field.name.as_str(), field.ty.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            fields(0),
+            [
+                ("Name", None),
+                ("Wage", Some(FieldType::Scalar(Type::Int(IntType::I64))))
+            ]
         );
+        assert_eq!(
+            fields(1),
+            [
+                ("Name", Some(FieldType::Scalar(Type::Str))),
+                ("Age", None),
+                ("Career", Some(FieldType::Record(String::from("Career")))),
+            ]
+        );
+        assert_eq!(
+            parser.scopes()[0].records.keys().count(),
+            2,
+            "records are registered in the global scope"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_records() {
+        let cases = [
+            (
+                "{ Point: X Y }",
+                "Record types are declared at the top level",
+                (2, 5),
+            ),
+            ("Point: X Y X\n", "Field X already exists in Point", (11, 1)),
+            (
+                "Point: X: u8\n",
+                "Expected one of i64, f32, f64, str or a record type",
+                (10, 2),
+            ),
+            ("Point: X Y = 1\n", "Expected the end of the line", (11, 1)),
+            ("Point: ;\nlet a = 1;", "Record Point has no fields", (0, 5)),
+            (
+                "Point: X\nPoint: Y\n",
+                "Record Point already exists",
+                (9, 5),
+            ),
+        ];
+
+        for (source, message, span) in cases {
+            let diagnostic = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(source))
+                .unwrap_err();
+            assert_eq!(diagnostic.message, message, "{source:?}");
+            assert_eq!(
+                (diagnostic.span.start, diagnostic.span.len),
+                span,
+                "{source:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn appends_rows_on_one_line_or_after_commas() {
+        let mut parser = Parser::new(CodeScope::global());
+
+        let ast = parser.parse(Tokenizer::new(
+            "Career: Name Wage\nPerson: Name Age Career\nPersons -> Jacek 24 Driver 2400, Tomek 30 Architect 5000\nPersons -> Jacek 24 Driver    2400,\n           Tomek -30 \"Chief architect\" 5000\nlet x = 1;\n",
+        )).unwrap();
+
+        assert_eq!(ast.items.len(), 5);
+        let Item::Stmt(stmt) = ast[ast.items[3]];
+        let StmtKind::Insert(insert) = &ast[stmt].kind else {
+            panic!("Expected insert");
+        };
+        assert_eq!(
+            (insert.collection.as_str(), insert.record.as_str()),
+            ("Persons", "Person")
+        );
+
+        let rows: Vec<Vec<&ExprKind>> = insert
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|value| &ast[*value].kind).collect())
+            .collect();
+        let str = |value: &str| ExprKind::Literal(Literals::STR(String::from(value)));
+        let number =
+            |value: &str| ExprKind::Literal(Literals::NUMBER(String::from(value), IntType::I64));
+        assert_eq!(
+            rows[0],
+            [
+                &str("Jacek"),
+                &number("24"),
+                &str("Driver"),
+                &number("2400")
+            ]
+        );
+        assert_eq!(
+            rows[1],
+            [
+                &str("Tomek"),
+                &number("-30"),
+                &str("Chief architect"),
+                &number("5000")
+            ]
+        );
+        assert_eq!(parser.scopes()[0].collections["Persons"], "Person");
+    }
+
+    #[test]
+    fn reads_tables_up_to_a_blank_line() {
+        let mut parser = Parser::new(CodeScope::global());
+
+        let ast = parser.parse(Tokenizer::new(
+            "Countries:\nName     Code    GDP: f64\nPoland   PLN     1000.5\nCzech    CZK     -12.0\n\nlet x = 1;\n",
+        )).unwrap();
+
+        assert_eq!(ast.items.len(), 2);
+        let Item::Stmt(stmt) = ast[ast.items[0]];
+        let StmtKind::Table(table) = &ast[stmt].kind else {
+            panic!("Expected table");
+        };
+        assert_eq!(table.record.name, "Country");
+        let fields: Vec<_> = table
+            .record
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.ty.clone()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("Name", None),
+                ("Code", None),
+                ("GDP", Some(FieldType::Scalar(Type::Float(FloatType::F64))))
+            ]
+        );
+        assert_eq!(
+            (
+                table.insert.collection.as_str(),
+                table.insert.rows.len(),
+                table.insert.rows[1].len()
+            ),
+            ("Countries", 2, 3)
+        );
+        assert_eq!((ast[stmt].span.start, ast[stmt].span.len), (0, 83));
+        assert_eq!(parser.scopes()[0].collections["Countries"], "Country");
+        assert!(parser.scopes()[0].records.contains_key("Country"));
+    }
+
+    #[test]
+    fn rejects_malformed_tables() {
+        let cases = [
+            (
+                "Countries:\n\nName Code\n",
+                "Table Countries needs column names on the next line",
+                (0, 9),
+            ),
+            ("Countries:\nName 12\n", "Expected a column name", (16, 2)),
+            (
+                "{ Countries:\nName\n }",
+                "Tables are declared at the top level",
+                (2, 9),
+            ),
+            (
+                "Country: Name\nCountries:\nName\n",
+                "Record Country already exists",
+                (14, 9),
+            ),
+        ];
+
+        for (source, message, span) in cases {
+            let diagnostic = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(source))
+                .unwrap_err();
+            assert_eq!(diagnostic.message, message, "{source:?}");
+            assert_eq!(
+                (diagnostic.span.start, diagnostic.span.len),
+                span,
+                "{source:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_queries_in_any_case() {
+        let mut parser = Parser::new(CodeScope::global());
+
+        let ast = parser.parse(Tokenizer::new(
+            "Person: Name Age\nPersons -> Jacek 24\nGet names, ages and Name FROM PERSON sorted by age DESC\nget name from persons\n",
+        )).unwrap();
+
+        let queries: Vec<&Query> = ast
+            .items
+            .iter()
+            .filter_map(|item| {
+                let Item::Stmt(stmt) = ast[*item];
+                match &ast[stmt].kind {
+                    StmtKind::Query(query) => Some(query),
+                    _ => None,
+                }
+            })
+            .collect();
+        assert_eq!(queries.len(), 2);
+
+        let words: Vec<String> = queries[0].fields.iter().map(Projection::label).collect();
+        assert_eq!(words, ["names", "ages", "Name"]);
+        assert_eq!(
+            (queries[0].collection.as_str(), queries[0].record.as_str()),
+            ("Persons", "Person")
+        );
+        let sort = queries[0].sort.as_ref().unwrap();
+        assert_eq!(
+            (sort.field.word.as_str(), sort.order),
+            ("age", Some(Order::Desc))
+        );
+        assert_eq!(queries[1].sort, None);
+
+        let ast = parser.parse(Tokenizer::new(
+            "get names from persons whose name ends with k or age >= 20 and (name contains ce or age is 3)",
+        )).unwrap();
+        let Item::Stmt(stmt) = ast[ast.items[0]];
+        let StmtKind::Query(query) = &ast[stmt].kind else {
+            panic!("Expected query");
+        };
+        let Some(Filter::Or(lhs, rhs)) = &query.filter else {
+            panic!("Expected 'or' at the top");
+        };
+        assert!(matches!(
+            **lhs,
+            Filter::Test(Test {
+                predicate: Predicate::EndsWith,
+                ..
+            })
+        ));
+        let Filter::And(_, group) = &**rhs else {
+            panic!("Expected 'and' to bind tighter");
+        };
+        let predicates: Vec<Predicate> = group.tests().iter().map(|test| test.predicate).collect();
+        assert_eq!(predicates, [Predicate::Contains, Predicate::Eq]);
+        assert_eq!(query.filter.as_ref().unwrap().values().len(), 4);
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        let cases = [
+            (
+                "get names from people",
+                "No collection named people",
+                (15, 6),
+            ),
+            (
+                "get names persons",
+                "Expected 'from' after the fields",
+                (4, 5),
+            ),
+            (
+                "get names from\npersons",
+                "Expected a name after this",
+                (10, 4),
+            ),
+            ("get names, 1 from persons", "Expected a name", (11, 1)),
+            (
+                "get names from persons sorted wage",
+                "Expected 'by' after 'sorted'",
+                (23, 6),
+            ),
+            (
+                "get names from persons sorted by wage up",
+                "Expected the end of the line",
+                (38, 2),
+            ),
+            (
+                "get names from persons whose wage",
+                "Expected a test after the field",
+                (29, 4),
+            ),
+            (
+                "get names from persons whose wage + 1",
+                "Expected one of =, !=, <, <=, >, >=, is, starts with, ends with or contains",
+                (34, 1),
+            ),
+            (
+                "get names from persons whose name starts J",
+                "Expected 'with' after this",
+                (34, 6),
+            ),
+            (
+                "get names from persons where wage >",
+                "Expected a value after this",
+                (34, 1),
+            ),
+            (
+                "get names from persons where (wage > 1",
+                "Unclosed '('",
+                (29, 1),
+            ),
+            (
+                "get names from persons join people",
+                "No collection named people",
+                (28, 6),
+            ),
+            (
+                "get names from persons join persons on name wage",
+                "Expected '=' and a field after this",
+                (39, 4),
+            ),
+            (
+                "get Person. from persons",
+                "Expected a field name after '.'",
+                (10, 1),
+            ),
+            (
+                "get names from persons sorted by wage sorted by name",
+                "The query already has a 'sorted' clause",
+                (38, 6),
+            ),
+            (
+                "get names from persons group name",
+                "Expected 'by' after 'group'",
+                (23, 5),
+            ),
+            (
+                "get a person with wage",
+                "Expected lowest or highest and a field after 'with'",
+                (13, 4),
+            ),
+        ];
+
+        for (query, message, span) in cases {
+            let source = format!("Person: Name Wage\nPersons -> Jacek 2400\n{query}\n");
+            let offset = source.find("get").unwrap();
+            let diagnostic = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(&source))
+                .unwrap_err();
+            assert_eq!(diagnostic.message, message, "{query:?}");
+            assert_eq!(
+                (diagnostic.span.start - offset, diagnostic.span.len),
+                span,
+                "{query:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_rows() {
+        let cases = [
+            (
+                "Persons -> Jacek 24",
+                "No record type for Persons, declare one in the singular",
+                (0, 7),
+            ),
+            (
+                "Person: Name\nPersons -> Jacek,, Tomek",
+                "Expected values before ','",
+                (30, 1),
+            ),
+            (
+                "Person: Name\nPersons -> Jacek,",
+                "Expected values",
+                (29, 1),
+            ),
+            (
+                "Person: Name\nPersons -> Jacek + 1",
+                "Expected a word, string or number",
+                (30, 1),
+            ),
+            (
+                "Person: Name\nPersons -> - 1",
+                "Expected a word, string or number",
+                (24, 1),
+            ),
+        ];
+
+        for (source, message, span) in cases {
+            let diagnostic = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(source))
+                .unwrap_err();
+            assert_eq!(diagnostic.message, message, "{source:?}");
+            assert_eq!(
+                (diagnostic.span.start, diagnostic.span.len),
+                span,
+                "{source:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_loads_and_saves() {
+        let cases = [
+            (
+                "load \"a.csv\" Countries",
+                "Expected 'as' and a collection name after the file",
+                (5, 7),
+            ),
+            (
+                "load \"a.csv\" as\nCountries",
+                "Expected a collection name after 'as'",
+                (13, 2),
+            ),
+            (
+                "load \"https://x.org/a.csv\" as Countries",
+                "Only local files can be loaded or saved",
+                (5, 21),
+            ),
+            (
+                "{ load \"a.csv\" as Countries }",
+                "Files are loaded at the top level",
+                (2, 4),
+            ),
+            (
+                "Persons -> Jacek\nsave names from persons",
+                "Expected 'to' and a file name after the query",
+                (33, 7),
+            ),
+            (
+                "Persons -> Jacek\nsave names from persons to out",
+                "Expected a file name in quotes",
+                (44, 3),
+            ),
+        ];
+
+        for (source, message, span) in cases {
+            let source = format!("Person: Name\n{source}");
+            let diagnostic = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(&source))
+                .unwrap_err();
+            assert_eq!(diagnostic.message, message, "{source:?}");
+            assert_eq!(
+                (diagnostic.span.start - 13, diagnostic.span.len),
+                span,
+                "{source:?}"
+            );
+        }
     }
 }
@@ -0,0 +1,766 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::AST,
+    ast_dump::branches,
+    declarations::{
+        ExprKind, Filter, Function, Item, Literals, Order, Predicate, Projection, Query, SaveStmt,
+        StmtKind, TableDecl, Type,
+    },
+    records::{Column, Records},
+};
+
+/// Joined collections of at most this many rows are looped over instead of hashed.
+const NESTED_LOOP_ROWS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinMethod {
+    /// The joined rows are put in a table by their key, looked up by every row so far
+    Hash,
+    /// Every row so far is compared with every joined row
+    NestedLoop,
+}
+
+impl JoinMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JoinMethod::Hash => "hash",
+            JoinMethod::NestedLoop => "nested loop",
+        }
+    }
+}
+
+/// Filter of a plan, its fields resolved to the columns of the rows.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Test {
+        column: Column,
+        /// Index of the record holding the column among those of the query
+        record: usize,
+        predicate: Predicate,
+        value: Literals,
+    },
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    fn tests(&self) -> Vec<(&Column, usize)> {
+        match self {
+            Condition::Test { column, record, .. } => vec![(column, *record)],
+            Condition::And(lhs, rhs) | Condition::Or(lhs, rhs) => {
+                [lhs.tests(), rhs.tests()].concat()
+            }
+        }
+    }
+
+    /// Parts joined by `and`, each of which may be tested on its own.
+    fn conjuncts(self) -> Vec<Condition> {
+        match self {
+            Condition::And(lhs, rhs) => [lhs.conjuncts(), rhs.conjuncts()].concat(),
+            condition => vec![condition],
+        }
+    }
+
+    fn render(&self, in_and: bool) -> String {
+        match self {
+            Condition::Test {
+                column,
+                predicate,
+                value,
+                ..
+            } => {
+                let value = match value {
+                    Literals::STR(text) => format!("{text:?}"),
+                    Literals::NUMBER(text, _) | Literals::FLOAT(text, _) => text.clone(),
+                };
+                format!("{} {} {value}", column.name, predicate.symbol())
+            }
+            Condition::And(lhs, rhs) => format!("{} and {}", lhs.render(true), rhs.render(true)),
+            Condition::Or(lhs, rhs) if in_and => {
+                format!("({} or {})", lhs.render(false), rhs.render(false))
+            }
+            Condition::Or(lhs, rhs) => format!("{} or {}", lhs.render(false), rhs.render(false)),
+        }
+    }
+}
+
+/// Value of a result row: a column, or a function of it over the rows of a group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub function: Option<Function>,
+    /// Only `count` goes without one
+    pub column: Option<Column>,
+}
+
+impl Output {
+    fn label(&self) -> String {
+        let column = self.column.as_ref().map(|column| column.name.as_str());
+        match (self.function, column) {
+            (Some(function), Some(column)) => format!("{} {column}", function.name()),
+            (Some(function), None) => function.name().to_string(),
+            (None, column) => column.unwrap_or_default().to_string(),
+        }
+    }
+}
+
+/// Logical plan of a query: operators each reading the rows of their inputs. Columns are
+/// named after their record and found in the rows of the whole query, joined ones included.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Plan {
+    /// Rows of a collection, only `columns` of them read
+    Scan {
+        collection: String,
+        /// Index of the record among those of the query
+        index: usize,
+        columns: Vec<Column>,
+        /// Rows inserted before the query, unknown for loaded files
+        rows: Option<usize>,
+    },
+    Filter {
+        input: Box<Plan>,
+        condition: Condition,
+    },
+    /// Every row of `left` with the rows of `right` holding the same value
+    Join {
+        left: Box<Plan>,
+        right: Box<Plan>,
+        keys: (Column, Column),
+        method: JoinMethod,
+    },
+    Sort {
+        input: Box<Plan>,
+        column: Column,
+        order: Order,
+    },
+    /// First rows of the input, those tied with the last of them included with `ties`
+    Limit {
+        input: Box<Plan>,
+        count: usize,
+        ties: bool,
+    },
+    /// First `count` rows in `order` and those tied with them, without sorting the rest
+    TopK {
+        input: Box<Plan>,
+        column: Column,
+        order: Order,
+        count: usize,
+    },
+    /// A row for each value of `group`, or one for all rows without it
+    Aggregate {
+        input: Box<Plan>,
+        group: Option<Column>,
+        outputs: Vec<Output>,
+    },
+    Project {
+        input: Box<Plan>,
+        columns: Vec<Column>,
+    },
+}
+
+impl Plan {
+    fn inputs(&self) -> Vec<&Plan> {
+        match self {
+            Plan::Scan { .. } => vec![],
+            Plan::Join { left, right, .. } => vec![left, right],
+            Plan::Filter { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Limit { input, .. }
+            | Plan::TopK { input, .. }
+            | Plan::Aggregate { input, .. }
+            | Plan::Project { input, .. } => vec![input],
+        }
+    }
+
+    fn inputs_mut(&mut self) -> Vec<&mut Plan> {
+        match self {
+            Plan::Scan { .. } => vec![],
+            Plan::Join { left, right, .. } => vec![left, right],
+            Plan::Filter { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Limit { input, .. }
+            | Plan::TopK { input, .. }
+            | Plan::Aggregate { input, .. }
+            | Plan::Project { input, .. } => vec![input],
+        }
+    }
+
+    /// Records of the query whose rows the plan reads.
+    pub fn records(&self) -> Vec<usize> {
+        match self {
+            Plan::Scan { index, .. } => vec![*index],
+            plan => plan.inputs().into_iter().flat_map(Plan::records).collect(),
+        }
+    }
+
+    /// Most rows the plan gives, as far as known.
+    fn rows(&self) -> Option<usize> {
+        match self {
+            Plan::Scan { rows, .. } => *rows,
+            Plan::Join { .. } | Plan::Aggregate { .. } => None,
+            Plan::Filter { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Limit { input, .. }
+            | Plan::TopK { input, .. }
+            | Plan::Project { input, .. } => input.rows(),
+        }
+    }
+
+    fn label(&self) -> String {
+        let names = |columns: &[Column]| -> String {
+            let names: Vec<&str> = columns.iter().map(|column| column.name.as_str()).collect();
+            names.join(", ")
+        };
+
+        match self {
+            Plan::Scan {
+                collection,
+                columns,
+                rows,
+                ..
+            } => {
+                let mut label = format!("Scan {collection} [{}]", names(columns));
+                if let Some(rows) = rows {
+                    label += &format!(" rows={rows}");
+                }
+                label
+            }
+            Plan::Filter { condition, .. } => format!("Filter {}", condition.render(false)),
+            Plan::Join { keys, method, .. } => format!(
+                "Join {} on {} = {}",
+                method.name(),
+                keys.0.name,
+                keys.1.name
+            ),
+            Plan::Sort { column, order, .. } => format!("Sort {} {}", column.name, order.name()),
+            Plan::Limit { count, ties, .. } => {
+                let ties = if *ties { " with ties" } else { "" };
+                format!("Limit {count}{ties}")
+            }
+            Plan::TopK {
+                column,
+                order,
+                count,
+                ..
+            } => format!("TopK {count} {} {} with ties", column.name, order.name()),
+            Plan::Aggregate { group, outputs, .. } => {
+                let outputs: Vec<String> = outputs.iter().map(Output::label).collect();
+                let mut label = format!("Aggregate {}", outputs.join(", "));
+                if let Some(group) = group {
+                    label += &format!(" by {}", group.name);
+                }
+                label
+            }
+            Plan::Project { columns, .. } => format!("Project {}", names(columns)),
+        }
+    }
+
+    /// The plan as a tree, an operator per line above its inputs.
+    pub fn render(&self) -> String {
+        let mut out = format!("{}\n", self.label());
+        self.render_inputs("", &mut out);
+        out
+    }
+
+    fn render_inputs(&self, prefix: &str, out: &mut String) {
+        let inputs = self.inputs();
+        for (i, input) in inputs.iter().enumerate() {
+            let (line, child_prefix) = branches(prefix, i + 1 == inputs.len());
+            *out += &format!("{line}{}\n", input.label());
+            input.render_inputs(&child_prefix, out);
+        }
+    }
+}
+
+/// Plans every query of the program in order, with the rows inserted into each
+/// collection before it as far as they are known.
+pub fn plan_program(ast: &AST, records: &Records) -> Vec<Plan> {
+    let mut rows: HashMap<String, Option<usize>> = HashMap::new();
+    let mut plans = vec![];
+    for item in &ast.items {
+        let Item::Stmt(stmt) = ast[*item];
+        match &ast[stmt].kind {
+            StmtKind::Insert(insert) | StmtKind::Table(TableDecl { insert, .. }) => {
+                let count = rows.entry(insert.collection.clone()).or_insert(Some(0));
+                *count = count.map(|count| count + insert.rows.len());
+            }
+            StmtKind::Load(load) => {
+                rows.insert(load.collection.clone(), None);
+            }
+            StmtKind::Query(query) | StmtKind::Save(SaveStmt { query, .. }) => {
+                plans.push(plan_query(ast, records, &rows, query));
+            }
+            _ => {}
+        }
+    }
+    plans
+}
+
+/// Lowers the query and optimizes its plan, given the rows of each collection so far.
+pub fn plan_query(
+    ast: &AST,
+    records: &Records,
+    rows: &HashMap<String, Option<usize>>,
+    query: &Query,
+) -> Plan {
+    optimize(lower_query(ast, records, rows, query))
+}
+
+/// Plan of the query as written, before it is optimized.
+pub fn lower_query(
+    ast: &AST,
+    records: &Records,
+    rows: &HashMap<String, Option<usize>>,
+    query: &Query,
+) -> Plan {
+    let planner = Planner {
+        ast,
+        records,
+        rows,
+        names: query.records(),
+    };
+    planner.lower(query)
+}
+
+/// Rewrites a plan as first lowered: filters pushed below joins, sorts kept for the first
+/// rows turned into top-k, unused columns left unread and the method of each join chosen.
+pub fn optimize(plan: Plan) -> Plan {
+    let mut plan = top_k(push_filters(plan));
+    prune_columns(&mut plan, &mut vec![]);
+    choose_joins(&mut plan);
+    plan
+}
+
+struct Planner<'a> {
+    ast: &'a AST,
+    records: &'a Records,
+    rows: &'a HashMap<String, Option<usize>>,
+    /// Records of the rows of the query
+    names: Vec<String>,
+}
+
+impl Planner<'_> {
+    /// Column named after its record, and the index of the record.
+    fn qualify(&self, column: Column) -> (usize, Column) {
+        if self.names.len() > 1 {
+            return (column.path[0], column);
+        }
+        let name = format!("{}.{}", self.names[0], column.name);
+        (0, Column { name, ..column })
+    }
+
+    fn resolve(&self, word: &str) -> (usize, Column) {
+        let column = self
+            .records
+            .resolve_joined(&self.names, word)
+            .unwrap_or_else(|message| panic!("{message}"));
+        self.qualify(column)
+    }
+
+    /// Columns a listed word stands for, every column of a nested record it names.
+    fn listed(&self, word: &str) -> Vec<Column> {
+        self.records
+            .listed(&self.names, word)
+            .unwrap_or_else(|message| panic!("{message}"))
+            .into_iter()
+            .map(|column| self.qualify(column).1)
+            .collect()
+    }
+
+    fn columns(&self) -> Vec<(usize, Column)> {
+        self.records
+            .joined_columns(&self.names)
+            .into_iter()
+            .map(|column| self.qualify(column))
+            .collect()
+    }
+
+    fn scan(&self, index: usize, collection: &str) -> Plan {
+        Plan::Scan {
+            collection: collection.to_string(),
+            index,
+            columns: self
+                .columns()
+                .into_iter()
+                .filter(|(record, _)| *record == index)
+                .map(|(_, column)| column)
+                .collect(),
+            rows: self.rows.get(collection).copied().flatten(),
+        }
+    }
+
+    fn condition(&self, filter: &Filter) -> Condition {
+        match filter {
+            Filter::Test(test) => {
+                let (record, column) = self.resolve(&test.field.word);
+                let ExprKind::Literal(value) = &self.ast[test.value].kind else {
+                    panic!("Filters test against literals");
+                };
+                Condition::Test {
+                    column,
+                    record,
+                    predicate: test.predicate,
+                    value: value.clone(),
+                }
+            }
+            Filter::And(lhs, rhs) => {
+                Condition::And(Box::new(self.condition(lhs)), Box::new(self.condition(rhs)))
+            }
+            Filter::Or(lhs, rhs) => {
+                Condition::Or(Box::new(self.condition(lhs)), Box::new(self.condition(rhs)))
+            }
+        }
+    }
+
+    /// Operators in the order the interpreter applies the clauses: joins, the filter,
+    /// `with` as the first rows after sorting, the sort, then grouping or listing.
+    fn lower(&self, query: &Query) -> Plan {
+        let mut plan = self.scan(0, &query.collection);
+        for (index, join) in query.joins.iter().enumerate() {
+            let on = join
+                .on
+                .as_ref()
+                .map(|(lhs, rhs)| (lhs.word.as_str(), rhs.word.as_str()));
+            let (left, right) = self
+                .records
+                .join_keys(&self.names[..index + 2], on)
+                .unwrap_or_else(|message| panic!("{message}"));
+            plan = Plan::Join {
+                left: Box::new(plan),
+                right: Box::new(self.scan(index + 1, &join.collection)),
+                keys: (left, right),
+                method: JoinMethod::Hash,
+            };
+        }
+
+        if let Some(filter) = &query.filter {
+            plan = Plan::Filter {
+                input: Box::new(plan),
+                condition: self.condition(filter),
+            };
+        }
+        if let Some(with) = &query.with {
+            let (_, column) = self.resolve(&with.field.as_ref().unwrap().word);
+            let order = match with.function {
+                Function::Min => Order::Asc,
+                _ => Order::Desc,
+            };
+            plan = Plan::Limit {
+                input: Box::new(Plan::Sort {
+                    input: Box::new(plan),
+                    column,
+                    order,
+                }),
+                count: 1,
+                ties: true,
+            };
+        }
+        if let Some(sort) = &query.sort {
+            let (_, column) = self.resolve(&sort.field.word);
+            let order = sort.order.unwrap_or(match column.ty {
+                Some(Type::Str) => Order::Asc,
+                _ => Order::Desc,
+            });
+            plan = Plan::Sort {
+                input: Box::new(plan),
+                column,
+                order,
+            };
+        }
+
+        let mut outputs: Vec<Output> = match query.fields.as_slice() {
+            [] => self
+                .columns()
+                .into_iter()
+                .map(|(_, column)| Output {
+                    function: None,
+                    column: Some(column),
+                })
+                .collect(),
+            fields => fields
+                .iter()
+                .flat_map(|field| match field {
+                    Projection::Field(field) => self
+                        .listed(&field.word)
+                        .into_iter()
+                        .map(|column| Output {
+                            function: None,
+                            column: Some(column),
+                        })
+                        .collect(),
+                    Projection::Aggregate(aggregate) => vec![Output {
+                        function: Some(aggregate.function),
+                        column: aggregate
+                            .field
+                            .as_ref()
+                            .map(|field| self.resolve(&field.word).1),
+                    }],
+                })
+                .collect(),
+        };
+        if query.group.is_some() || query.aggregates() {
+            let group = query
+                .group
+                .as_ref()
+                .map(|group| self.resolve(&group.word).1);
+            // Each group's row starts with its key unless an output holds it
+            if let Some(group) = &group {
+                if !outputs.iter().any(|output| {
+                    output.function.is_none()
+                        && output.column.as_ref().map(|column| &column.path) == Some(&group.path)
+                }) {
+                    outputs.insert(
+                        0,
+                        Output {
+                            function: None,
+                            column: Some(group.clone()),
+                        },
+                    );
+                }
+            }
+            return Plan::Aggregate {
+                input: Box::new(plan),
+                group,
+                outputs,
+            };
+        }
+        Plan::Project {
+            input: Box::new(plan),
+            columns: outputs
+                .into_iter()
+                .filter_map(|output| output.column)
+                .collect(),
+        }
+    }
+}
+
+/// Moves each part of a filter joined by `and` below the joins, onto the side holding
+/// every record it tests, so fewer rows are joined.
+fn push_filters(plan: Plan) -> Plan {
+    match plan {
+        Plan::Filter { input, condition } => {
+            let mut plan = push_filters(*input);
+            for part in condition.conjuncts() {
+                plan = push_down(plan, part);
+            }
+            plan
+        }
+        mut plan => {
+            for input in plan.inputs_mut() {
+                let taken = std::mem::replace(input, placeholder());
+                *input = push_filters(taken);
+            }
+            plan
+        }
+    }
+}
+
+fn push_down(plan: Plan, condition: Condition) -> Plan {
+    let tested: Vec<usize> = condition
+        .tests()
+        .into_iter()
+        .map(|(_, record)| record)
+        .collect();
+    match plan {
+        Plan::Join {
+            left,
+            right,
+            keys,
+            method,
+        } if tested.iter().all(|record| left.records().contains(record)) => Plan::Join {
+            left: Box::new(push_down(*left, condition)),
+            right,
+            keys,
+            method,
+        },
+        Plan::Join {
+            left,
+            right,
+            keys,
+            method,
+        } if tested.iter().all(|record| right.records().contains(record)) => Plan::Join {
+            left,
+            right: Box::new(push_down(*right, condition)),
+            keys,
+            method,
+        },
+        // Parts that stay together are tested in the order they were written
+        Plan::Filter {
+            input,
+            condition: before,
+        } => Plan::Filter {
+            input,
+            condition: Condition::And(Box::new(before), Box::new(condition)),
+        },
+        plan => Plan::Filter {
+            input: Box::new(plan),
+            condition,
+        },
+    }
+}
+
+/// Takes the place of an input while it is rewritten.
+fn placeholder() -> Plan {
+    Plan::Scan {
+        collection: String::new(),
+        index: 0,
+        columns: vec![],
+        rows: None,
+    }
+}
+
+/// Replaces sorting every row only to keep the first ones with keeping the first ones.
+fn top_k(plan: Plan) -> Plan {
+    match plan {
+        Plan::Limit {
+            input,
+            count,
+            ties: true,
+        } => match *input {
+            Plan::Sort {
+                input,
+                column,
+                order,
+            } => Plan::TopK {
+                input: Box::new(top_k(*input)),
+                column,
+                order,
+                count,
+            },
+            input => Plan::Limit {
+                input: Box::new(top_k(input)),
+                count,
+                ties: true,
+            },
+        },
+        mut plan => {
+            for input in plan.inputs_mut() {
+                let taken = std::mem::replace(input, placeholder());
+                *input = top_k(taken);
+            }
+            plan
+        }
+    }
+}
+
+/// Leaves the columns no operator above a scan uses out of it.
+fn prune_columns(plan: &mut Plan, used: &mut Vec<String>) {
+    let mut uses = |column: &Column| used.push(column.name.clone());
+    match plan {
+        Plan::Scan { columns, .. } => {
+            columns.retain(|column| used.contains(&column.name));
+            return;
+        }
+        Plan::Filter { condition, .. } => {
+            for (column, _) in condition.tests() {
+                uses(column);
+            }
+        }
+        Plan::Join { keys, .. } => {
+            uses(&keys.0);
+            uses(&keys.1);
+        }
+        Plan::Sort { column, .. } | Plan::TopK { column, .. } => uses(column),
+        Plan::Limit { .. } => {}
+        Plan::Aggregate { group, outputs, .. } => {
+            let columns = outputs.iter().filter_map(|output| output.column.as_ref());
+            for column in group.iter().chain(columns) {
+                uses(column);
+            }
+        }
+        Plan::Project { columns, .. } => {
+            for column in columns.iter() {
+                uses(column);
+            }
+        }
+    }
+    for input in plan.inputs_mut() {
+        prune_columns(input, used);
+    }
+}
+
+/// Joins through nested loops when the joined rows are known to be few enough that
+/// hashing them costs more than comparing every pair.
+fn choose_joins(plan: &mut Plan) {
+    if let Plan::Join { right, method, .. } = plan {
+        *method = match right.rows() {
+            Some(rows) if rows <= NESTED_LOOP_ROWS => JoinMethod::NestedLoop,
+            _ => JoinMethod::Hash,
+        };
+    }
+    for input in plan.inputs_mut() {
+        choose_joins(input);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+    use crate::symbols::CodeScope;
+    use crate::tokenizer::Tokenizer;
+    use crate::typeck::TypeChecker;
+
+    use super::plan_program;
+
+    fn plans(source: &str) -> Vec<String> {
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(source))
+            .unwrap();
+        let types = TypeChecker::new().check(&ast).unwrap();
+        plan_program(&ast, types.records())
+            .iter()
+            .map(|plan| plan.render())
+            .collect()
+    }
+
+    #[test]
+    fn pushes_filters_below_joins_and_prunes_columns() {
+        let source = std::fs::read_to_string("samples/careers.x").unwrap();
+        let source = format!("{source}get names from persons join careers whose wage > 3000 and age < 40 and (name is Ola or wage = 1)\n");
+
+        assert_eq!(
+            plans(&source),
+            [
+                "Project Person.Name, Career.Wage
+└── Sort Career.Wage desc
+    └── Join nested loop on Person.Career = Career.Name
+        ├── Scan Persons [Person.Name, Person.Career] rows=3
+        └── Scan Careers [Career.Name, Career.Wage] rows=2
+",
+                "Project Person.Name
+└── Filter Person.Name = \"Ola\" or Career.Wage = 1
+    └── Join nested loop on Person.Career = Career.Name
+        ├── Filter Person.Age < 40
+        │   └── Scan Persons [Person.Name, Person.Age, Person.Career] rows=3
+        └── Filter Career.Wage > 3000
+            └── Scan Careers [Career.Name, Career.Wage] rows=2
+",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_first_rows_without_sorting_and_hashes_many_rows() {
+        let rows: Vec<String> = (0..10).map(|index| format!("Job{index} {index}")).collect();
+        let source = format!(
+            "Person: Name Career\nCareer: Name Wage\nCareers -> {}\nPersons -> Jacek Job1\n\nget a person with lowest name\nget max wage of persons join careers group by Person.Name sorted by names\n",
+            rows.join(", ")
+        );
+
+        assert_eq!(
+            plans(&source),
+            [
+                "Project Person.Name, Person.Career
+└── TopK 1 Person.Name asc with ties
+    └── Scan Persons [Person.Name, Person.Career] rows=1
+",
+                "Aggregate Person.Name, max Career.Wage by Person.Name
+└── Sort Person.Name asc
+    └── Join hash on Person.Career = Career.Name
+        ├── Scan Persons [Person.Name, Person.Career] rows=1
+        └── Scan Careers [Career.Name, Career.Wage] rows=10
+",
+            ]
+        );
+    }
+}
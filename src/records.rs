@@ -0,0 +1,537 @@
+use crate::{
+    declarations::{FieldType, RecordDecl, Type},
+    tokenizer::FloatType,
+};
+
+/// Bytes a value takes and the boundary it starts on. Records also tell where each of
+/// their fields starts, in declaration order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub size: u16,
+    pub align: u16,
+    pub offsets: Vec<u16>,
+}
+
+impl Layout {
+    /// Strings are a pointer and a length.
+    pub fn scalar(ty: Type) -> Self {
+        let size = match ty {
+            Type::Int(_) | Type::Float(FloatType::F64) => 8,
+            Type::Float(FloatType::F32) => 4,
+            Type::Str => 16,
+        };
+
+        Layout {
+            size,
+            align: size.min(8),
+            offsets: vec![],
+        }
+    }
+}
+
+/// Record types a collection may hold, its name in the singular:
+/// `Persons` holds `Person` and `Countries` holds `Country`.
+pub fn singular(collection: &str) -> Vec<String> {
+    let mut names = vec![];
+    if let Some(stem) = collection.strip_suffix("ies") {
+        names.push(format!("{stem}y"));
+    }
+    if let Some(stem) = collection.strip_suffix("es") {
+        names.push(stem.to_string());
+    }
+    if let Some(stem) = collection.strip_suffix('s') {
+        names.push(stem.to_string());
+    }
+    names
+}
+
+/// Record type a table declares for its rows. Only `es` after a hissing sound is dropped
+/// whole, so `Boxes` holds `Box` but `Houses` holds `House`.
+pub fn singular_name(collection: &str) -> String {
+    if let Some(stem) = collection.strip_suffix("ies") {
+        return format!("{stem}y");
+    }
+    if let Some(stem) = collection.strip_suffix("es") {
+        if ["ss", "x", "z", "ch", "sh"]
+            .iter()
+            .any(|end| stem.ends_with(end))
+        {
+            return stem.to_string();
+        }
+    }
+    collection
+        .strip_suffix('s')
+        .unwrap_or(collection)
+        .to_string()
+}
+
+/// Column of record `names[index]` as found in rows joined from `names`.
+fn joined(names: &[String], index: usize, column: Column) -> Column {
+    Column {
+        path: [vec![index], column.path].concat(),
+        name: format!("{}.{}", names[index], column.name),
+        ..column
+    }
+}
+
+/// Whether a word written in a query names `name`, ignoring case and the plural.
+pub fn names_match(word: &str, name: &str) -> bool {
+    let (word, name) = (word.to_lowercase(), name.to_lowercase());
+    word == name || singular(&word).contains(&name) || singular(&name).contains(&word)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub ty: Option<FieldType>,
+    /// Characters from the field to the next one where it was declared, so results line
+    /// up as the header of a table does
+    pub width: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+/// Scalar field reached from a record, through nested records as in `Career.Wage`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    /// Index of the field in each record on the way
+    pub path: Vec<usize>,
+    pub name: String,
+    pub ty: Option<Type>,
+    pub width: usize,
+}
+
+/// Record types declared so far. Fields declared without a type get one from the first
+/// values stored in them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Records {
+    records: Vec<Record>,
+}
+
+impl Records {
+    pub fn declare(&mut self, decl: &RecordDecl) {
+        let fields = decl
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| Field {
+                name: field.name.clone(),
+                ty: field.ty.clone(),
+                width: decl.fields.get(index + 1).map_or(field.span.len, |next| {
+                    next.span.start.saturating_sub(field.span.start)
+                }),
+            })
+            .collect();
+
+        self.records.push(Record {
+            name: decl.name.clone(),
+            fields,
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Record> {
+        self.records.iter().find(|record| record.name == name)
+    }
+
+    /// Scalar fields in the order values of a row are written, nested records expanded in
+    /// place.
+    pub fn columns(&self, name: &str) -> Vec<Column> {
+        let Some(record) = self.get(name) else {
+            return vec![];
+        };
+
+        let mut columns = vec![];
+        for (index, field) in record.fields.iter().enumerate() {
+            match &field.ty {
+                Some(FieldType::Record(nested)) => {
+                    for column in self.columns(nested) {
+                        columns.push(Column {
+                            path: [vec![index], column.path].concat(),
+                            name: format!("{}.{}", field.name, column.name),
+                            ty: column.ty,
+                            width: column.width,
+                        });
+                    }
+                }
+                ty => columns.push(Column {
+                    path: vec![index],
+                    name: field.name.clone(),
+                    ty: match ty {
+                        Some(FieldType::Scalar(ty)) => Some(*ty),
+                        _ => None,
+                    },
+                    width: field.width,
+                }),
+            }
+        }
+        columns
+    }
+
+    /// Column a query names with `word`, by its whole name or its last field, as `wage` for
+    /// `Career.Wage`. Fields closer to the record win over nested ones.
+    pub fn resolve(&self, name: &str, word: &str) -> Result<Column, String> {
+        let mut closest = self.closest(name, word).into_iter();
+        let Some(column) = closest.next() else {
+            return Err(format!("{name} has no field {word}"));
+        };
+        if let Some(other) = closest.next() {
+            return Err(format!(
+                "{word} could be {} or {} of {name}",
+                column.name, other.name
+            ));
+        }
+        Ok(column)
+    }
+
+    fn closest(&self, name: &str, word: &str) -> Vec<Column> {
+        let matches: Vec<Column> = self
+            .columns(name)
+            .into_iter()
+            .filter(|column| {
+                let last = column.name.rsplit('.').next().unwrap();
+                names_match(word, last) || names_match(word, &column.name)
+            })
+            .collect();
+
+        let depth = matches.iter().map(|column| column.path.len()).min();
+        matches
+            .into_iter()
+            .filter(|column| Some(column.path.len()) == depth)
+            .collect()
+    }
+
+    /// Columns of rows joined from records `names`, each record's under its index and
+    /// named after it. A single record's are its own.
+    pub fn joined_columns(&self, names: &[String]) -> Vec<Column> {
+        if let [name] = names {
+            return self.columns(name);
+        }
+        (0..names.len())
+            .flat_map(|index| {
+                self.columns(&names[index])
+                    .into_iter()
+                    .map(move |column| joined(names, index, column))
+            })
+            .collect()
+    }
+
+    /// Column of rows joined from records `names`, in the first record that has a field
+    /// `word` unless it is written after a record, as `Career.Name`.
+    pub fn resolve_joined(&self, names: &[String], word: &str) -> Result<Column, String> {
+        if let [name] = names {
+            return self.resolve(name, word);
+        }
+
+        if let Some((record, field)) = word.split_once('.') {
+            if let Some(index) = names.iter().position(|name| names_match(record, name)) {
+                let column = self.resolve(&names[index], field)?;
+                return Ok(joined(names, index, column));
+            }
+        }
+        for (index, name) in names.iter().enumerate() {
+            if !self.closest(name, word).is_empty() {
+                let column = self.resolve(name, word)?;
+                return Ok(joined(names, index, column));
+            }
+        }
+        Err(format!("{} have no field {word}", names.join(" and ")))
+    }
+
+    /// Columns a word listed by a query stands for: the one it names, or every column of
+    /// the nested record a field holds, as `careers` for `Career` of a `Person`.
+    pub fn listed(&self, names: &[String], word: &str) -> Result<Vec<Column>, String> {
+        let message = match self.resolve_joined(names, word) {
+            Ok(column) => return Ok(vec![column]),
+            Err(message) => message,
+        };
+
+        // Columns of a nested record are named after the fields on the way to it
+        let columns = self.joined_columns(names);
+        let mut holders: Vec<(&[usize], String)> = vec![];
+        for column in &columns {
+            let fields: Vec<&str> = column.name.split('.').collect();
+            for depth in 1..fields.len() {
+                if names_match(word, fields[depth - 1]) {
+                    holders.push((&column.path[..depth], fields[..depth].join(".")));
+                }
+            }
+        }
+        let shallowest = holders.iter().map(|(path, _)| path.len()).min();
+        holders.retain(|(path, _)| Some(path.len()) == shallowest);
+        holders.dedup();
+
+        match holders.as_slice() {
+            [] => Err(message),
+            [(path, _)] => Ok(columns
+                .iter()
+                .filter(|column| column.path.starts_with(path))
+                .cloned()
+                .collect()),
+            [(_, first), (_, second), ..] => Err(format!(
+                "{word} could be {first} or {second} of {}",
+                names.join(" and ")
+            )),
+        }
+    }
+
+    /// Columns the last join of rows from records `names` compares: one of the records
+    /// before and one of the last. Without `on`, the field of a record before named after
+    /// the last one holds the value of its first field.
+    pub fn join_keys(
+        &self,
+        names: &[String],
+        on: Option<(&str, &str)>,
+    ) -> Result<(Column, Column), String> {
+        let last = names.len() - 1;
+        let (left, right) = match on {
+            Some((a, b)) => {
+                let (a, b) = (
+                    self.resolve_joined(names, a)?,
+                    self.resolve_joined(names, b)?,
+                );
+                match (a.path[0] == last, b.path[0] == last) {
+                    (false, true) => (a, b),
+                    (true, false) => (b, a),
+                    _ => {
+                        return Err(format!(
+                            "Join {} on a field of it and one of {}",
+                            names[last],
+                            names[..last].join(" or ")
+                        ))
+                    }
+                }
+            }
+            None => {
+                let reference = (0..last).find_map(|index| {
+                    self.columns(&names[index])
+                        .into_iter()
+                        .find(|column| {
+                            column.path.len() == 1 && names_match(&names[last], &column.name)
+                        })
+                        .map(|column| joined(names, index, column))
+                });
+                let key = self.columns(&names[last]).into_iter().next();
+                match (reference, key) {
+                    (Some(reference), Some(key)) => (reference, joined(names, last, key)),
+                    _ => {
+                        return Err(format!(
+                            "{} has no field named after {}, join on fields with 'on'",
+                            names[..last].join(" or "),
+                            names[last]
+                        ))
+                    }
+                }
+            }
+        };
+
+        match (left.ty, right.ty) {
+            (Some(left_ty), Some(right_ty)) if left_ty != right_ty => Err(format!(
+                "{} is {}, {} is {}",
+                left.name,
+                left_ty.name(),
+                right.name,
+                right_ty.name()
+            )),
+            _ => Ok((left, right)),
+        }
+    }
+
+    /// Settles the type of a field declared without one.
+    pub fn set_type(&mut self, name: &str, path: &[usize], ty: Type) {
+        let Some(record) = self.records.iter_mut().find(|record| record.name == name) else {
+            return;
+        };
+
+        match (&record.fields[path[0]].ty, &path[1..]) {
+            (Some(FieldType::Record(nested)), rest) if !rest.is_empty() => {
+                let nested = nested.clone();
+                self.set_type(&nested, rest, ty);
+            }
+            (_, _) => record.fields[path[0]].ty = Some(FieldType::Scalar(ty)),
+        }
+    }
+
+    /// Laid out like a C struct: every field aligned, the size rounded up to the largest
+    /// alignment. Fails on the first field whose type is not known yet.
+    pub fn layout(&self, name: &str) -> Result<Layout, String> {
+        let record = self
+            .get(name)
+            .ok_or_else(|| format!("Unknown record {name}"))?;
+
+        let mut layout = Layout {
+            size: 0,
+            align: 1,
+            offsets: vec![],
+        };
+        for field in &record.fields {
+            let field_layout = match &field.ty {
+                Some(FieldType::Scalar(ty)) => Layout::scalar(*ty),
+                Some(FieldType::Record(nested)) => self.layout(nested)?,
+                None => return Err(format!("Type of {name}.{} is not known yet", field.name)),
+            };
+
+            let offset = layout.size.next_multiple_of(field_layout.align);
+            layout.offsets.push(offset);
+            layout.size = offset + field_layout.size;
+            layout.align = layout.align.max(field_layout.align);
+        }
+        layout.size = layout.size.next_multiple_of(layout.align);
+
+        Ok(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::AST;
+    use crate::declarations::{Item, StmtKind, Type};
+    use crate::parser::Parser;
+    use crate::symbols::CodeScope;
+    use crate::tokenizer::Tokenizer;
+    use crate::tokenizer::{FloatType, IntType};
+
+    use super::{names_match, singular, singular_name, Layout, Records};
+
+    fn declare(source: &str) -> Records {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast: AST = parser.parse(Tokenizer::new(source)).unwrap();
+
+        let mut records = Records::default();
+        for item in &ast.items {
+            let Item::Stmt(stmt) = ast[*item];
+            if let StmtKind::Record(decl) = &ast[stmt].kind {
+                records.declare(decl);
+            }
+        }
+        records
+    }
+
+    #[test]
+    fn aligns_fields_and_nests_records() {
+        let records =
+            declare("Career: Name: str Wage: i64\nPerson: Name: str Ratio: f32 Age: i64 Career\n");
+
+        assert_eq!(
+            records.layout("Career"),
+            Ok(Layout {
+                size: 24,
+                align: 8,
+                offsets: vec![0, 16],
+            })
+        );
+        // The f32 leaves 4 bytes of padding before the next i64
+        assert_eq!(
+            records.layout("Person"),
+            Ok(Layout {
+                size: 56,
+                align: 8,
+                offsets: vec![0, 16, 24, 32],
+            })
+        );
+    }
+
+    #[test]
+    fn names_collections_in_the_plural() {
+        assert_eq!(singular("Persons"), ["Person"]);
+        assert_eq!(singular("Countries"), ["Country", "Countri", "Countrie"]);
+        assert_eq!(singular("Boxes"), ["Box", "Boxe"]);
+        assert!(singular("Data").is_empty());
+
+        assert_eq!(singular_name("Countries"), "Country");
+        assert_eq!(singular_name("Boxes"), "Box");
+        assert_eq!(singular_name("Houses"), "House");
+        assert_eq!(singular_name("Data"), "Data");
+
+        assert!(names_match("persons", "Persons"));
+        assert!(names_match("names", "Name"));
+        assert!(names_match("COUNTRY", "Countries"));
+        assert!(!names_match("age", "Wage"));
+    }
+
+    #[test]
+    fn needs_every_field_type() {
+        let records = declare("Career: Name Wage: f32\nPerson: Age: i64 Career\n");
+
+        assert_eq!(
+            records.layout("Person"),
+            Err(String::from("Type of Career.Name is not known yet"))
+        );
+
+        let mut records = records;
+        records.set_type("Career", &[0], Type::Str);
+        let columns: Vec<_> = records
+            .columns("Person")
+            .into_iter()
+            .map(|column| (column.path, column.name, column.ty))
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                (vec![0], String::from("Age"), Some(Type::Int(IntType::I64))),
+                (vec![1, 0], String::from("Career.Name"), Some(Type::Str)),
+                (
+                    vec![1, 1],
+                    String::from("Career.Wage"),
+                    Some(Type::Float(FloatType::F32))
+                ),
+            ]
+        );
+        assert_eq!(records.layout("Person").unwrap().size, 32);
+
+        assert_eq!(records.resolve("Person", "wages").unwrap().path, [1, 1]);
+        assert_eq!(
+            records.resolve("Person", "name").map(|column| column.name),
+            Ok(String::from("Career.Name"))
+        );
+        assert_eq!(
+            records
+                .resolve("Person", "Careers")
+                .map(|column| column.name),
+            Err(String::from("Person has no field Careers"))
+        );
+        let person = [String::from("Person")];
+        let listed = |records: &Records, names: &[String], word| {
+            records.listed(names, word).map(|columns| {
+                columns
+                    .into_iter()
+                    .map(|column| column.name)
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            listed(&records, &person, "Careers"),
+            Ok(vec![
+                String::from("Career.Name"),
+                String::from("Career.Wage")
+            ])
+        );
+        assert_eq!(
+            listed(&records, &person, "ages"),
+            Ok(vec![String::from("Age")])
+        );
+        assert_eq!(
+            listed(&records, &person, "bosses"),
+            Err(String::from("Person has no field bosses"))
+        );
+        let nested = declare("Boss: Name\nCareer: Name\nTeam: Boss Career\n");
+        assert_eq!(
+            nested.resolve("Team", "names").map(|column| column.name),
+            Err(String::from(
+                "names could be Boss.Name or Career.Name of Team"
+            ))
+        );
+        let team = [String::from("Team")];
+        assert_eq!(
+            listed(&nested, &team, "boss"),
+            Ok(vec![String::from("Boss.Name")])
+        );
+
+        let records = declare("Point: X: f32 Y: f32\n");
+        assert_eq!(records.layout("Point").unwrap().size, 8);
+        assert_eq!(records.layout("Point").unwrap().align, 4);
+    }
+}
@@ -0,0 +1,314 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    assembly::Assembler,
+    ast::AST,
+    diagnostic::Diagnostic,
+    driver::{dump_ast, dump_scopes, dump_tokens},
+    interpreter::{Interpreter, Value},
+    parser::Parser,
+    symbols::CodeScope,
+    tokenizer::{Token, TokenType, Tokenizer},
+    typeck::{TypeChecker, Types},
+};
+
+const HELP: &str = "\
+:ast      print the AST of the last input
+:tokens   print the tokens of the last input
+:asm      print the assembly of the last input
+:scopes   print all scopes collected so far
+:help     print this message
+:quit     exit the repl
+";
+
+/// Tree, types and bindings of an entry which ran.
+type Evaluated = (AST, Types, Vec<(String, Value)>);
+
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    /// Braces are not balanced yet, rows end with `,` or a table has no blank line after
+    /// it, more lines are needed.
+    Continue,
+    Output(String),
+    Quit,
+}
+
+/// Keeps the parser (and with it the global scope) alive between entries.
+pub struct Repl {
+    parser: Parser,
+    checker: TypeChecker,
+    interpreter: Interpreter,
+    pending: String,
+    last_source: String,
+    last_ast: Option<(AST, Types)>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            parser: Parser::new(CodeScope::global()),
+            checker: TypeChecker::new(),
+            interpreter: Interpreter::new(),
+            pending: String::new(),
+            last_source: String::new(),
+            last_ast: None,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn feed(&mut self, line: &str) -> Step {
+        if !self.is_pending() && line.trim_start().starts_with(':') {
+            return self.meta(line.trim());
+        }
+
+        self.pending += line;
+        self.pending.push('\n');
+
+        // Input which does not even tokenize is complete, evaluating it reports the error
+        let pending = &self.pending;
+        let tokens: Result<Vec<Token>, _> = Tokenizer::new(pending).collect();
+        let (depth, continues) = tokens.map_or((0, false), |tokens| {
+            let continues = tokens.last().is_some_and(|token| {
+                matches!(token.token_type, TokenType::COMMA | TokenType::COLON)
+            });
+            // Rows of a table follow its header up to a blank line
+            let table = matches!(tokens.as_slice(), [name, colon, next, ..]
+                if matches!(name.token_type, TokenType::IDENT(_))
+                    && colon.token_type == TokenType::COLON
+                    && pending[colon.start..next.start].contains('\n'));
+            (
+                Parser::brace_depth(&tokens),
+                continues || (table && !line.trim().is_empty()),
+            )
+        });
+        if depth > 0 || (depth == 0 && continues) {
+            return Step::Continue;
+        }
+
+        let source = std::mem::take(&mut self.pending);
+        if depth < 0 {
+            return Step::Output(String::from("error: unexpected '}'\n"));
+        }
+
+        self.eval(source)
+    }
+
+    fn eval(&mut self, source: String) -> Step {
+        let snapshot = (self.parser.clone(), self.checker.clone());
+
+        match self.run(&source) {
+            Ok((ast, types, bindings)) => {
+                let mut text = String::new();
+                for (identifier, value) in bindings {
+                    if identifier.is_empty() {
+                        text += &format!("{value}\n");
+                    } else {
+                        text += &format!("{identifier} = {value}\n");
+                    }
+                }
+
+                self.last_source = source;
+                self.last_ast = Some((ast, types));
+                Step::Output(text)
+            }
+            Err(errors) => {
+                (self.parser, self.checker) = snapshot;
+                Step::Output(render(&errors, &source))
+            }
+        }
+    }
+
+    fn run(&mut self, source: &str) -> Result<Evaluated, Vec<Diagnostic>> {
+        let ast = self
+            .parser
+            .parse(Tokenizer::new(source))
+            .map_err(|error| vec![error])?;
+        let types = self.checker.check(&ast)?;
+        let bindings = self.interpreter.run(&ast).map_err(|error| vec![error])?;
+        Ok((ast, types, bindings))
+    }
+
+    fn meta(&mut self, command: &str) -> Step {
+        let text = match command {
+            ":quit" | ":q" => return Step::Quit,
+            ":help" => HELP.to_string(),
+            ":scopes" => dump_scopes(self.parser.scopes()),
+            ":tokens" => {
+                // Only sources which ran are kept, so they tokenize
+                let tokens: Vec<Token> = Tokenizer::new(&self.last_source)
+                    .map_while(Result::ok)
+                    .collect();
+                dump_tokens(&tokens)
+            }
+            ":ast" => match &self.last_ast {
+                Some((ast, _)) => dump_ast(ast),
+                None => String::from("no input yet\n"),
+            },
+            ":asm" => match &self.last_ast {
+                Some((ast, types)) => {
+                    let mut assembler = Assembler::new("repl");
+                    match assembler.assemble_ast(ast, types.clone()) {
+                        Ok(()) => assembler.finish(),
+                        Err(errors) => render(&errors, &self.last_source),
+                    }
+                }
+                None => String::from("no input yet\n"),
+            },
+            _ => format!("unknown command '{command}', try :help\n"),
+        };
+
+        Step::Output(text)
+    }
+}
+
+fn render(errors: &[Diagnostic], source: &str) -> String {
+    errors
+        .iter()
+        .map(|error| format!("error: {}", error.render(source)))
+        .collect()
+}
+
+/// Reads entries from stdin until EOF or `:quit`.
+pub fn run() -> io::Result<()> {
+    let mut repl = Repl::new();
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    loop {
+        let prompt = if repl.is_pending() { ".. " } else { ">> " };
+        write!(stdout, "{prompt}")?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+
+        match repl.feed(line.trim_end_matches(['\n', '\r'])) {
+            Step::Continue => {}
+            Step::Output(text) => write!(stdout, "{text}")?,
+            Step::Quit => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Repl, Step};
+
+    fn output(step: Step) -> String {
+        match step {
+            Step::Output(text) => text,
+            other => panic!("Expected output, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn prints_bindings() {
+        let mut repl = Repl::new();
+
+        assert_eq!(output(repl.feed("let x = 10;")), "x = 10\n");
+        assert_eq!(output(repl.feed("let y = 20;")), "y = 20\n");
+        assert_eq!(output(repl.feed("x * y + 1")), "201\n");
+    }
+
+    #[test]
+    fn shows_diagnostics_with_the_source_line() {
+        let mut repl = Repl::new();
+
+        assert_eq!(
+            output(repl.feed("let x = 0x1FF_u8;")),
+            "error: 1:9: Number literal '0x1FF_u8' is out of range for u8 (max 255)\n  |\n1 | let x = 0x1FF_u8;\n  |         ^^^^^^^^\n"
+        );
+        assert_eq!(output(repl.feed("0x10 + 0b1")), "17\n");
+        assert_eq!(
+            output(repl.feed("let y = 1 / (2 - 2);")),
+            "error: 1:9: Division by zero\n  |\n1 | let y = 1 / (2 - 2);\n  |         ^^^^^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn names_and_strings_may_be_unicode() {
+        let mut repl = Repl::new();
+
+        assert_eq!(
+            output(repl.feed("let imię = \"Łódź\";")),
+            "imię = \"Łódź\"\n"
+        );
+        // `e` followed by a combining ogonek names the same variable
+        assert_eq!(output(repl.feed("imie\u{328}")), "\"Łódź\"\n");
+        assert!(output(repl.feed("imię + 1")).starts_with("error: 1:1: Cannot apply '+' to str"));
+    }
+
+    #[test]
+    fn global_scope_is_kept_between_entries() {
+        let mut repl = Repl::new();
+
+        repl.feed("let x = 10;");
+        assert!(output(repl.feed("let x = 11;"))
+            .starts_with("error: 1:5: Symbol x already exists in this scope\n"));
+
+        // The failed entry must not leave anything behind
+        assert_eq!(output(repl.feed("let z = 12;")), "z = 12\n");
+        let scopes = output(repl.feed(":scopes"));
+        assert!(scopes.contains("\"x\""));
+        assert!(scopes.contains("\"z\""));
+    }
+
+    #[test]
+    fn waits_for_unbalanced_braces() {
+        let mut repl = Repl::new();
+
+        assert_eq!(repl.feed("{"), Step::Continue);
+        assert_eq!(repl.feed("let a = 1;"), Step::Continue);
+        assert_eq!(repl.feed("{ let b = 2; }"), Step::Continue);
+        assert_eq!(output(repl.feed("}")), "a = 1\nb = 2\n");
+
+        assert_eq!(output(repl.feed("}")), "error: unexpected '}'\n");
+    }
+
+    #[test]
+    fn waits_for_rows_of_tables_and_after_a_comma() {
+        let mut repl = Repl::new();
+
+        assert_eq!(repl.feed("Countries:"), Step::Continue);
+        assert_eq!(repl.feed("Name    GDP"), Step::Continue);
+        assert_eq!(repl.feed("Poland  1000"), Step::Continue);
+        assert!(matches!(repl.feed(""), Step::Output(_)));
+
+        repl.feed("Career: Name Wage");
+        repl.feed("Person: Name Age Career");
+        assert_eq!(
+            repl.feed("Persons -> Jacek 24 Driver 2400,"),
+            Step::Continue
+        );
+        assert!(matches!(
+            repl.feed("Tomek 30 Architect 5000"),
+            Step::Output(_)
+        ));
+    }
+
+    #[test]
+    fn meta_commands_dump_last_input() {
+        let mut repl = Repl::new();
+
+        assert_eq!(output(repl.feed(":ast")), "no input yet\n");
+
+        repl.feed("let x = 7;");
+        assert!(output(repl.feed(":tokens")).contains("IDENT(\"x\")"));
+        assert!(output(repl.feed(":ast")).contains("Let x offset=0"));
+        assert!(output(repl.feed(":asm")).contains("mov  QWORD PTR [rbp-16], rax # x"));
+        assert_eq!(repl.feed(":quit"), Step::Quit);
+    }
+}
@@ -1,13 +1,24 @@
 use std::collections::HashMap;
 
-use crate::tokenizer::{Token, TokenType};
+use crate::diagnostic::Diagnostic;
+use crate::tokenizer::{Span, Token, TokenType};
+use crate::unicode::nfc;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CodeScope {
     pub stack_pointer: u16,
-    pub symbols: HashMap<String, Token>,
+    /// Declared names and where they were declared
+    pub symbols: HashMap<String, Span>,
+    /// Where each variable declared in this scope lives on the stack
+    pub stack_offsets: HashMap<String, u16>,
+    /// Record types and the names of their declarations, only used in the global scope
+    pub records: HashMap<String, Span>,
+    /// Collections filled with `->` and the record type of their rows, also global
+    pub collections: HashMap<String, String>,
     pub was_closed: bool,
     pub parent_scope: Option<usize>,
+    /// From the left brace to the right brace, or the whole input for the global scope
+    pub span: Span,
 
     nested_scopes: Vec<usize>,
 }
@@ -17,8 +28,12 @@ impl CodeScope {
         Self {
             stack_pointer: 0,
             symbols: HashMap::new(),
+            stack_offsets: HashMap::new(),
+            records: HashMap::new(),
+            collections: HashMap::new(),
             nested_scopes: vec![],
             parent_scope: None,
+            span: Span::default(),
             was_closed: false,
         }
     }
@@ -26,23 +41,68 @@ impl CodeScope {
         Self {
             stack_pointer: 0,
             symbols: HashMap::new(),
+            stack_offsets: HashMap::new(),
+            records: HashMap::new(),
+            collections: HashMap::new(),
             nested_scopes: vec![],
             parent_scope,
+            span: Span::default(),
             was_closed: false,
         }
     }
 
+    /// Stack slot of a variable declared in this very scope.
+    pub fn stack_offset(&self, identifier: &str) -> Option<u16> {
+        self.stack_offsets.get(nfc(identifier).as_ref()).copied()
+    }
+
+    pub fn nested_scopes(&self) -> &[usize] {
+        &self.nested_scopes
+    }
+
+    pub fn nested_scopes_mut(&mut self) -> &mut Vec<usize> {
+        &mut self.nested_scopes
+    }
+
     pub fn add_nested_scope(&mut self, nested_scope_index: usize) {
         self.nested_scopes.push(nested_scope_index);
     }
 
-    pub fn add_symbol(&mut self, token: Token) {
+    pub fn add_variable(&mut self, token: &Token, stack_offset: u16) -> Result<(), Diagnostic> {
+        self.add_symbol(token)?;
+
+        if let TokenType::IDENT(symbol) = token.token_type {
+            self.stack_offsets
+                .insert(nfc(symbol).into_owned(), stack_offset);
+        }
+        Ok(())
+    }
+
+    /// Takes the name in NFC and where the declaration names it.
+    pub fn add_record(&mut self, name: &str, span: Span) -> Result<(), Diagnostic> {
+        if self.records.contains_key(name) {
+            return Err(Diagnostic::new(
+                span,
+                format!("Record {name} already exists"),
+            ));
+        }
+        self.records.insert(name.to_string(), span);
+        Ok(())
+    }
+
+    /// Symbols are stored in NFC, so differently encoded spellings of a name clash.
+    pub fn add_symbol(&mut self, token: &Token) -> Result<(), Diagnostic> {
         match token.token_type {
-            TokenType::IDENT(ref symbol) => {
-                if self.symbols.contains_key(symbol) {
-                    panic!("Symbol {symbol} already exists in this scope");
+            TokenType::IDENT(symbol) => {
+                let symbol = nfc(symbol);
+                if self.symbols.contains_key(symbol.as_ref()) {
+                    return Err(Diagnostic::new(
+                        token.span(),
+                        format!("Symbol {symbol} already exists in this scope"),
+                    ));
                 }
-                self.symbols.insert(symbol.clone(), token);
+                self.symbols.insert(symbol.into_owned(), token.span());
+                Ok(())
             }
             _ => {
                 panic!("Cannot add non identifiers as symbols")
@@ -57,42 +117,79 @@ mod tests {
 
     use super::CodeScope;
 
-    #[should_panic]
     #[test]
     fn add_symbol_do_not_allow_duplicates() {
         let mut scope = CodeScope::new(None);
 
-        scope.add_symbol(Token {
-            token_type: TokenType::IDENT(String::from("a")),
+        let first = scope.add_symbol(&Token {
+            token_type: TokenType::IDENT("a"),
             start: 0,
             len: 1,
         });
 
-        scope.add_symbol(Token {
-            token_type: TokenType::IDENT(String::from("a")),
+        let second = scope.add_symbol(&Token {
+            token_type: TokenType::IDENT("a"),
             start: 2,
             len: 1,
         });
+
+        assert_eq!(first, Ok(()));
+        assert!(second.is_err());
     }
 
     #[test]
     fn add_different_symbol() {
         let mut scope = CodeScope::new(None);
 
-        scope.add_symbol(Token {
-            token_type: TokenType::IDENT(String::from("a")),
-            start: 0,
-            len: 1,
-        });
+        scope
+            .add_symbol(&Token {
+                token_type: TokenType::IDENT("a"),
+                start: 0,
+                len: 1,
+            })
+            .unwrap();
 
-        scope.add_symbol(Token {
-            token_type: TokenType::IDENT(String::from("b")),
-            start: 1,
-            len: 1,
-        });
+        scope
+            .add_symbol(&Token {
+                token_type: TokenType::IDENT("b"),
+                start: 1,
+                len: 1,
+            })
+            .unwrap();
 
-        assert_eq!(true, scope.symbols.contains_key("a"));
-        assert_eq!(true, scope.symbols.contains_key("b"));
+        assert!(scope.symbols.contains_key("a"));
+        assert!(scope.symbols.contains_key("b"));
         assert_eq!(2, scope.symbols.len());
     }
+
+    #[test]
+    fn compares_symbols_in_nfc() {
+        let mut scope = CodeScope::new(None);
+
+        scope
+            .add_variable(
+                &Token {
+                    token_type: TokenType::IDENT("żółw"),
+                    start: 0,
+                    len: 9,
+                },
+                0,
+            )
+            .unwrap();
+        assert_eq!(scope.stack_offset("z\u{307}o\u{301}\u{142}w"), Some(0));
+
+        let diagnostic = scope
+            .add_symbol(&Token {
+                token_type: TokenType::IDENT("z\u{307}o\u{301}\u{142}w"),
+                start: 10,
+                len: 12,
+            })
+            .unwrap_err();
+
+        assert_eq!(
+            diagnostic.message,
+            "Symbol żółw already exists in this scope"
+        );
+        assert_eq!((diagnostic.span.start, diagnostic.span.len), (10, 12));
+    }
 }
@@ -0,0 +1,642 @@
+use std::collections::HashMap;
+use std::ops::Index;
+
+use crate::{
+    ast::{walk_expr, walk_stmt, ExprId, StmtId, Visitor, AST},
+    csv,
+    declarations::{
+        ExprKind, FieldRef, Filter, Function, InsertStmt, Literals, Projection, Query, SaveStmt,
+        StmtKind, Type,
+    },
+    diagnostic::Diagnostic,
+    records::{Column, Records},
+};
+
+/// Type of every expression, indexed by its id.
+#[derive(Debug, Clone, Default)]
+pub struct Types {
+    exprs: Vec<Option<Type>>,
+    records: Records,
+}
+
+impl Types {
+    /// Every record type declared up to and including the checked program.
+    pub fn records(&self) -> &Records {
+        &self.records
+    }
+
+    /// Type of an expression, none when checking it failed.
+    pub fn get(&self, id: ExprId) -> Option<Type> {
+        self.exprs.get(id.index()).copied().flatten()
+    }
+
+    fn set(&mut self, id: ExprId, ty: Type) {
+        if self.exprs.len() <= id.index() {
+            self.exprs.resize(id.index() + 1, None);
+        }
+        self.exprs[id.index()] = Some(ty);
+    }
+}
+
+impl Index<ExprId> for Types {
+    type Output = Type;
+
+    fn index(&self, id: ExprId) -> &Self::Output {
+        self.exprs[id.index()]
+            .as_ref()
+            .expect("Expression was not type checked")
+    }
+}
+
+/// Figures out the type of every expression. Ints and floats never mix implicitly,
+/// one side has to be converted with `as`.
+/// Variables are tracked by stack slot, so one checker can be kept across repl entries.
+/// A statement failing the check is reported and checking goes on with the next one,
+/// expressions built from values without a type are skipped to report each mistake once.
+#[derive(Debug, Clone, Default)]
+pub struct TypeChecker {
+    variables: HashMap<u16, Type>,
+    records: Records,
+    types: Types,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&mut self, ast: &AST) -> Result<Types, Vec<Diagnostic>> {
+        let (types, diagnostics) = self.check_partial(ast);
+        match diagnostics.is_empty() {
+            true => Ok(types),
+            false => Err(diagnostics),
+        }
+    }
+
+    /// Types of everything that checks, along with the mistakes found in the rest.
+    pub fn check_partial(&mut self, ast: &AST) -> (Types, Vec<Diagnostic>) {
+        self.visit_program(ast);
+        let mut types = std::mem::take(&mut self.types);
+        types.records = self.records.clone();
+        (types, std::mem::take(&mut self.diagnostics))
+    }
+}
+
+impl TypeChecker {
+    /// Every row needs a value for each column, of its type. Columns declared without a type
+    /// take the type of their first value.
+    fn check_rows(&mut self, ast: &AST, insert: &InsertStmt) -> Result<(), Diagnostic> {
+        let record = &insert.record;
+
+        for row in &insert.rows {
+            let columns = self.records.columns(record);
+            if row.len() != columns.len() {
+                let names: Vec<&str> = columns.iter().map(|column| column.name.as_str()).collect();
+                let span = ast[row[0]].span.to(ast[*row.last().unwrap()].span);
+                return Err(Diagnostic::new(
+                    span,
+                    format!(
+                        "{record} needs {} values ({}), found {}",
+                        columns.len(),
+                        names.join(" "),
+                        row.len()
+                    ),
+                ));
+            }
+
+            for (column, value) in columns.iter().zip(row) {
+                let Some(ty) = self.types.get(*value) else {
+                    continue;
+                };
+                match column.ty {
+                    None => self.records.set_type(record, &column.path, ty),
+                    Some(expected) if expected != ty => {
+                        return Err(Diagnostic::new(
+                            ast[*value].span,
+                            format!(
+                                "{record}.{} is {}, found {}",
+                                column.name,
+                                expected.name(),
+                                ty.name()
+                            ),
+                        ))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every word of the query names one field of the rows, or when listed the field holding
+    /// a nested record. Next to aggregates, only the field the rows are grouped by may be
+    /// listed.
+    /// Filters test text with text and compare numbers with numbers of the same type.
+    fn check_query(&self, ast: &AST, query: &Query) -> Result<(), Diagnostic> {
+        let records = query.records();
+        for (index, join) in query.joins.iter().enumerate() {
+            let joined = &records[..index + 2];
+            let mut on = None;
+            if let Some((lhs, rhs)) = &join.on {
+                for field in [lhs, rhs] {
+                    self.records
+                        .resolve_joined(joined, &field.word)
+                        .map_err(|message| Diagnostic::new(field.span, message))?;
+                }
+                on = Some((lhs.word.as_str(), rhs.word.as_str()));
+            }
+            self.records
+                .join_keys(joined, on)
+                .map_err(|message| Diagnostic::new(join.span, message))?;
+        }
+
+        let resolve = |field: &FieldRef| {
+            self.records
+                .resolve_joined(&records, &field.word)
+                .map_err(|message| Diagnostic::new(field.span, message))
+        };
+        // Columns of joined rows are already named after their record
+        let qualified = |column: &Column| {
+            if query.joins.is_empty() {
+                format!("{}.{}", query.record, column.name)
+            } else {
+                column.name.clone()
+            }
+        };
+
+        let sorted = query.sort.iter().map(|sort| &sort.field);
+        for field in query.group.iter().chain(sorted) {
+            resolve(field)?;
+        }
+
+        let group = query.group.as_ref().map(resolve).transpose()?;
+        let group = group.map(|column| column.path);
+        let mut aggregates = vec![];
+        for field in &query.fields {
+            match field {
+                Projection::Field(field) if !query.aggregates() => {
+                    self.records
+                        .listed(&records, &field.word)
+                        .map_err(|message| Diagnostic::new(field.span, message))?;
+                }
+                Projection::Field(field) => {
+                    let column = resolve(field)?;
+                    if query.aggregates() && group.as_ref() != Some(&column.path) {
+                        return Err(Diagnostic::new(
+                            field.span,
+                            format!("{} is neither grouped by nor aggregated", field.word),
+                        ));
+                    }
+                }
+                Projection::Aggregate(aggregate) => aggregates.push(aggregate),
+            }
+        }
+        for aggregate in aggregates.into_iter().chain(&query.with) {
+            let Some(field) = &aggregate.field else {
+                continue;
+            };
+            let column = resolve(field)?;
+            let numeric = matches!(aggregate.function, Function::Avg | Function::Sum);
+            if numeric && column.ty == Some(Type::Str) {
+                return Err(Diagnostic::new(
+                    field.span,
+                    format!(
+                        "{} takes numbers, {} is str",
+                        aggregate.function.name(),
+                        qualified(&column)
+                    ),
+                ));
+            }
+        }
+
+        for test in query.filter.iter().flat_map(Filter::tests) {
+            let column = resolve(&test.field)?;
+            let name = qualified(&column);
+            let Some(ty) = self.types.get(test.value) else {
+                continue;
+            };
+            let predicate = test.predicate.symbol();
+
+            let (span, message) = match column.ty {
+                Some(Type::Str) if test.predicate.orders() => (
+                    test.field.span,
+                    format!("{predicate} compares numbers, {name} is str"),
+                ),
+                Some(column_ty) if test.predicate.on_text() && column_ty != Type::Str => (
+                    test.field.span,
+                    format!("{predicate} tests text, {name} is {}", column_ty.name()),
+                ),
+                _ if test.predicate.on_text() && ty != Type::Str => (
+                    ast[test.value].span,
+                    format!("{predicate} tests text, found {}", ty.name()),
+                ),
+                Some(column_ty) if column_ty != ty => (
+                    ast[test.value].span,
+                    format!("{name} is {}, found {}", column_ty.name(), ty.name()),
+                ),
+                _ => continue,
+            };
+            return Err(Diagnostic::new(span, message));
+        }
+        Ok(())
+    }
+}
+
+impl Visitor for TypeChecker {
+    fn visit_stmt(&mut self, ast: &AST, id: StmtId) {
+        walk_stmt(self, ast, id);
+
+        let checked = match &ast[id].kind {
+            StmtKind::Let(decl) => {
+                if let Some(ty) = self.types.get(decl.value) {
+                    self.variables.insert(decl.stack_offset, ty);
+                }
+                Ok(())
+            }
+            StmtKind::Record(decl) => {
+                self.records.declare(decl);
+                Ok(())
+            }
+            StmtKind::Insert(insert) => self.check_rows(ast, insert),
+            StmtKind::Table(table) => {
+                self.records.declare(&table.record);
+                self.check_rows(ast, &table.insert)
+            }
+            StmtKind::Load(load) => csv::load(&load.path, &load.record, ast[id].span)
+                .map(|sheet| self.records.declare(&sheet.record))
+                .map_err(|message| Diagnostic::new(ast[id].span, message)),
+            StmtKind::Query(query) | StmtKind::Save(SaveStmt { query, .. }) => {
+                self.check_query(ast, query)
+            }
+            _ => Ok(()),
+        };
+        if let Err(diagnostic) = checked {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    fn visit_expr(&mut self, ast: &AST, id: ExprId) {
+        walk_expr(self, ast, id);
+
+        let expr = &ast[id];
+        let ty = match &expr.kind {
+            ExprKind::Literal(Literals::NUMBER(_, int_type)) => Type::Int(*int_type),
+            ExprKind::Literal(Literals::FLOAT(_, float_type)) => Type::Float(*float_type),
+            ExprKind::Literal(Literals::STR(_)) => Type::Str,
+            // Variables whose value failed the check have no type
+            ExprKind::Variable(var) => match self.variables.get(&var.stack_offset) {
+                Some(ty) => *ty,
+                None => return,
+            },
+            ExprKind::Binary(op, lhs, rhs) => {
+                let (Some(lhs), Some(rhs)) = (self.types.get(*lhs), self.types.get(*rhs)) else {
+                    return;
+                };
+                let texts = lhs == Type::Str || rhs == Type::Str;
+                if texts || lhs != rhs {
+                    let mut message = format!(
+                        "Cannot apply '{}' to {} and {}",
+                        op.symbol(),
+                        lhs.name(),
+                        rhs.name()
+                    );
+                    if !texts {
+                        message += ", convert one side with 'as'";
+                    }
+                    self.diagnostics.push(Diagnostic::new(expr.span, message));
+                    return;
+                }
+                lhs
+            }
+            ExprKind::Cast(value, ty) => match self.types.get(*value) {
+                None => return,
+                Some(Type::Str) => {
+                    let message = format!("Cannot cast str to {}", ty.name());
+                    self.diagnostics.push(Diagnostic::new(expr.span, message));
+                    return;
+                }
+                Some(_) => *ty,
+            },
+        };
+
+        self.types.set(id, ty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::declarations::{Item, StmtKind, Type};
+    use crate::parser::Parser;
+    use crate::symbols::CodeScope;
+    use crate::tokenizer::{FloatType, IntType, Tokenizer};
+
+    use super::TypeChecker;
+
+    #[test]
+    fn infers_types_through_variables_and_casts() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser.parse(Tokenizer::new(
+            "let sum = 7;\nlet count = 2;\nlet average = sum as f64 / count as f64;\nlet half = 0.5f32 * 3 as f32;\nlet rounded = average as i64;\n",
+        )).unwrap();
+
+        let types = TypeChecker::new().check(&ast).unwrap();
+
+        let value_type = |index: usize| {
+            let Item::Stmt(stmt) = ast[ast.items[index]];
+            let StmtKind::Let(decl) = &ast[stmt].kind else {
+                panic!("Expected let statement");
+            };
+            types[decl.value]
+        };
+        assert_eq!(value_type(0), Type::Int(IntType::I64));
+        assert_eq!(value_type(2), Type::Float(FloatType::F64));
+        assert_eq!(value_type(3), Type::Float(FloatType::F32));
+        assert_eq!(value_type(4), Type::Int(IntType::I64));
+    }
+
+    #[test]
+    fn checks_rows_against_their_record() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser.parse(Tokenizer::new(
+            "Career: Name Wage\nPerson: Name Age: i64 Career\nPersons -> Jacek 24 Driver 2400\n",
+        )).unwrap();
+
+        let types = TypeChecker::new().check(&ast).unwrap();
+        let columns: Vec<_> = types
+            .records()
+            .columns("Person")
+            .into_iter()
+            .map(|column| (column.name, column.ty))
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                (String::from("Name"), Some(Type::Str)),
+                (String::from("Age"), Some(Type::Int(IntType::I64))),
+                (String::from("Career.Name"), Some(Type::Str)),
+                (String::from("Career.Wage"), Some(Type::Int(IntType::I64))),
+            ]
+        );
+
+        let cases = [
+            (
+                "Persons -> Tomek 30 Architect",
+                "Person needs 4 values (Name Age Career.Name Career.Wage), found 3",
+                (11, 18),
+            ),
+            (
+                "Persons -> Jacek 24 Driver 2400, Tomek 30.5 Architect 5000",
+                "Person.Age is i64, found f64",
+                (39, 4),
+            ),
+            (
+                "Persons -> Jacek 24 Driver 2400, Tomek 30 Architect high",
+                "Person.Career.Wage is i64, found str",
+                (52, 4),
+            ),
+            (
+                "Countries:\nName     Code    GDP\nPoland   PLN     1000\nCzech    CZK\n",
+                "Country needs 3 values (Name Code GDP), found 2",
+                (54, 12),
+            ),
+            (
+                "Countries:\nName     Code    GDP\nPoland   PLN     1000\nCzech    CZK     high\n",
+                "Country.GDP is i64, found str",
+                (71, 4),
+            ),
+        ];
+        for (rows, message, span) in cases {
+            let source = format!("Career: Name Wage\nPerson: Name Age: i64 Career\n{rows}\n");
+            let offset = source
+                .find("Persons")
+                .or_else(|| source.find("Countries"))
+                .unwrap();
+            let mut parser = Parser::new(CodeScope::global());
+            let ast = parser.parse(Tokenizer::new(&source)).unwrap();
+
+            let diagnostic = TypeChecker::new().check(&ast).unwrap_err().remove(0);
+            assert_eq!(diagnostic.message, message);
+            assert_eq!(
+                (diagnostic.span.start - offset, diagnostic.span.len),
+                span,
+                "{rows}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_mixing_ints_and_floats() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser
+            .parse(Tokenizer::new("let a = 1;\nlet b = a * 2.5;\n"))
+            .unwrap();
+
+        let diagnostic = TypeChecker::new().check(&ast).unwrap_err().remove(0);
+
+        assert_eq!(
+            diagnostic.message,
+            "Cannot apply '*' to i64 and f64, convert one side with 'as'"
+        );
+        assert_eq!((diagnostic.span.start, diagnostic.span.len), (19, 7));
+    }
+
+    #[test]
+    fn reports_every_failing_statement_once() {
+        let source = "let a = 1 + 2.5;
+let b = a * 2;
+let c = \"x\" as i64;
+let d = 3 * 4;
+";
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser.parse(Tokenizer::new(source)).unwrap();
+
+        let errors = TypeChecker::new().check(&ast).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|error| error.message.as_str()).collect();
+
+        // `b` uses `a`, which has no type, and is not reported again
+        assert_eq!(
+            messages,
+            [
+                "Cannot apply '+' to i64 and f64, convert one side with 'as'",
+                "Cannot cast str to i64",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_integers_of_different_widths_apart() {
+        let mut parser = Parser::new(CodeScope::global());
+        let ast = parser
+            .parse(Tokenizer::new(
+                "let a = 5u8 + 3 as u8;\nlet b = 5i64 + 3u8;\n",
+            ))
+            .unwrap();
+
+        let diagnostic = TypeChecker::new().check(&ast).unwrap_err().remove(0);
+
+        assert_eq!(
+            diagnostic.message,
+            "Cannot apply '+' to i64 and u8, convert one side with 'as'"
+        );
+        assert_eq!((diagnostic.span.start, diagnostic.span.len), (31, 10));
+    }
+
+    #[test]
+    fn resolves_the_fields_of_queries() {
+        let source = "Career: Name Wage\nPerson: Name Age Career\nPersons -> Jacek 24 Driver 2400\nget names from persons sorted by salary\n";
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(source))
+            .unwrap();
+
+        let diagnostic = TypeChecker::new().check(&ast).unwrap_err().remove(0);
+        assert_eq!(diagnostic.message, "Person has no field salary");
+        assert_eq!(diagnostic.span.start, source.find("salary").unwrap());
+    }
+
+    #[test]
+    fn checks_aggregates_and_groups() {
+        let cases = [
+            (
+                "get name and max GDP of countries",
+                "name is neither grouped by nor aggregated",
+                "name and",
+            ),
+            (
+                "get code and count of countries grouped by name",
+                "code is neither grouped by nor aggregated",
+                "code",
+            ),
+            (
+                "get sum names of countries",
+                "sum takes numbers, Country.Name is str",
+                "names of",
+            ),
+            (
+                "get a country with lowest size",
+                "Country has no field size",
+                "size",
+            ),
+        ];
+
+        for (query, message, at) in cases {
+            let source = format!("Countries:\nName  Code  GDP\nPoland  PLN  1000\n\n{query}\n");
+            let ast = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(&source))
+                .unwrap();
+
+            let diagnostic = TypeChecker::new().check(&ast).unwrap_err().remove(0);
+            assert_eq!(diagnostic.message, message);
+            assert_eq!(diagnostic.span.start, source.rfind(at).unwrap(), "{query}");
+        }
+    }
+
+    #[test]
+    fn checks_the_fields_of_joins() {
+        let cases = [
+            (
+                "get names from persons join careers on name = wage",
+                "Person.Name is str, Career.Wage is i64",
+                "join",
+            ),
+            (
+                "get names from persons join careers on name = age",
+                "Join Career on a field of it and one of Person",
+                "join",
+            ),
+            (
+                "get names from careers join persons",
+                "Career has no field named after Person, join on fields with 'on'",
+                "join",
+            ),
+            (
+                "get salaries from persons join careers",
+                "Person and Career have no field salaries",
+                "salaries",
+            ),
+            (
+                "get names from persons join careers on Career.Age = career",
+                "Career has no field Age",
+                "Career.Age",
+            ),
+            (
+                "get names from persons join careers whose wage > 1.5",
+                "Career.Wage is i64, found f64",
+                "1.5",
+            ),
+        ];
+
+        for (query, message, at) in cases {
+            let source = format!("Person: Name Age Career\nCareer: Name Wage\nCareers -> Driver 2400\nPersons -> Jacek 24 Driver\n{query}\n");
+            let ast = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(&source))
+                .unwrap();
+
+            let diagnostic = TypeChecker::new().check(&ast).unwrap_err().remove(0);
+            assert_eq!(diagnostic.message, message);
+            assert_eq!(diagnostic.span.start, source.rfind(at).unwrap(), "{query}");
+        }
+    }
+
+    #[test]
+    fn checks_filters_against_the_columns() {
+        let cases = [
+            (
+                "whose name > 3",
+                "> compares numbers, Country.Name is str",
+                "name",
+            ),
+            (
+                "whose GDP starts with 1",
+                "starts with tests text, Country.GDP is i64",
+                "GDP",
+            ),
+            (
+                "whose name contains 1",
+                "contains tests text, found i64",
+                "1",
+            ),
+            ("whose GDP = 1.5", "Country.GDP is i64, found f64", "1.5"),
+            (
+                "whose code is 12 or name = Czech",
+                "Country.Code is str, found i64",
+                "12",
+            ),
+        ];
+
+        for (filter, message, at) in cases {
+            let source = format!(
+                "Countries:\nName  Code  GDP\nPoland  PLN  1000\n\nget names from countries {filter}\n"
+            );
+            let ast = Parser::new(CodeScope::global())
+                .parse(Tokenizer::new(&source))
+                .unwrap();
+
+            let diagnostic = TypeChecker::new().check(&ast).unwrap_err().remove(0);
+            assert_eq!(diagnostic.message, message);
+            assert_eq!(diagnostic.span.start, source.rfind(at).unwrap(), "{filter}");
+        }
+    }
+
+    #[test]
+    fn declares_the_records_of_loaded_files() {
+        let source = "load \"samples/countries.csv\" as Countries\nget names from countries whose GDP > 1.5\n";
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(source))
+            .unwrap();
+
+        let diagnostic = TypeChecker::new().check(&ast).unwrap_err().remove(0);
+        assert_eq!(diagnostic.message, "Country.GDP is i64, found f64");
+
+        let ast = Parser::new(CodeScope::global())
+            .parse(Tokenizer::new(
+                "load \"samples/missing.csv\" as Countries\n",
+            ))
+            .unwrap();
+        let diagnostic = TypeChecker::new().check(&ast).unwrap_err().remove(0);
+        assert!(diagnostic
+            .message
+            .starts_with("Cannot read samples/missing.csv: "));
+        assert_eq!((diagnostic.span.start, diagnostic.span.len), (0, 39));
+    }
+}
@@ -0,0 +1,188 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+#[rustfmt::skip]
+mod tables;
+
+fn in_ranges(c: char, ranges: &[(u32, u32)]) -> bool {
+    let c = c as u32;
+    ranges
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Characters which may start an identifier, `_` included.
+pub fn is_ident_start(c: char) -> bool {
+    c == '_' || c.is_ascii_alphabetic() || (!c.is_ascii() && in_ranges(c, tables::XID_START))
+}
+
+/// Characters which may follow the first one in an identifier.
+pub fn is_ident_continue(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric() || (!c.is_ascii() && in_ranges(c, tables::XID_CONTINUE))
+}
+
+fn combining_class(c: char) -> u8 {
+    let c = c as u32;
+    tables::COMBINING_CLASSES
+        .binary_search_by(|&(start, end, _)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .map(|index| tables::COMBINING_CLASSES[index].2)
+        .unwrap_or(0)
+}
+
+// Hangul syllables are composed arithmetically instead of through the tables
+const HANGUL_S: u32 = 0xAC00;
+const HANGUL_L: u32 = 0x1100;
+const HANGUL_V: u32 = 0x1161;
+const HANGUL_T: u32 = 0x11A7;
+const HANGUL_V_COUNT: u32 = 21;
+const HANGUL_T_COUNT: u32 = 28;
+const HANGUL_N_COUNT: u32 = HANGUL_V_COUNT * HANGUL_T_COUNT;
+const HANGUL_S_COUNT: u32 = 19 * HANGUL_N_COUNT;
+
+fn char_at(code: u32) -> char {
+    char::from_u32(code).expect("Unicode tables hold valid characters")
+}
+
+fn decompose(c: char, out: &mut Vec<char>) {
+    let code = c as u32;
+
+    if (HANGUL_S..HANGUL_S + HANGUL_S_COUNT).contains(&code) {
+        let index = code - HANGUL_S;
+        out.push(char_at(HANGUL_L + index / HANGUL_N_COUNT));
+        out.push(char_at(HANGUL_V + index % HANGUL_N_COUNT / HANGUL_T_COUNT));
+        if !index.is_multiple_of(HANGUL_T_COUNT) {
+            out.push(char_at(HANGUL_T + index % HANGUL_T_COUNT));
+        }
+        return;
+    }
+
+    match tables::DECOMPOSITIONS.binary_search_by_key(&code, |&(composite, _, _)| composite) {
+        Ok(index) => {
+            let (_, first, second) = tables::DECOMPOSITIONS[index];
+            decompose(char_at(first), out);
+            if second != 0 {
+                decompose(char_at(second), out);
+            }
+        }
+        Err(_) => out.push(c),
+    }
+}
+
+fn compose(first: char, second: char) -> Option<char> {
+    let (first, second) = (first as u32, second as u32);
+
+    if (HANGUL_L..HANGUL_L + 19).contains(&first)
+        && (HANGUL_V..HANGUL_V + HANGUL_V_COUNT).contains(&second)
+    {
+        let index = (first - HANGUL_L) * HANGUL_N_COUNT + (second - HANGUL_V) * HANGUL_T_COUNT;
+        return Some(char_at(HANGUL_S + index));
+    }
+
+    let is_lv = (HANGUL_S..HANGUL_S + HANGUL_S_COUNT).contains(&first)
+        && (first - HANGUL_S).is_multiple_of(HANGUL_T_COUNT);
+    if is_lv && (HANGUL_T + 1..HANGUL_T + HANGUL_T_COUNT).contains(&second) {
+        return Some(char_at(first + second - HANGUL_T));
+    }
+
+    tables::COMPOSITIONS
+        .binary_search_by_key(&(first, second), |&(a, b, _)| (a, b))
+        .ok()
+        .map(|index| char_at(tables::COMPOSITIONS[index].2))
+}
+
+/// Normalization Form C, so that `ż` typed as one character or as `z` followed by a
+/// combining dot names the same symbol.
+pub fn nfc(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() {
+        return Cow::Borrowed(text);
+    }
+
+    let mut chars = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        decompose(c, &mut chars);
+    }
+
+    // Canonical ordering: combining marks sorted by class between starters
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start;
+        while end < chars.len() && combining_class(chars[end]) != 0 {
+            end += 1;
+        }
+        chars[start..end].sort_by_key(|c| combining_class(*c));
+        start = end + 1;
+    }
+
+    let mut composed: Vec<char> = Vec::with_capacity(chars.len());
+    let mut starter: Option<usize> = None;
+    let mut last_class = 0;
+    for c in chars {
+        let class = combining_class(c);
+
+        if let Some(index) = starter {
+            let blocked = composed.len() > index + 1 && (last_class == 0 || last_class >= class);
+            if !blocked {
+                if let Some(combined) = compose(composed[index], c) {
+                    composed[index] = combined;
+                    continue;
+                }
+            }
+        }
+
+        if class == 0 {
+            starter = Some(composed.len());
+        }
+        last_class = class;
+        composed.push(c);
+    }
+
+    Cow::Owned(composed.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_ident_continue, is_ident_start, nfc};
+
+    #[test]
+    fn classifies_identifier_characters() {
+        for c in ['z', '_', 'ż', 'Ł', 'ć', 'λ', 'я', '漢'] {
+            assert!(is_ident_start(c), "{c} should start an identifier");
+        }
+        for c in ['1', '·', '\u{301}'] {
+            assert!(!is_ident_start(c) && is_ident_continue(c), "{c}");
+        }
+        for c in ['-', '€', '→', ' ', '\u{200B}'] {
+            assert!(!is_ident_continue(c), "{c} should not be in an identifier");
+        }
+    }
+
+    #[test]
+    fn composes_canonical_equivalents() {
+        assert_eq!(nfc("zaz\u{307}o\u{301}\u{142}c\u{301}"), "zażółć");
+        assert_eq!(nfc("zażółć"), "zażółć");
+        // Marks are reordered by combining class before composing
+        assert_eq!(nfc("a\u{323}\u{302}"), "\u{1EAD}");
+        assert_eq!(nfc("a\u{302}\u{323}"), "\u{1EAD}");
+        // Hangul is composed arithmetically
+        assert_eq!(nfc("\u{1100}\u{1161}\u{11A8}"), "\u{AC01}");
+        // Composition exclusions stay decomposed
+        assert_eq!(nfc("\u{958}"), "\u{915}\u{93C}");
+        assert_eq!(nfc("\u{212B}"), "\u{C5}");
+    }
+}
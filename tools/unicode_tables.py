@@ -0,0 +1,123 @@
+#!/usr/bin/env python3
+"""Regenerates src/unicode/tables.rs from Python's own Unicode database.
+
+    python3 tools/unicode_tables.py > src/unicode/tables.rs
+"""
+import unicodedata
+
+MAX = 0x110000
+
+
+def ranges(predicate):
+    result = []
+    start = None
+    for cp in range(MAX + 1):
+        inside = cp < MAX and predicate(cp)
+        if inside and start is None:
+            start = cp
+        elif not inside and start is not None:
+            result.append((start, cp - 1))
+            start = None
+    return result
+
+
+def is_surrogate(cp):
+    return 0xD800 <= cp <= 0xDFFF
+
+
+# str.isidentifier() follows XID_Start / XID_Continue, plus `_` which we add by hand
+def xid_start(cp):
+    return not is_surrogate(cp) and cp != 0x5F and chr(cp).isidentifier()
+
+
+def xid_continue(cp):
+    return not is_surrogate(cp) and ("a" + chr(cp)).isidentifier()
+
+
+def canonical_decomposition(cp):
+    if is_surrogate(cp):
+        return None
+    decomposition = unicodedata.decomposition(chr(cp))
+    if not decomposition or decomposition.startswith("<"):
+        return None
+    return [int(part, 16) for part in decomposition.split()]
+
+
+def write_ranges(name, items):
+    print(f"pub const {name}: &[(u32, u32)] = &[")
+    line = "   "
+    for start, end in items:
+        entry = f" (0x{start:X}, 0x{end:X}),"
+        if len(line) + len(entry) > 99:
+            print(line)
+            line = "   "
+        line += entry
+    print(line)
+    print("];")
+    print()
+
+
+def write_entries(name, type_, entries):
+    print(f"pub const {name}: &[{type_}] = &[")
+    line = "   "
+    for entry in entries:
+        entry = " (" + ", ".join(entry) + "),"
+        if len(line) + len(entry) > 99:
+            print(line)
+            line = "   "
+        line += entry
+    print(line)
+    print("];")
+    print()
+
+
+def main():
+    print(f"// Generated by tools/unicode_tables.py from Unicode {unicodedata.unidata_version}, do not edit.")
+    print()
+
+    write_ranges("XID_START", ranges(xid_start))
+    write_ranges("XID_CONTINUE", ranges(xid_continue))
+
+    classes = []
+    for cp in range(MAX):
+        if is_surrogate(cp):
+            continue
+        ccc = unicodedata.combining(chr(cp))
+        if ccc == 0:
+            continue
+        if classes and classes[-1][1] == cp - 1 and classes[-1][2] == ccc:
+            classes[-1][1] = cp
+        else:
+            classes.append([cp, cp, ccc])
+    write_entries(
+        "COMBINING_CLASSES",
+        "(u32, u32, u8)",
+        [(f"0x{s:X}", f"0x{e:X}", str(c)) for s, e, c in classes],
+    )
+
+    decompositions = []
+    compositions = []
+    for cp in range(MAX):
+        parts = canonical_decomposition(cp)
+        if parts is None:
+            continue
+        first, second = parts[0], parts[1] if len(parts) > 1 else 0
+        decompositions.append((cp, first, second))
+        # Primary composites are the ones NFC puts back together
+        if second and unicodedata.normalize("NFC", chr(first) + chr(second)) == chr(cp):
+            compositions.append((first, second, cp))
+
+    write_entries(
+        "DECOMPOSITIONS",
+        "(u32, u32, u32)",
+        [(f"0x{c:X}", f"0x{a:X}", f"0x{b:X}") for c, a, b in decompositions],
+    )
+    compositions.sort()
+    write_entries(
+        "COMPOSITIONS",
+        "(u32, u32, u32)",
+        [(f"0x{a:X}", f"0x{b:X}", f"0x{c:X}") for a, b, c in compositions],
+    )
+
+
+main()